@@ -4,11 +4,13 @@ CREATE TABLE IF NOT EXISTS tabs (
     title TEXT NOT NULL,
     content TEXT DEFAULT '',
     -- This stores which window ID this tab "opened"
-    child_window_id TEXT, 
+    child_window_id TEXT,
     -- This stores the ID of the window this tab LIVES in
-    parent_id TEXT, 
+    parent_id TEXT,
     created_at BIGINT NOT NULL
 );
 
--- Index for faster lookups when traversing hierarchy
-CREATE INDEX IF NOT EXISTS idx_parent_id ON tabs(parent_id);
\ No newline at end of file
+-- Index for faster lookups when traversing hierarchy. Databases created from
+-- the old init.sql carry the same index as idx_parent_id.
+DROP INDEX IF EXISTS idx_parent_id;
+CREATE INDEX IF NOT EXISTS idx_tabs_parent_id ON tabs(parent_id);
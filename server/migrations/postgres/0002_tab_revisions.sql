@@ -0,0 +1,9 @@
+-- Every save of a tab appends a row here so old versions can be restored
+CREATE TABLE IF NOT EXISTS tab_revisions (
+    tab_id TEXT NOT NULL REFERENCES tabs(id) ON DELETE CASCADE,
+    rev INTEGER NOT NULL,
+    title TEXT NOT NULL,
+    content TEXT DEFAULT '',
+    saved_at BIGINT NOT NULL,
+    PRIMARY KEY (tab_id, rev)
+);
@@ -0,0 +1,2 @@
+-- Optimistic concurrency: bumped on every change, checked against If-Match on save
+ALTER TABLE tabs ADD COLUMN IF NOT EXISTS version BIGINT NOT NULL DEFAULT 1;
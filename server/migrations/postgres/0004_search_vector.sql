@@ -0,0 +1,7 @@
+-- Full-text index over the title and the tag-stripped article body
+ALTER TABLE tabs ADD COLUMN IF NOT EXISTS search_vector tsvector GENERATED ALWAYS AS (
+    setweight(to_tsvector('english', title), 'A') ||
+    setweight(to_tsvector('english', regexp_replace(COALESCE(content, ''), '<[^>]*>', ' ', 'g')), 'B')
+) STORED;
+
+CREATE INDEX IF NOT EXISTS idx_tabs_search ON tabs USING GIN (search_vector);
@@ -0,0 +1,11 @@
+-- Outgoing wiki links (<span data-tab-id>) extracted from content on save.
+-- No FK on target_id: links to deleted tabs must stay visible.
+CREATE TABLE IF NOT EXISTS tab_links (
+    source_id TEXT NOT NULL REFERENCES tabs(id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    target_id TEXT NOT NULL,
+    link_text TEXT NOT NULL DEFAULT '',
+    PRIMARY KEY (source_id, position)
+);
+
+CREATE INDEX IF NOT EXISTS idx_tab_links_target_id ON tab_links(target_id);
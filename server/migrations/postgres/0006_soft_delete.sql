@@ -0,0 +1,6 @@
+-- Soft delete: deleted_at is set while the tab is in the trash. deleted_root is
+-- the tab whose deletion took this one down, so restores bring back whole subtrees.
+ALTER TABLE tabs ADD COLUMN IF NOT EXISTS deleted_at BIGINT;
+ALTER TABLE tabs ADD COLUMN IF NOT EXISTS deleted_root TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_tabs_deleted_root ON tabs(deleted_root);
@@ -0,0 +1,2 @@
+-- Index among siblings in the same column
+ALTER TABLE tabs ADD COLUMN IF NOT EXISTS position INTEGER NOT NULL DEFAULT 0;
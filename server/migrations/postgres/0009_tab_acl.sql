@@ -0,0 +1,11 @@
+-- Who may see or change a branch. A grant applies to the tab and everything
+-- under it until a grant for the same user further down overrides it.
+CREATE TABLE IF NOT EXISTS tab_acl (
+    tab_id TEXT NOT NULL REFERENCES tabs(id) ON DELETE CASCADE,
+    user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    role TEXT NOT NULL CHECK (role IN ('owner', 'editor', 'viewer')),
+    granted_at BIGINT NOT NULL,
+    PRIMARY KEY (tab_id, user_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_tab_acl_user_id ON tab_acl(user_id);
@@ -0,0 +1,12 @@
+-- Read-only links to one subtree for people without an account. As with
+-- auth tokens, only a SHA-256 of the link token is kept.
+CREATE TABLE IF NOT EXISTS share_links (
+    id TEXT PRIMARY KEY,
+    tab_id TEXT NOT NULL REFERENCES tabs(id) ON DELETE CASCADE,
+    token_hash TEXT NOT NULL UNIQUE,
+    created_by TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_at BIGINT NOT NULL,
+    expires_at BIGINT
+);
+
+CREATE INDEX IF NOT EXISTS idx_share_links_tab_id ON share_links(tab_id);
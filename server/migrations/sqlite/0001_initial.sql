@@ -0,0 +1,49 @@
+-- Same shape as the Postgres schema, minus what SQLite can't express:
+-- the full-text index is an FTS5 table kept in sync by the application.
+CREATE TABLE IF NOT EXISTS tabs (
+    id TEXT PRIMARY KEY,
+    title TEXT NOT NULL,
+    content TEXT DEFAULT '',
+    -- This stores which window ID this tab "opened"
+    child_window_id TEXT,
+    -- This stores the ID of the window this tab LIVES in
+    parent_id TEXT,
+    created_at INTEGER NOT NULL,
+    -- Bumped on every change, checked against If-Match on save
+    version INTEGER NOT NULL DEFAULT 1,
+    -- Index among siblings in the same column
+    position INTEGER NOT NULL DEFAULT 0,
+    -- Soft delete, see the Postgres migrations for the details
+    deleted_at INTEGER,
+    deleted_root TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_tabs_parent_id ON tabs(parent_id);
+CREATE INDEX IF NOT EXISTS idx_tabs_deleted_root ON tabs(deleted_root);
+
+CREATE TABLE IF NOT EXISTS tab_revisions (
+    tab_id TEXT NOT NULL REFERENCES tabs(id) ON DELETE CASCADE,
+    rev INTEGER NOT NULL,
+    title TEXT NOT NULL,
+    content TEXT DEFAULT '',
+    saved_at INTEGER NOT NULL,
+    PRIMARY KEY (tab_id, rev)
+);
+
+CREATE TABLE IF NOT EXISTS tab_links (
+    source_id TEXT NOT NULL REFERENCES tabs(id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    target_id TEXT NOT NULL,
+    link_text TEXT NOT NULL DEFAULT '',
+    PRIMARY KEY (source_id, position)
+);
+
+CREATE INDEX IF NOT EXISTS idx_tab_links_target_id ON tab_links(target_id);
+
+-- Title and tag-stripped body, written alongside every save
+CREATE VIRTUAL TABLE IF NOT EXISTS tabs_fts USING fts5(
+    id UNINDEXED,
+    title,
+    body,
+    tokenize = 'porter unicode61'
+);
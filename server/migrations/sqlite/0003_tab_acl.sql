@@ -0,0 +1,10 @@
+-- Per-branch access grants, see the Postgres migration for the details.
+CREATE TABLE IF NOT EXISTS tab_acl (
+    tab_id TEXT NOT NULL REFERENCES tabs(id) ON DELETE CASCADE,
+    user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    role TEXT NOT NULL CHECK (role IN ('owner', 'editor', 'viewer')),
+    granted_at INTEGER NOT NULL,
+    PRIMARY KEY (tab_id, user_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_tab_acl_user_id ON tab_acl(user_id);
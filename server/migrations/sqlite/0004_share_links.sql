@@ -0,0 +1,11 @@
+-- Public read-only subtree links, see the Postgres migration for the details.
+CREATE TABLE IF NOT EXISTS share_links (
+    id TEXT PRIMARY KEY,
+    tab_id TEXT NOT NULL REFERENCES tabs(id) ON DELETE CASCADE,
+    token_hash TEXT NOT NULL UNIQUE,
+    created_by TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_at INTEGER NOT NULL,
+    expires_at INTEGER
+);
+
+CREATE INDEX IF NOT EXISTS idx_share_links_tab_id ON share_links(tab_id);
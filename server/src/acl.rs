@@ -0,0 +1,353 @@
+//! Per-branch permissions. A grant gives one user a role on a tab and on
+//! everything below it; a grant for the same user further down the tree
+//! overrides it, so a branch can be narrowed to read-only or widened for
+//! someone who can't see the rest.
+//!
+//! Branches nobody has granted anything on stay open to every logged-in user.
+//! As soon as a tab or one of its ancestors carries a grant, only the users
+//! named on that path get in.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+use crate::auth::{CurrentUser, User};
+use crate::{now_millis, store_error, AppState};
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Viewer,
+    Editor,
+    /// Can also grant and revoke access.
+    Owner,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Editor => "editor",
+            Role::Owner => "owner",
+        }
+    }
+
+    pub fn parse(role: &str) -> Option<Self> {
+        match role {
+            "viewer" => Some(Role::Viewer),
+            "editor" => Some(Role::Editor),
+            "owner" => Some(Role::Owner),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct Grant {
+    /// The tab the grant was made on; it covers that tab's whole subtree.
+    pub tab_id: String,
+    pub user_id: String,
+    pub username: String,
+    pub role: Role,
+    pub granted_at: i64,
+}
+
+pub struct AclNode {
+    pub parent_id: Option<String>,
+    /// False for tabs in the trash.
+    pub live: bool,
+}
+
+/// Every tab's place in the tree and every grant, enough to answer access
+/// questions for a whole request without going back to the store.
+#[derive(Default)]
+pub struct AclIndex {
+    pub nodes: HashMap<String, AclNode>,
+    pub grants: HashMap<String, Vec<Grant>>,
+}
+
+impl AclIndex {
+    /// The tab and its ancestors, nearest first.
+    pub fn lineage<'a>(&'a self, id: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        // The store refuses cycles; the bound only guards against a corrupt row.
+        let mut next = Some(id);
+        std::iter::from_fn(move || {
+            let current = next?;
+            next = self.nodes.get(current).and_then(|n| n.parent_id.as_deref());
+            Some(current)
+        })
+        .take(self.nodes.len() + 1)
+    }
+
+    /// `None` means the caller may not even know the tab exists.
+    pub fn role(&self, id: &str, user_id: &str) -> Option<Role> {
+        let mut restricted = false;
+        for tab_id in self.lineage(id) {
+            if let Some(grants) = self.grants.get(tab_id) {
+                if let Some(grant) = grants.iter().find(|g| g.user_id == user_id) {
+                    return Some(grant.role);
+                }
+                restricted |= !grants.is_empty();
+            }
+        }
+        (!restricted).then_some(Role::Owner)
+    }
+
+    pub fn can(&self, id: &str, user_id: &str, role: Role) -> bool {
+        self.role(id, user_id).is_some_and(|r| r >= role)
+    }
+
+    /// Grants on the tab and every ancestor, nearest first.
+    pub fn inherited_grants(&self, id: &str) -> Vec<Grant> {
+        self.lineage(id)
+            .flat_map(|tab_id| self.grants.get(tab_id).into_iter().flatten().cloned())
+            .collect()
+    }
+
+    /// Live tabs below `id`, not counting `id` itself.
+    pub fn live_descendants(&self, id: &str) -> Vec<String> {
+        let mut children: HashMap<&str, Vec<&str>> = HashMap::new();
+        for (tab_id, node) in &self.nodes {
+            if let (Some(parent_id), true) = (node.parent_id.as_deref(), node.live) {
+                children.entry(parent_id).or_default().push(tab_id);
+            }
+        }
+
+        let mut seen = HashSet::from([id]);
+        let mut found = Vec::new();
+        let mut stack = vec![id];
+        while let Some(current) = stack.pop() {
+            for child in children.get(current).into_iter().flatten() {
+                if seen.insert(child) {
+                    found.push(child.to_string());
+                    stack.push(child);
+                }
+            }
+        }
+        found
+    }
+}
+
+/// Loads the index for one request.
+pub async fn load(state: &AppState) -> Result<AclIndex, (StatusCode, String)> {
+    state.store.acl_index().await.map_err(|e| store_error("Permissions", e))
+}
+
+/// Fails unless `user` holds at least `role` on `id`. Tabs the caller can't
+/// see at all answer 404, so private branches don't give themselves away.
+pub fn require(index: &AclIndex, id: &str, user: &User, role: Role) -> Result<(), (StatusCode, String)> {
+    match index.role(id, &user.id) {
+        Some(held) if held >= role => Ok(()),
+        Some(_) => Err((StatusCode::FORBIDDEN, format!("You need {} access to {}", role.as_str(), id))),
+        None => Err((StatusCode::NOT_FOUND, format!("Tab {} not found", id))),
+    }
+}
+
+/// Creating or moving a tab under `parent_id` needs edit access to the
+/// parent. The root column belongs to everyone.
+pub fn require_parent(index: &AclIndex, parent_id: Option<&str>, user: &User) -> Result<(), (StatusCode, String)> {
+    match parent_id {
+        Some(parent_id) => require(index, parent_id, user, Role::Editor),
+        None => Ok(()),
+    }
+}
+
+#[derive(Serialize)]
+pub struct AclView {
+    /// The caller's own effective role.
+    role: Role,
+    grants: Vec<Grant>,
+}
+
+#[derive(Deserialize)]
+pub struct GrantRequest {
+    role: Role,
+}
+
+pub async fn get_acl(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<String>
+) -> Result<Json<AclView>, (StatusCode, String)> {
+    let index = load(&state).await?;
+    if !index.nodes.contains_key(&id) {
+        return Err((StatusCode::NOT_FOUND, format!("Tab {} not found", id)));
+    }
+    require(&index, &id, &user, Role::Viewer)?;
+
+    let role = index.role(&id, &user.id).unwrap_or(Role::Viewer);
+    Ok(Json(AclView { role, grants: index.inherited_grants(&id) }))
+}
+
+/// Gives `username` a role on the tab's subtree. Granting on an open branch
+/// makes it private, so the caller is made owner of it at the same time.
+pub async fn grant_access(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Path((id, username)): Path<(String, String)>,
+    Json(req): Json<GrantRequest>
+) -> Result<Json<AclView>, (StatusCode, String)> {
+    let index = load(&state).await?;
+    require(&index, &id, &user, Role::Owner)?;
+    if !index.nodes.contains_key(&id) {
+        return Err((StatusCode::NOT_FOUND, format!("Tab {} not found", id)));
+    }
+
+    let (grantee, _) = state.store
+        .find_login(&username)
+        .await
+        .map_err(|e| store_error("Grant", e))?
+        .ok_or((StatusCode::NOT_FOUND, format!("No user named {}", username)))?;
+    if grantee.id == user.id && req.role != Role::Owner {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, "You can't lower your own access; ask another owner".to_string()));
+    }
+
+    let now = now_millis();
+    if index.inherited_grants(&id).is_empty() && grantee.id != user.id {
+        state.store.grant_access(&id, &user.id, Role::Owner, now).await.map_err(|e| store_error("Grant", e))?;
+    }
+    state.store.grant_access(&id, &grantee.id, req.role, now).await.map_err(|e| store_error("Grant", e))?;
+
+    println!("🔐 {} granted {} {} on {}", user.username, grantee.username, req.role.as_str(), id);
+    get_acl(State(state), CurrentUser(user), Path(id)).await
+}
+
+pub async fn revoke_access(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Path((id, username)): Path<(String, String)>
+) -> Result<StatusCode, (StatusCode, String)> {
+    let index = load(&state).await?;
+    require(&index, &id, &user, Role::Owner)?;
+
+    let (grantee, _) = state.store
+        .find_login(&username)
+        .await
+        .map_err(|e| store_error("Revoke", e))?
+        .ok_or((StatusCode::NOT_FOUND, format!("No user named {}", username)))?;
+    if grantee.id == user.id {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, "You can't remove your own access; ask another owner".to_string()));
+    }
+
+    if state.store.revoke_access(&id, &grantee.id).await.map_err(|e| store_error("Revoke", e))? {
+        println!("🔐 {} revoked {}'s access to {}", user.username, grantee.username, id);
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err((StatusCode::NOT_FOUND, format!("{} has no grant on {}", username, id)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{state, stores, tab, user};
+
+    /// `open` stands alone. Under `team` ann owns everything and bob may only
+    /// read, except under `shared`, where bob edits. Cat is named nowhere.
+    fn index() -> AclIndex {
+        let mut index = AclIndex::default();
+        for (id, parent_id, live) in [
+            ("open", None, true),
+            ("team", None, true),
+            ("draft", Some("team"), true),
+            ("shared", Some("team"), true),
+            ("deep", Some("shared"), true),
+            ("binned", Some("shared"), false),
+        ] {
+            index.nodes.insert(id.to_string(), AclNode { parent_id: parent_id.map(str::to_string), live });
+        }
+        for (tab_id, user_id, role) in [
+            ("team", "ann", Role::Owner),
+            ("team", "bob", Role::Viewer),
+            ("draft", "ann", Role::Owner),
+            ("shared", "bob", Role::Editor),
+        ] {
+            let grant = Grant { tab_id: tab_id.to_string(), user_id: user_id.to_string(), username: user_id.to_string(), role, granted_at: 1 };
+            index.grants.entry(tab_id.to_string()).or_default().push(grant);
+        }
+        index
+    }
+
+    #[test]
+    fn the_nearest_grant_for_the_user_decides() {
+        let index = index();
+        assert_eq!(index.role("open", "bob"), Some(Role::Owner));
+        assert_eq!(index.role("team", "bob"), Some(Role::Viewer));
+        assert_eq!(index.role("deep", "bob"), Some(Role::Editor));
+        assert_eq!(index.role("deep", "ann"), Some(Role::Owner));
+        // A grant naming someone else leaves what bob holds above it in force.
+        assert_eq!(index.role("draft", "bob"), Some(Role::Viewer));
+        assert_eq!(index.role("team", "cat"), None);
+        assert_eq!(index.role("deep", "cat"), None);
+
+        assert!(index.can("team", "bob", Role::Viewer) && !index.can("team", "bob", Role::Editor));
+        let held: Vec<String> = index.inherited_grants("deep").into_iter().map(|g| g.tab_id).collect();
+        assert_eq!(held, ["shared", "team", "team"]);
+    }
+
+    #[test]
+    fn hidden_tabs_answer_404_and_visible_ones_403() {
+        let index = index();
+        let bob = User { id: "bob".to_string(), username: "bob".to_string(), created_at: 1 };
+        let cat = User { id: "cat".to_string(), username: "cat".to_string(), created_at: 1 };
+        let status = |result: Result<(), (StatusCode, String)>| result.err().map(|(status, _)| status);
+        assert_eq!(status(require(&index, "draft", &cat, Role::Viewer)), Some(StatusCode::NOT_FOUND));
+        assert_eq!(status(require(&index, "team", &bob, Role::Editor)), Some(StatusCode::FORBIDDEN));
+        assert_eq!(status(require(&index, "shared", &bob, Role::Editor)), None);
+        assert_eq!(status(require_parent(&index, Some("team"), &bob)), Some(StatusCode::FORBIDDEN));
+        assert_eq!(status(require_parent(&index, None, &bob)), None);
+    }
+
+    #[test]
+    fn descendants_skip_the_trash_and_survive_a_cycle() {
+        let mut index = index();
+        let mut below = index.live_descendants("team");
+        below.sort();
+        assert_eq!(below, ["deep", "draft", "shared"]);
+
+        index.nodes.insert("team".to_string(), AclNode { parent_id: Some("deep".to_string()), live: true });
+        assert_eq!(index.lineage("deep").count(), index.nodes.len() + 1);
+        assert_eq!(index.live_descendants("deep").len(), 3);
+    }
+
+    #[test]
+    fn roles_order_by_power() {
+        assert!(Role::Viewer < Role::Editor && Role::Editor < Role::Owner);
+        for role in [Role::Viewer, Role::Editor, Role::Owner] {
+            assert_eq!(Role::parse(role.as_str()), Some(role));
+        }
+        assert_eq!(Role::parse("admin"), None);
+    }
+
+    #[tokio::test]
+    async fn granting_on_an_open_branch_makes_the_granter_its_owner() {
+        for (name, store) in stores().await {
+            let ann = user(&store, "ann").await;
+            let bob = user(&store, "bob").await;
+            user(&store, "cat").await;
+            store.save_tab(&tab("a", None, ""), None).await.unwrap();
+            let state = state(store.clone());
+
+            let req = || Json(GrantRequest { role: Role::Viewer });
+            let Json(view) = grant_access(State(state.clone()), CurrentUser(ann.clone()), Path(("a".to_string(), "bob".to_string())), req()).await.unwrap();
+            let granted: Vec<(&str, Role)> = view.grants.iter().map(|g| (g.username.as_str(), g.role)).collect();
+            assert_eq!(view.role, Role::Owner, "{}", name);
+            assert!(granted.contains(&("ann", Role::Owner)) && granted.contains(&("bob", Role::Viewer)), "{}", name);
+
+            // Bob may read now but not hand access on, and cat can't see it at all.
+            let denied = grant_access(State(state.clone()), CurrentUser(bob.clone()), Path(("a".to_string(), "cat".to_string())), req()).await;
+            assert_eq!(denied.err().map(|(status, _)| status), Some(StatusCode::FORBIDDEN), "{}", name);
+            assert!(!store.acl_index().await.unwrap().can("a", "cat", Role::Viewer), "{}", name);
+
+            let revoked = revoke_access(State(state.clone()), CurrentUser(ann.clone()), Path(("a".to_string(), "bob".to_string()))).await;
+            assert_eq!(revoked, Ok(StatusCode::NO_CONTENT), "{}", name);
+            let own = revoke_access(State(state), CurrentUser(ann), Path(("a".to_string(), "ann".to_string()))).await;
+            assert_eq!(own.err().map(|(status, _)| status), Some(StatusCode::UNPROCESSABLE_ENTITY), "{}", name);
+            assert_eq!(store.acl_index().await.unwrap().role("a", &bob.id), None, "{}", name);
+        }
+    }
+}
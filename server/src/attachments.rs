@@ -0,0 +1,515 @@
+//! Images and other files kept on local disk instead of inline in `content`.
+//!
+//! Blobs are content-addressed: a file's name is the SHA-256 of its bytes, so
+//! uploading the same image twice stores it once and a reference can never
+//! point at the wrong bytes. Layout under `ATTACHMENTS_DIR` (default
+//! `attachments`) is `ab/abcdef…` with the content type in `ab/abcdef….type`.
+//!
+//! Raster images also get resized variants: `/attachments/:hash/thumbnail` for
+//! the column previews and `?w=` for the editor. They are made on first
+//! request (the thumbnail right after upload) and cached beside the original
+//! as `abcdef….thumb` or `abcdef….w512`.
+//!
+//! `GET /attachments/:hash` needs no login, the same way an `<img>` in a shared
+//! subtree has to load for someone without an account. The hash can only be
+//! learned from content the reader could already see.
+
+use axum::{
+    extract::{Multipart, Path, Query, State},
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use base64::Engine;
+use image::codecs::jpeg::JpegEncoder;
+use image::{DynamicImage, ImageFormat};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use crate::auth::{random_hex, CurrentUser};
+use crate::AppState;
+
+const DEFAULT_CONTENT_TYPE: &str = "application/octet-stream";
+const DEFAULT_MAX_UPLOAD_MB: usize = 25;
+const THUMBNAIL_SIZE: u32 = 256;
+const JPEG_QUALITY: u8 = 85;
+
+// `?w=` rounds up to one of these, so a handful of files per image covers
+// every request and nobody can fill the disk by walking through widths.
+const WIDTHS: [u32; 8] = [64, 128, 256, 512, 768, 1024, 1536, 2048];
+
+// Types the `image` build here can decode. Everything else, SVG included, is
+// served as uploaded whatever the size asked for.
+const RESIZABLE: [&str; 4] = ["image/png", "image/jpeg", "image/gif", "image/webp"];
+
+#[derive(Serialize)]
+pub struct Attachment {
+    pub hash: String,
+    /// Absolute URL to put in `content`.
+    pub url: String,
+    pub content_type: String,
+    pub size: usize,
+}
+
+#[derive(Deserialize)]
+pub struct SizeParams {
+    w: Option<u32>,
+}
+
+#[derive(Clone, Copy)]
+pub enum Variant {
+    /// Fits in a [`THUMBNAIL_SIZE`] square.
+    Thumbnail,
+    /// At most this wide; always one of [`WIDTHS`].
+    Width(u32),
+}
+
+impl Variant {
+    pub fn width(requested: u32) -> Self {
+        let snapped = WIDTHS.iter().copied().find(|w| *w >= requested).unwrap_or(WIDTHS[WIDTHS.len() - 1]);
+        Variant::Width(snapped)
+    }
+
+    fn suffix(self) -> String {
+        match self {
+            Variant::Thumbnail => "thumb".to_string(),
+            Variant::Width(w) => format!("w{}", w),
+        }
+    }
+
+    /// `None` when the image is already small enough to serve as is.
+    fn resize(self, image: &DynamicImage) -> Option<DynamicImage> {
+        match self {
+            Variant::Thumbnail if image.width() > THUMBNAIL_SIZE || image.height() > THUMBNAIL_SIZE => {
+                Some(image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE))
+            }
+            Variant::Width(w) if image.width() > w => Some(image.resize(w, u32::MAX, image::imageops::FilterType::Lanczos3)),
+            _ => None,
+        }
+    }
+}
+
+/// A blob ready to send: bytes, content type and the ETag that names them.
+pub struct Blob {
+    pub bytes: Vec<u8>,
+    pub content_type: String,
+    pub etag: String,
+}
+
+#[derive(Clone)]
+pub struct Attachments {
+    dir: PathBuf,
+    /// Where this server is reached from the browser, e.g. `http://localhost:8080`.
+    public_url: String,
+}
+
+impl Attachments {
+    pub fn from_env() -> Self {
+        let dir = std::env::var("ATTACHMENTS_DIR").unwrap_or_else(|_| "attachments".to_string());
+        let public_url = std::env::var("PUBLIC_URL").unwrap_or_else(|_| "http://localhost:8080".to_string());
+        Attachments { dir: PathBuf::from(dir), public_url: public_url.trim_end_matches('/').to_string() }
+    }
+
+    /// A store in a fresh directory under the system's temp dir, so tests
+    /// never write into the working tree. The directory appears on first put.
+    #[cfg(test)]
+    pub fn scratch() -> Self {
+        let dir = std::env::temp_dir().join(format!("miller-attachments-{}", random_hex(8)));
+        Attachments { dir, public_url: "http://test".to_string() }
+    }
+
+    pub fn url(&self, hash: &str) -> String {
+        format!("{}/attachments/{}", self.public_url, hash)
+    }
+
+    fn path(&self, hash: &str) -> PathBuf {
+        self.dir.join(&hash[..2]).join(hash)
+    }
+
+    /// Stores `bytes` unless an identical blob is already there.
+    pub async fn put(&self, bytes: &[u8], content_type: &str) -> std::io::Result<Attachment> {
+        let hash = hex::encode(Sha256::digest(bytes));
+        let path = self.path(&hash);
+
+        if tokio::fs::metadata(&path).await.is_err() {
+            let shard = path.parent().expect("blob paths have a shard directory");
+            tokio::fs::create_dir_all(shard).await?;
+            // Write aside and rename so a reader never sees half a file.
+            let partial = shard.join(format!("{}.{}.partial", hash, random_hex(4)));
+            tokio::fs::write(&partial, bytes).await?;
+            tokio::fs::write(path.with_extension("type"), content_type).await?;
+            tokio::fs::rename(&partial, &path).await?;
+        }
+
+        Ok(Attachment { url: self.url(&hash), hash, content_type: content_type.to_string(), size: bytes.len() })
+    }
+
+    /// The blob and its content type, or `None` for an unknown hash.
+    pub async fn get(&self, hash: &str) -> std::io::Result<Option<(Vec<u8>, String)>> {
+        if !is_hash(hash) {
+            return Ok(None);
+        }
+        let path = self.path(hash);
+        let bytes = match tokio::fs::read(&path).await {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        Ok(Some((bytes, self.content_type(hash).await)))
+    }
+
+    /// The type a blob was uploaded as, without reading the blob.
+    async fn content_type(&self, hash: &str) -> String {
+        tokio::fs::read_to_string(self.path(hash).with_extension("type"))
+            .await
+            .unwrap_or_else(|_| DEFAULT_CONTENT_TYPE.to_string())
+    }
+
+    /// The image scaled down to `variant`, made and cached on first use. Falls
+    /// back to the original for non-images, images already small enough and
+    /// anything that fails to decode.
+    pub async fn variant(&self, hash: &str, variant: Variant) -> std::io::Result<Option<Blob>> {
+        if !is_hash(hash) {
+            return Ok(None);
+        }
+        let original = |bytes, content_type| Some(Blob { bytes, content_type, etag: hash.to_string() });
+        let content_type = self.content_type(hash).await;
+        if !RESIZABLE.contains(&content_type.as_str()) {
+            return Ok(self.get(hash).await?.and_then(|(bytes, content_type)| original(bytes, content_type)));
+        }
+
+        // Photos stay JPEG; anything that may carry transparency becomes PNG.
+        let (format, output_type) = if content_type == "image/jpeg" {
+            (ImageFormat::Jpeg, "image/jpeg")
+        } else {
+            (ImageFormat::Png, "image/png")
+        };
+        let resized = Blob { bytes: Vec::new(), content_type: output_type.to_string(), etag: format!("{}-{}", hash, variant.suffix()) };
+
+        // A cached variant is served without touching the original.
+        let cached = self.path(hash).with_extension(variant.suffix());
+        match tokio::fs::read(&cached).await {
+            Ok(bytes) => return Ok(Some(Blob { bytes, ..resized })),
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        let Some((bytes, content_type)) = self.get(hash).await? else {
+            return Ok(None);
+        };
+        let source = bytes.clone();
+        let encoded = tokio::task::spawn_blocking(move || {
+            let image = image::load_from_memory(&source).ok()?;
+            let scaled = variant.resize(&image)?;
+            encode(&scaled, format)
+        })
+        .await
+        .ok()
+        .flatten();
+
+        let Some(encoded) = encoded else {
+            return Ok(original(bytes, content_type));
+        };
+        let shard = cached.parent().expect("blob paths have a shard directory");
+        let partial = shard.join(format!("{}.{}.partial", hash, random_hex(4)));
+        tokio::fs::write(&partial, &encoded).await?;
+        tokio::fs::rename(&partial, &cached).await?;
+
+        Ok(Some(Blob { bytes: encoded, ..resized }))
+    }
+
+    /// Moves base64 `data:` images in sanitized HTML into the store and points
+    /// their `src` at it. Returns the new HTML and how many images moved.
+    /// Data URIs that don't decode are left alone.
+    pub async fn extract_inline(&self, html: &str) -> std::io::Result<(String, usize)> {
+        const MARKER: &str = "src=\"data:";
+
+        let mut out = String::with_capacity(html.len());
+        let mut rest = html;
+        let mut moved = 0;
+        while let Some(start) = rest.find(MARKER) {
+            let value_start = start + "src=\"".len();
+            let Some(len) = rest[value_start..].find('"') else { break };
+            let uri = &rest[value_start..value_start + len];
+
+            out.push_str(&rest[..value_start]);
+            match decode_data_uri(uri) {
+                Some((content_type, bytes)) => {
+                    out.push_str(&self.put(&bytes, &content_type).await?.url);
+                    moved += 1;
+                }
+                None => out.push_str(uri),
+            }
+            rest = &rest[value_start + len..];
+        }
+        out.push_str(rest);
+        Ok((out, moved))
+    }
+}
+
+/// Largest accepted upload, from `ATTACHMENT_MAX_MB`.
+pub fn max_upload_bytes() -> usize {
+    std::env::var("ATTACHMENT_MAX_MB")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_MAX_UPLOAD_MB)
+        * 1024
+        * 1024
+}
+
+fn encode(image: &DynamicImage, format: ImageFormat) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    if format == ImageFormat::Jpeg {
+        // JPEG has no alpha channel.
+        image.to_rgb8().write_with_encoder(JpegEncoder::new_with_quality(&mut out, JPEG_QUALITY)).ok()?;
+    } else {
+        image.write_to(&mut std::io::Cursor::new(&mut out), format).ok()?;
+    }
+    Some(out)
+}
+
+fn is_hash(hash: &str) -> bool {
+    hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+/// The blob hash in a URL like `…/attachments/<hash>?w=1024`, whichever host
+/// it was saved with.
+pub fn hash_in_url(url: &str) -> Option<&str> {
+    let (_, rest) = url.split_once("/attachments/")?;
+    let hash = rest.get(..64)?;
+    is_hash(hash).then_some(hash)
+}
+
+/// Splits `data:image/png;base64,…` into its type and bytes.
+fn decode_data_uri(uri: &str) -> Option<(String, Vec<u8>)> {
+    let (meta, payload) = uri.strip_prefix("data:")?.split_once(',')?;
+    let content_type = meta.strip_suffix(";base64")?;
+    let content_type = if content_type.is_empty() { DEFAULT_CONTENT_TYPE } else { content_type };
+    let payload: String = payload.chars().filter(|c| !c.is_ascii_whitespace()).collect();
+    let bytes = base64::engine::general_purpose::STANDARD.decode(payload).ok()?;
+    Some((clean_content_type(content_type), bytes))
+}
+
+/// Keeps a declared type only if it looks like `type/subtype`; it is echoed
+/// back in a response header later.
+fn clean_content_type(declared: &str) -> String {
+    let essence = declared.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+    let valid = essence.split_once('/').is_some_and(|(kind, sub)| {
+        let token = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$&-^_.+".contains(&b));
+        token(kind) && token(sub)
+    });
+    if valid { essence } else { DEFAULT_CONTENT_TYPE.to_string() }
+}
+
+fn io_error(context: &str, e: std::io::Error) -> (StatusCode, String) {
+    eprintln!("❌ Attachment Error ({}): {:?}", context, e);
+    (StatusCode::INTERNAL_SERVER_ERROR, format!("Attachment Error: {}", e))
+}
+
+/// Takes the `file` field of a multipart form.
+pub async fn upload(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    mut multipart: Multipart
+) -> Result<(StatusCode, Json<Attachment>), (StatusCode, String)> {
+    while let Some(field) = multipart.next_field().await.map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))? {
+        if field.name() != Some("file") {
+            continue;
+        }
+        let content_type = clean_content_type(field.content_type().unwrap_or(DEFAULT_CONTENT_TYPE));
+        let bytes = field.bytes().await.map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+
+        let attachment = state.attachments.put(&bytes, &content_type).await.map_err(|e| io_error("Upload", e))?;
+        println!("📎 {} uploaded {} ({} bytes)", user.username, attachment.hash, attachment.size);
+
+        // The columns will ask for the preview straight away; have it ready.
+        if RESIZABLE.contains(&content_type.as_str()) {
+            let (attachments, hash) = (state.attachments.clone(), attachment.hash.clone());
+            tokio::spawn(async move {
+                if let Err(e) = attachments.variant(&hash, Variant::Thumbnail).await {
+                    eprintln!("❌ Thumbnail for {} failed: {:?}", hash, e);
+                }
+            });
+        }
+        return Ok((StatusCode::CREATED, Json(attachment)));
+    }
+    Err((StatusCode::BAD_REQUEST, "Expected a multipart field named \"file\"".to_string()))
+}
+
+/// The original, or with `?w=` a copy at most that wide.
+pub async fn download(
+    State(state): State<AppState>,
+    Path(hash): Path<String>,
+    Query(params): Query<SizeParams>
+) -> Result<Response, (StatusCode, String)> {
+    let blob = match params.w {
+        Some(w) => state.attachments.variant(&hash, Variant::width(w)).await,
+        None => state.attachments
+            .get(&hash)
+            .await
+            .map(|found| found.map(|(bytes, content_type)| Blob { bytes, content_type, etag: hash.clone() })),
+    };
+    let blob = blob
+        .map_err(|e| io_error("Download", e))?
+        .ok_or((StatusCode::NOT_FOUND, format!("Attachment {} not found", hash)))?;
+
+    Ok(blob_response(blob))
+}
+
+pub async fn thumbnail(
+    State(state): State<AppState>,
+    Path(hash): Path<String>
+) -> Result<Response, (StatusCode, String)> {
+    let blob = state.attachments
+        .variant(&hash, Variant::Thumbnail)
+        .await
+        .map_err(|e| io_error("Thumbnail", e))?
+        .ok_or((StatusCode::NOT_FOUND, format!("Attachment {} not found", hash)))?;
+
+    Ok(blob_response(blob))
+}
+
+/// Serves blob bytes. The hash names the exact bytes, so browsers may cache
+/// them forever. Uploads are arbitrary files, so they never get to run script
+/// on this origin, and anything that isn't an image downloads instead of
+/// rendering.
+pub fn blob_response(blob: Blob) -> Response {
+    let content_type = HeaderValue::from_str(&blob.content_type).unwrap_or(HeaderValue::from_static(DEFAULT_CONTENT_TYPE));
+    let etag = HeaderValue::from_str(&format!("\"{}\"", blob.etag)).expect("hashes are hex");
+    let disposition = if content_type.as_bytes().starts_with(b"image/") && content_type != "image/svg+xml" {
+        "inline"
+    } else {
+        "attachment"
+    };
+
+    (
+        [
+            (header::CONTENT_TYPE, content_type),
+            (header::ETAG, etag),
+            (header::CACHE_CONTROL, HeaderValue::from_static("public, max-age=31536000, immutable")),
+            (header::CONTENT_DISPOSITION, HeaderValue::from_static(disposition)),
+            (header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff")),
+            (header::CONTENT_SECURITY_POLICY, HeaderValue::from_static("default-src 'none'; style-src 'unsafe-inline'; sandbox")),
+        ],
+        blob.bytes,
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A store in a fresh directory of its own, removed when dropped.
+    struct Scratch(Attachments);
+
+    impl Scratch {
+        fn new() -> Self {
+            Scratch(Attachments::scratch())
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0.dir);
+        }
+    }
+
+    #[tokio::test]
+    async fn identical_bytes_are_stored_once() {
+        let scratch = Scratch::new();
+        let first = scratch.0.put(b"hello", "text/plain").await.unwrap();
+        let second = scratch.0.put(b"hello", "text/plain").await.unwrap();
+        assert_eq!(first.hash, second.hash);
+        assert_eq!(first.url, format!("http://test/attachments/{}", first.hash));
+
+        let shard = std::fs::read_dir(scratch.0.dir.join(&first.hash[..2])).unwrap().count();
+        assert_eq!(shard, 2, "the blob and its type, nothing partial");
+        assert_eq!(scratch.0.get(&first.hash).await.unwrap(), Some((b"hello".to_vec(), "text/plain".to_string())));
+        assert_eq!(scratch.0.get(&"0".repeat(64)).await.unwrap(), None);
+        assert_eq!(scratch.0.get("../../etc/passwd").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn inline_images_move_out_and_broken_ones_stay() {
+        let scratch = Scratch::new();
+        let html = "<p><img src=\"data:image/png;base64,aGk=\"><img src=\"data:image/png;base64,@@\"><img src=\"/x.png\"></p>";
+        let (out, moved) = scratch.0.extract_inline(html).await.unwrap();
+        let hash = hex::encode(Sha256::digest(b"hi"));
+        assert_eq!(moved, 1);
+        assert_eq!(out, format!("<p><img src=\"http://test/attachments/{}\"><img src=\"data:image/png;base64,@@\"><img src=\"/x.png\"></p>", hash));
+        assert_eq!(scratch.0.get(&hash).await.unwrap().map(|(_, t)| t).as_deref(), Some("image/png"));
+    }
+
+    #[test]
+    fn data_uris_and_content_types_are_checked() {
+        assert_eq!(decode_data_uri("data:image/PNG;base64,aG k="), Some(("image/png".to_string(), b"hi".to_vec())));
+        assert_eq!(decode_data_uri("data:;base64,aGk="), Some((DEFAULT_CONTENT_TYPE.to_string(), b"hi".to_vec())));
+        assert_eq!(decode_data_uri("data:image/png,hi"), None);
+        assert_eq!(clean_content_type("text/html; charset=utf-8"), "text/html");
+        assert_eq!(clean_content_type("text/html\r\nX-Evil: 1"), DEFAULT_CONTENT_TYPE);
+        assert_eq!(clean_content_type("nonsense"), DEFAULT_CONTENT_TYPE);
+    }
+
+    #[test]
+    fn hashes_are_found_in_urls_from_any_host() {
+        let hash = "ab".repeat(32);
+        assert_eq!(hash_in_url(&format!("http://old-host/attachments/{}?w=512", hash)), Some(hash.as_str()));
+        assert_eq!(hash_in_url(&format!("/attachments/{}/thumbnail", hash)), Some(hash.as_str()));
+        assert_eq!(hash_in_url(&format!("/attachments/{}", hash.to_uppercase())), None);
+        assert_eq!(hash_in_url("/attachments/abc"), None);
+    }
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        encode(&DynamicImage::new_rgba8(width, height), ImageFormat::Png).unwrap()
+    }
+
+    #[test]
+    fn requested_widths_snap_up_to_a_fixed_few() {
+        let snapped = |w| match Variant::width(w) {
+            Variant::Width(w) => w,
+            Variant::Thumbnail => unreachable!(),
+        };
+        assert_eq!([snapped(0), snapped(64), snapped(65), snapped(1000), snapped(9999)], [64, 64, 128, 1024, 2048]);
+    }
+
+    #[tokio::test]
+    async fn variants_are_scaled_once_and_cached() {
+        let scratch = Scratch::new();
+        let hash = scratch.0.put(&png(600, 300), "image/png").await.unwrap().hash;
+
+        let thumb = scratch.0.variant(&hash, Variant::Thumbnail).await.unwrap().unwrap();
+        let image = image::load_from_memory(&thumb.bytes).unwrap();
+        assert_eq!((image.width(), image.height()), (256, 128));
+        assert_eq!((thumb.content_type.as_str(), thumb.etag), ("image/png", format!("{}-thumb", hash)));
+        assert!(scratch.0.path(&hash).with_extension("thumb").exists());
+
+        let narrow = scratch.0.variant(&hash, Variant::width(500)).await.unwrap().unwrap();
+        assert_eq!(image::load_from_memory(&narrow.bytes).unwrap().width(), 512);
+        let cached = scratch.0.variant(&hash, Variant::width(500)).await.unwrap().unwrap();
+        assert_eq!(cached.bytes, narrow.bytes);
+
+        // Already narrow enough: the original comes back under its own ETag.
+        let wide = scratch.0.variant(&hash, Variant::width(2000)).await.unwrap().unwrap();
+        assert_eq!(wide.etag, hash);
+
+        // Once cached, a variant is served without the original.
+        std::fs::remove_file(scratch.0.path(&hash)).unwrap();
+        assert_eq!(scratch.0.variant(&hash, Variant::Thumbnail).await.unwrap().unwrap().bytes, thumb.bytes);
+    }
+
+    #[tokio::test]
+    async fn what_cannot_be_scaled_is_served_as_uploaded() {
+        let scratch = Scratch::new();
+        let svg = scratch.0.put(b"<svg/>", "image/svg+xml").await.unwrap().hash;
+        let broken = scratch.0.put(b"not a png", "image/png").await.unwrap().hash;
+
+        for hash in [&svg, &broken] {
+            let blob = scratch.0.variant(hash, Variant::Thumbnail).await.unwrap().unwrap();
+            assert_eq!(&blob.etag, hash);
+        }
+        assert!(scratch.0.variant(&"0".repeat(64), Variant::Thumbnail).await.unwrap().is_none());
+    }
+}
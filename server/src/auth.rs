@@ -0,0 +1,493 @@
+//! Accounts, password login and the tokens that prove who is calling.
+//!
+//! Browsers log in with a username and password and get an HttpOnly session
+//! cookie. Scripts and other tools mint long-lived API tokens and send them as
+//! `Authorization: Bearer …`. Either way only a SHA-256 of the token is
+//! stored. [`CurrentUser`] resolves the caller and guards every route except
+//! `/health`, login and the first sign-up.
+
+use argon2::password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Path, State},
+    http::{header, request::Parts, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::OnceLock;
+
+use crate::{now_millis, store_error, AppState};
+
+const SESSION_COOKIE: &str = "session";
+const SESSION_DAYS: i64 = 30;
+const DAY_MILLIS: i64 = 24 * 60 * 60 * 1000;
+const MIN_PASSWORD_LEN: usize = 8;
+
+#[derive(Serialize, Clone, Debug)]
+pub struct User {
+    pub id: String,
+    pub username: String,
+    pub created_at: i64,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TokenKind {
+    Session,
+    Api,
+}
+
+impl TokenKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TokenKind::Session => "session",
+            TokenKind::Api => "api",
+        }
+    }
+}
+
+pub struct NewToken {
+    pub id: String,
+    pub user_id: String,
+    pub token_hash: String,
+    pub kind: TokenKind,
+    pub name: Option<String>,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+}
+
+/// An API token as listed back to its owner; the secret itself is only shown
+/// once, when it is created.
+#[derive(Serialize)]
+pub struct ApiToken {
+    pub id: String,
+    pub name: Option<String>,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+    pub last_used_at: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct Credentials {
+    username: String,
+    password: String,
+}
+
+#[derive(Deserialize)]
+pub struct TokenRequest {
+    name: Option<String>,
+    expires_in_days: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct CreatedToken {
+    id: String,
+    name: Option<String>,
+    token: String,
+    expires_at: Option<i64>,
+}
+
+/// The authenticated caller. Resolved once per request and cached in the
+/// request extensions, so handlers behind the auth layer can take it as an
+/// argument without a second lookup.
+#[derive(Clone)]
+pub struct CurrentUser(pub User);
+
+#[async_trait]
+impl FromRequestParts<AppState> for CurrentUser {
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        if let Some(user) = parts.extensions.get::<CurrentUser>() {
+            return Ok(user.clone());
+        }
+
+        let token = bearer_token(&parts.headers)
+            .or_else(|| session_cookie(&parts.headers))
+            .ok_or_else(unauthorized)?;
+        let user = state.store
+            .token_user(&hash_token(&token), now_millis())
+            .await
+            .map_err(|e| store_error("Authenticate", e))?
+            .ok_or_else(unauthorized)?;
+
+        let user = CurrentUser(user);
+        parts.extensions.insert(user.clone());
+        Ok(user)
+    }
+}
+
+fn unauthorized() -> (StatusCode, String) {
+    (StatusCode::UNAUTHORIZED, "Log in or send a valid API token".to_string())
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.split_once(' ')?;
+    scheme.eq_ignore_ascii_case("bearer").then(|| token.trim().to_string())
+}
+
+fn session_cookie(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .find_map(|pair| {
+            let (name, value) = pair.trim().split_once('=')?;
+            (name == SESSION_COOKIE).then(|| value.to_string())
+        })
+}
+
+pub fn random_hex(bytes: usize) -> String {
+    let mut buf = vec![0u8; bytes];
+    rand::rngs::OsRng.fill_bytes(&mut buf);
+    hex::encode(buf)
+}
+
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+// Argon2 is deliberately slow; keep it off the async workers.
+async fn hash_password(password: String) -> Result<String, (StatusCode, String)> {
+    tokio::task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default().hash_password(password.as_bytes(), &salt).map(|hash| hash.to_string())
+    })
+    .await
+    .ok()
+    .and_then(Result::ok)
+    .ok_or((StatusCode::INTERNAL_SERVER_ERROR, "Could not hash the password".to_string()))
+}
+
+async fn verify_password(password: String, hash: String) -> bool {
+    tokio::task::spawn_blocking(move || {
+        PasswordHash::new(&hash).is_ok_and(|parsed| Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok())
+    })
+    .await
+    .unwrap_or(false)
+}
+
+/// A real hash to check against when the username doesn't exist, so a failed
+/// login takes as long either way and doesn't reveal which usernames exist.
+fn decoy_hash() -> String {
+    static DECOY: OnceLock<String> = OnceLock::new();
+    DECOY
+        .get_or_init(|| {
+            let salt = SaltString::generate(&mut OsRng);
+            Argon2::default().hash_password(b"decoy", &salt).map(|h| h.to_string()).unwrap_or_default()
+        })
+        .clone()
+}
+
+/// Set `ALLOW_SIGNUP=true` to let anyone register. Otherwise only the very
+/// first account can be created without logging in.
+fn signup_open() -> bool {
+    std::env::var("ALLOW_SIGNUP").is_ok_and(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+}
+
+fn session_cookie_header(token: &str, max_age_secs: i64) -> HeaderValue {
+    // Behind HTTPS the cookie should never travel in the clear.
+    let secure = if std::env::var("COOKIE_SECURE").is_ok_and(|v| v == "1" || v.eq_ignore_ascii_case("true")) {
+        "; Secure"
+    } else {
+        ""
+    };
+    HeaderValue::from_str(&format!(
+        "{}={}; HttpOnly; SameSite=Lax; Path=/; Max-Age={}{}",
+        SESSION_COOKIE, token, max_age_secs, secure
+    ))
+    .expect("token is hex")
+}
+
+pub async fn register(
+    State(state): State<AppState>,
+    caller: Option<CurrentUser>,
+    Json(creds): Json<Credentials>
+) -> Result<(StatusCode, Json<User>), (StatusCode, String)> {
+    let username = creds.username.trim().to_string();
+    if username.is_empty() || username.len() > 64 {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, "Username must be 1 to 64 characters".to_string()));
+    }
+    if creds.password.chars().count() < MIN_PASSWORD_LEN {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, format!("Password must be at least {} characters", MIN_PASSWORD_LEN)));
+    }
+
+    // Without sign-up, only the very first account may register itself. The
+    // count turns most latecomers away before hashing; the insert decides.
+    let first_only = caller.is_none() && !signup_open();
+    let closed = || (StatusCode::UNAUTHORIZED, "Sign-up is closed; an existing user has to create your account".to_string());
+    if first_only && state.store.count_users().await.map_err(|e| store_error("Register", e))? > 0 {
+        return Err(closed());
+    }
+
+    let user = User { id: random_hex(16), username, created_at: now_millis() };
+    let password_hash = hash_password(creds.password).await?;
+    if first_only {
+        if !state.store.create_first_user(&user, &password_hash).await.map_err(|e| store_error("Register", e))? {
+            return Err(closed());
+        }
+    } else {
+        state.store.create_user(&user, &password_hash).await.map_err(|e| store_error("Register", e))?;
+    }
+
+    println!("👤 Registered {}", user.username);
+    Ok((StatusCode::CREATED, Json(user)))
+}
+
+pub async fn login(
+    State(state): State<AppState>,
+    Json(creds): Json<Credentials>
+) -> Result<Response, (StatusCode, String)> {
+    let found = state.store.find_login(creds.username.trim()).await.map_err(|e| store_error("Login", e))?;
+    let (user, hash) = match found {
+        Some((user, hash)) => (Some(user), hash),
+        None => (None, decoy_hash()),
+    };
+    let verified = verify_password(creds.password, hash).await;
+    let Some(user) = user.filter(|_| verified) else {
+        return Err((StatusCode::UNAUTHORIZED, "Invalid username or password".to_string()));
+    };
+
+    let token = random_hex(32);
+    let now = now_millis();
+    state.store
+        .create_token(&NewToken {
+            id: random_hex(16),
+            user_id: user.id.clone(),
+            token_hash: hash_token(&token),
+            kind: TokenKind::Session,
+            name: None,
+            created_at: now,
+            expires_at: Some(now + SESSION_DAYS * DAY_MILLIS),
+        })
+        .await
+        .map_err(|e| store_error("Login", e))?;
+
+    println!("🔑 {} logged in", user.username);
+    let cookie = session_cookie_header(&token, SESSION_DAYS * 24 * 60 * 60);
+    Ok(([(header::SET_COOKIE, cookie)], Json(user)).into_response())
+}
+
+/// Ends the browser session the request came with. API tokens are revoked
+/// separately, by id.
+pub async fn logout(
+    State(state): State<AppState>,
+    headers: HeaderMap
+) -> Result<Response, (StatusCode, String)> {
+    if let Some(token) = session_cookie(&headers) {
+        state.store.delete_session(&hash_token(&token)).await.map_err(|e| store_error("Logout", e))?;
+    }
+    Ok((StatusCode::NO_CONTENT, [(header::SET_COOKIE, session_cookie_header("", 0))]).into_response())
+}
+
+pub async fn me(CurrentUser(user): CurrentUser) -> Json<User> {
+    Json(user)
+}
+
+pub async fn create_api_token(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Json(req): Json<TokenRequest>
+) -> Result<(StatusCode, Json<CreatedToken>), (StatusCode, String)> {
+    let now = now_millis();
+    let expires_at = match req.expires_in_days {
+        Some(days) if days <= 0 => {
+            return Err((StatusCode::UNPROCESSABLE_ENTITY, "expires_in_days must be positive".to_string()));
+        }
+        Some(days) => Some(
+            days.checked_mul(DAY_MILLIS)
+                .and_then(|span| now.checked_add(span))
+                .ok_or((StatusCode::UNPROCESSABLE_ENTITY, "expires_in_days is too large".to_string()))?,
+        ),
+        None => None,
+    };
+
+    let token = random_hex(32);
+    let id = random_hex(16);
+    state.store
+        .create_token(&NewToken {
+            id: id.clone(),
+            user_id: user.id,
+            token_hash: hash_token(&token),
+            kind: TokenKind::Api,
+            name: req.name.clone(),
+            created_at: now,
+            expires_at,
+        })
+        .await
+        .map_err(|e| store_error("Create Token", e))?;
+
+    println!("🔑 {} created API token {}", user.username, id);
+    Ok((StatusCode::CREATED, Json(CreatedToken { id, name: req.name, token, expires_at })))
+}
+
+pub async fn list_api_tokens(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser
+) -> Result<Json<Vec<ApiToken>>, (StatusCode, String)> {
+    let tokens = state.store.list_api_tokens(&user.id).await.map_err(|e| store_error("List Tokens", e))?;
+    Ok(Json(tokens))
+}
+
+pub async fn revoke_api_token(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<String>
+) -> Result<StatusCode, (StatusCode, String)> {
+    if state.store.revoke_api_token(&user.id, &id).await.map_err(|e| store_error("Revoke Token", e))? {
+        println!("🔑 {} revoked API token {}", user.username, id);
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err((StatusCode::NOT_FOUND, format!("No API token {}", id)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::StoreError;
+    use crate::testing::{state, stores, user};
+
+    #[tokio::test]
+    async fn token_lifetimes_past_the_end_of_time_are_refused() {
+        for (name, store) in stores().await {
+            let ann = user(&store, "ann").await;
+            let state = state(store);
+
+            let req = TokenRequest { name: None, expires_in_days: Some(i64::MAX / 2) };
+            let result = create_api_token(State(state.clone()), CurrentUser(ann.clone()), Json(req)).await;
+            assert_eq!(result.err().map(|(status, _)| status), Some(StatusCode::UNPROCESSABLE_ENTITY), "{}", name);
+
+            let req = TokenRequest { name: None, expires_in_days: Some(30) };
+            let (status, Json(created)) = create_api_token(State(state), CurrentUser(ann), Json(req)).await.unwrap();
+            assert_eq!(status, StatusCode::CREATED, "{}", name);
+            assert!(created.expires_at.is_some_and(|at| at > now_millis()), "{}", name);
+        }
+    }
+
+    fn headers(pairs: &[(header::HeaderName, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(name.clone(), HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn tokens_come_from_the_bearer_header_or_the_session_cookie() {
+        assert_eq!(bearer_token(&headers(&[(header::AUTHORIZATION, "Bearer abc ")])).as_deref(), Some("abc"));
+        assert_eq!(bearer_token(&headers(&[(header::AUTHORIZATION, "bearer abc")])).as_deref(), Some("abc"));
+        assert_eq!(bearer_token(&headers(&[(header::AUTHORIZATION, "Basic abc")])), None);
+
+        let cookies = headers(&[(header::COOKIE, "theme=dark"), (header::COOKIE, "a=1; session=xyz; b=2")]);
+        assert_eq!(session_cookie(&cookies).as_deref(), Some("xyz"));
+        assert_eq!(session_cookie(&headers(&[(header::COOKIE, "my_session=xyz")])), None);
+    }
+
+    fn new_token(user_id: &str, secret: &str, kind: TokenKind, expires_at: Option<i64>) -> NewToken {
+        NewToken {
+            id: random_hex(16),
+            user_id: user_id.to_string(),
+            token_hash: hash_token(secret),
+            kind,
+            name: None,
+            created_at: 1,
+            expires_at,
+        }
+    }
+
+    #[tokio::test]
+    async fn tokens_stop_working_once_expired_revoked_or_logged_out() {
+        for (name, store) in stores().await {
+            let ann = user(&store, "ann").await;
+            let api = new_token(&ann.id, "api", TokenKind::Api, Some(1_000));
+            store.create_token(&api).await.unwrap();
+            store.create_token(&new_token(&ann.id, "session", TokenKind::Session, None)).await.unwrap();
+
+            let resolve = |secret: &str, now: i64| {
+                let store = store.clone();
+                let hash = hash_token(secret);
+                async move { store.token_user(&hash, now).await.unwrap().map(|u| u.username) }
+            };
+            assert_eq!(resolve("api", 999).await.as_deref(), Some("ann"), "{}", name);
+            assert_eq!(resolve("api", 1_000).await, None, "{}", name);
+            assert_eq!(resolve("nope", 0).await, None, "{}", name);
+
+            // Sessions aren't API tokens: they are neither listed nor revocable by id.
+            let listed: Vec<String> = store.list_api_tokens(&ann.id).await.unwrap().into_iter().map(|t| t.id).collect();
+            assert_eq!(listed, [api.id.as_str()], "{}", name);
+            assert!(!store.revoke_api_token("someone-else", &api.id).await.unwrap(), "{}", name);
+            assert!(store.revoke_api_token(&ann.id, &api.id).await.unwrap(), "{}", name);
+            assert_eq!(resolve("api", 0).await, None, "{}", name);
+
+            store.delete_session(&hash_token("session")).await.unwrap();
+            assert_eq!(resolve("session", 0).await, None, "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn usernames_are_unique() {
+        for (name, store) in stores().await {
+            user(&store, "ann").await;
+            let twin = User { id: random_hex(16), username: "ann".to_string(), created_at: 1 };
+            assert!(matches!(store.create_user(&twin, "").await, Err(StoreError::Conflict(_))), "{}", name);
+            assert_eq!(store.count_users().await.unwrap(), 1, "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn only_the_first_account_can_register_itself() {
+        let store = stores().await.remove(0).1;
+        let state = state(store);
+        let creds = |username: &str, password: &str| Json(Credentials { username: username.to_string(), password: password.to_string() });
+
+        let short = register(State(state.clone()), None, creds("ann", "short")).await;
+        assert_eq!(short.err().map(|(status, _)| status), Some(StatusCode::UNPROCESSABLE_ENTITY));
+        let (_, Json(ann)) = register(State(state.clone()), None, creds(" ann ", "long enough")).await.unwrap();
+        assert_eq!(ann.username, "ann");
+
+        let closed = register(State(state.clone()), None, creds("bob", "long enough")).await;
+        assert_eq!(closed.err().map(|(status, _)| status), Some(StatusCode::UNAUTHORIZED));
+        let (status, _) = register(State(state.clone()), Some(CurrentUser(ann)), creds("bob", "long enough")).await.unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+
+        let wrong = login(State(state.clone()), creds("bob", "not the one")).await;
+        assert_eq!(wrong.err().map(|(status, _)| status), Some(StatusCode::UNAUTHORIZED));
+        let response = login(State(state.clone()), creds("bob", "long enough")).await.unwrap();
+        let session = session_cookie(&headers(&[(header::COOKIE, response.headers()[header::SET_COOKIE].to_str().unwrap())])).unwrap();
+        let resolved = state.store.token_user(&hash_token(&session), now_millis()).await.unwrap();
+        assert_eq!(resolved.map(|u| u.username).as_deref(), Some("bob"));
+    }
+
+    #[tokio::test]
+    async fn only_one_of_racing_first_accounts_gets_in() {
+        for (name, store) in stores().await {
+            let new = |username: &str| User { id: random_hex(16), username: username.to_string(), created_at: 1 };
+            let (ann, bob, cat) = (new("ann"), new("bob"), new("cat"));
+            let results = tokio::join!(
+                store.create_first_user(&ann, ""),
+                store.create_first_user(&bob, ""),
+                store.create_first_user(&cat, ""),
+            );
+            let created = [results.0.unwrap(), results.1.unwrap(), results.2.unwrap()];
+            assert_eq!(created.iter().filter(|&&created| created).count(), 1, "{}", name);
+            assert_eq!(store.count_users().await.unwrap(), 1, "{}", name);
+        }
+
+        // Both requests pass the early count while the other is still hashing.
+        for (name, store) in stores().await {
+            let state = state(store.clone());
+            let creds = |username: &str| Json(Credentials { username: username.to_string(), password: "long enough".to_string() });
+            let (ann, bob) = tokio::join!(register(State(state.clone()), None, creds("ann")), register(State(state.clone()), None, creds("bob")));
+            let status = |result: Result<(StatusCode, Json<User>), (StatusCode, String)>| result.map_or_else(|(status, _)| status, |(status, _)| status);
+            let statuses = [status(ann), status(bob)];
+            assert!(statuses.contains(&StatusCode::CREATED) && statuses.contains(&StatusCode::UNAUTHORIZED), "{}: {:?}", name, statuses);
+            assert_eq!(store.count_users().await.unwrap(), 1, "{}", name);
+        }
+    }
+}
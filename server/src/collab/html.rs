@@ -0,0 +1,464 @@
+//! Conversion between the HTML stored in `tabs.content` and the Y.XmlFragment
+//! layout y-prosemirror uses for the editor: one XmlElement per ProseMirror
+//! node, named after the node type, and one XmlText per run of text whose
+//! formatting attributes are the marks (`{"bold": {}, "link": {"href": …}}`).
+
+use scraper::{ElementRef, Html, Node};
+use std::collections::HashMap;
+use std::sync::Arc;
+use yrs::types::text::YChange;
+use yrs::types::Attrs;
+use yrs::{Any, Number, Out, ReadTxn, Text, TransactionMut, Xml, XmlElementPrelim, XmlFragment, XmlFragmentRef, XmlOut, XmlTextPrelim};
+
+/// Name of the fragment tiptap's Collaboration extension binds to by default.
+pub const FRAGMENT: &str = "default";
+
+enum PmNode {
+    Element { name: &'static str, attrs: Vec<(&'static str, Any)>, children: Vec<PmNode> },
+    /// Consecutive text nodes, each with its marks.
+    Text(Vec<(String, Attrs)>),
+}
+
+impl PmNode {
+    fn element(name: &'static str, children: Vec<PmNode>) -> Self {
+        PmNode::Element { name, attrs: Vec::new(), children }
+    }
+
+    fn paragraph() -> Self {
+        PmNode::element("paragraph", Vec::new())
+    }
+
+    fn is_blank(&self) -> bool {
+        match self {
+            PmNode::Text(chunks) => chunks.iter().all(|(text, _)| text.trim().is_empty()),
+            PmNode::Element { .. } => false,
+        }
+    }
+}
+
+/// Fills an empty fragment with the document described by `html`.
+pub fn seed(txn: &mut TransactionMut, fragment: &XmlFragmentRef, html: &str) {
+    let document = Html::parse_fragment(html);
+    for node in blocks(document.root_element()) {
+        insert(txn, fragment, node);
+    }
+}
+
+fn insert<F: XmlFragment>(txn: &mut TransactionMut, parent: &F, node: PmNode) {
+    match node {
+        PmNode::Element { name, attrs, children } => {
+            let element = parent.push_back(txn, XmlElementPrelim::empty(name));
+            for (key, value) in attrs {
+                element.insert_attribute(txn, key, value);
+            }
+            for child in children {
+                insert(txn, &element, child);
+            }
+        }
+        PmNode::Text(chunks) => {
+            let text = parent.push_back(txn, XmlTextPrelim::new(""));
+            for (chunk, marks) in chunks {
+                let index = text.len(txn);
+                text.insert_with_attributes(txn, index, &chunk, marks);
+            }
+        }
+    }
+}
+
+fn is_block(tag: &str) -> bool {
+    matches!(
+        tag,
+        "p" | "div" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "ul" | "ol" | "blockquote" | "pre" | "hr" | "img" | "table"
+    )
+}
+
+/// Content for a node whose schema expects `block+`. Loose inline content is
+/// gathered into paragraphs.
+fn blocks(parent: ElementRef) -> Vec<PmNode> {
+    let mut out = Vec::new();
+    let mut pending = Vec::new();
+
+    for child in parent.children() {
+        match ElementRef::wrap(child) {
+            Some(element) if is_block(element.value().name()) => {
+                flush_paragraph(&mut pending, &mut out);
+                block(element, &mut out);
+            }
+            _ => inline(child, &Attrs::new(), &mut pending),
+        }
+    }
+    flush_paragraph(&mut pending, &mut out);
+    out
+}
+
+fn flush_paragraph(pending: &mut Vec<PmNode>, out: &mut Vec<PmNode>) {
+    if !pending.iter().all(PmNode::is_blank) {
+        out.push(PmNode::element("paragraph", std::mem::take(pending)));
+    }
+    pending.clear();
+}
+
+/// Like [`blocks`], but never empty: ProseMirror rejects list items, cells
+/// and quotes without a paragraph in them.
+fn non_empty_blocks(parent: ElementRef) -> Vec<PmNode> {
+    let mut children = blocks(parent);
+    if !matches!(children.first(), Some(PmNode::Element { name: "paragraph", .. })) {
+        children.insert(0, PmNode::paragraph());
+    }
+    children
+}
+
+fn block(element: ElementRef, out: &mut Vec<PmNode>) {
+    let el = element.value();
+    match el.name() {
+        "p" => {
+            let children = blocks(element);
+            if children.is_empty() {
+                out.push(PmNode::paragraph());
+            } else {
+                out.extend(children);
+            }
+        }
+        "div" => out.extend(blocks(element)),
+        tag @ ("h1" | "h2" | "h3" | "h4" | "h5" | "h6") => {
+            // The editor only offers three levels.
+            let level = tag[1..].parse::<u8>().unwrap_or(1).min(3);
+            let mut children = Vec::new();
+            for child in element.children() {
+                inline(child, &Attrs::new(), &mut children);
+            }
+            out.push(PmNode::Element { name: "heading", attrs: vec![("level", int(level as i64))], children });
+        }
+        "ul" | "ol" => {
+            let items = element
+                .children()
+                .filter_map(ElementRef::wrap)
+                .filter(|li| li.value().name() == "li")
+                .map(|li| PmNode::element("listItem", non_empty_blocks(li)))
+                .collect();
+            let mut attrs = Vec::new();
+            if el.name() == "ol" {
+                let start = el.attr("start").and_then(|s| s.parse::<i64>().ok()).unwrap_or(1);
+                attrs.push(("start", int(start)));
+            }
+            let name = if el.name() == "ol" { "orderedList" } else { "bulletList" };
+            out.push(PmNode::Element { name, attrs, children: items });
+        }
+        "blockquote" => out.push(PmNode::element("blockquote", non_empty_blocks(element))),
+        "pre" => {
+            let language = element
+                .select(&code_selector())
+                .next()
+                .and_then(|code| code.value().classes().find_map(|c| c.strip_prefix("language-")))
+                .map(|language| Any::String(language.into()));
+            let text: String = element.text().collect();
+            let children = if text.is_empty() { Vec::new() } else { vec![PmNode::Text(vec![(text, Attrs::new())])] };
+            out.push(PmNode::Element {
+                name: "codeBlock",
+                attrs: language.into_iter().map(|l| ("language", l)).collect(),
+                children,
+            });
+        }
+        "hr" => out.push(PmNode::element("horizontalRule", Vec::new())),
+        "img" => {
+            let attrs: Vec<(&'static str, Any)> = ["src", "alt", "title"]
+                .into_iter()
+                .filter_map(|key| el.attr(key).map(|value| (key, Any::String(value.into()))))
+                .collect();
+            if !attrs.is_empty() {
+                out.push(PmNode::Element { name: "image", attrs, children: Vec::new() });
+            }
+        }
+        "table" => {
+            let rows = element
+                .descendants()
+                .filter_map(ElementRef::wrap)
+                .filter(|tr| tr.value().name() == "tr")
+                .map(|tr| PmNode::element("tableRow", tr.children().filter_map(ElementRef::wrap).filter_map(table_cell).collect()))
+                .collect();
+            out.push(PmNode::element("table", rows));
+        }
+        _ => out.extend(blocks(element)),
+    }
+}
+
+fn code_selector() -> scraper::Selector {
+    scraper::Selector::parse("code").expect("valid selector")
+}
+
+fn table_cell(cell: ElementRef) -> Option<PmNode> {
+    let el = cell.value();
+    let name = match el.name() {
+        "th" => "tableHeader",
+        "td" => "tableCell",
+        _ => return None,
+    };
+    let mut attrs = Vec::new();
+    for key in ["colspan", "rowspan"] {
+        if let Some(n) = el.attr(key).and_then(|v| v.parse::<i64>().ok()) {
+            attrs.push((key, int(n)));
+        }
+    }
+    if let Some(widths) = el.attr("colwidth") {
+        let widths: Vec<Any> = widths.split(',').filter_map(|w| w.trim().parse::<i64>().ok()).map(int).collect();
+        attrs.push(("colwidth", Any::Array(widths.into())));
+    }
+    Some(PmNode::Element { name, attrs, children: non_empty_blocks(cell) })
+}
+
+fn inline(node: ego_tree::NodeRef<Node>, marks: &Attrs, out: &mut Vec<PmNode>) {
+    match node.value() {
+        Node::Text(text) => push_text(out, text, marks),
+        Node::Element(el) => {
+            let mark: Option<(&str, Any)> = match el.name() {
+                "br" => {
+                    out.push(PmNode::element("hardBreak", Vec::new()));
+                    return;
+                }
+                "strong" | "b" => Some(("bold", empty_map())),
+                "em" | "i" => Some(("italic", empty_map())),
+                "u" => Some(("underline", empty_map())),
+                "s" | "strike" | "del" => Some(("strike", empty_map())),
+                "code" => Some(("code", empty_map())),
+                "a" => el.attr("href").map(|href| ("link", map([("href", href)]))),
+                "span" => el.attr("data-tab-id").map(|id| ("wikiLink", map([("tabId", id)]))),
+                _ => None,
+            };
+            let mut marks = marks.clone();
+            if let Some((name, attrs)) = mark {
+                marks.insert(name.into(), attrs);
+            }
+            for child in node.children() {
+                inline(child, &marks, out);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn push_text(out: &mut Vec<PmNode>, text: &str, marks: &Attrs) {
+    if text.is_empty() {
+        return;
+    }
+    match out.last_mut() {
+        Some(PmNode::Text(chunks)) => match chunks.last_mut() {
+            Some((last, last_marks)) if last_marks == marks => last.push_str(text),
+            _ => chunks.push((text.to_string(), marks.clone())),
+        },
+        _ => out.push(PmNode::Text(vec![(text.to_string(), marks.clone())])),
+    }
+}
+
+fn int(n: i64) -> Any {
+    Any::Number(Number::Int(n))
+}
+
+fn empty_map() -> Any {
+    Any::Map(Arc::new(HashMap::new()))
+}
+
+fn map<const N: usize>(entries: [(&str, &str); N]) -> Any {
+    Any::Map(Arc::new(entries.into_iter().map(|(k, v)| (k.to_string(), Any::String(v.into()))).collect()))
+}
+
+/// Renders the fragment the way tiptap's `getHTML()` would, so that a session
+/// writes back the same markup the editor saves.
+pub fn render<T: ReadTxn>(txn: &T, fragment: &XmlFragmentRef) -> String {
+    let mut html = String::new();
+    for child in fragment.children(txn) {
+        render_node(txn, child, &mut html);
+    }
+    html
+}
+
+fn render_node<T: ReadTxn>(txn: &T, node: XmlOut, html: &mut String) {
+    match node {
+        XmlOut::Element(element) => {
+            let attr = |key: &str| element.get_attribute(txn, key);
+            let (open, close) = match element.tag().as_ref() {
+                "paragraph" => ("<p>".to_string(), "</p>".to_string()),
+                "heading" => {
+                    let level = attr("level").and_then(|v| number(&v)).unwrap_or(1).clamp(1, 3);
+                    (format!("<h{}>", level), format!("</h{}>", level))
+                }
+                "bulletList" => ("<ul>".to_string(), "</ul>".to_string()),
+                "orderedList" => match attr("start").and_then(|v| number(&v)) {
+                    Some(start) if start != 1 => (format!("<ol start=\"{}\">", start), "</ol>".to_string()),
+                    _ => ("<ol>".to_string(), "</ol>".to_string()),
+                },
+                "listItem" => ("<li>".to_string(), "</li>".to_string()),
+                "blockquote" => ("<blockquote>".to_string(), "</blockquote>".to_string()),
+                "codeBlock" => match attr("language").and_then(|v| string(&v)) {
+                    Some(language) => (format!("<pre><code class=\"language-{}\">", escape(&language)), "</code></pre>".to_string()),
+                    None => ("<pre><code>".to_string(), "</code></pre>".to_string()),
+                },
+                "horizontalRule" => return html.push_str("<hr>"),
+                "hardBreak" => return html.push_str("<br>"),
+                "image" => {
+                    html.push_str("<img");
+                    for key in ["src", "alt", "title"] {
+                        if let Some(value) = attr(key).and_then(|v| string(&v)) {
+                            html.push_str(&format!(" {}=\"{}\"", key, escape(&value)));
+                        }
+                    }
+                    return html.push('>');
+                }
+                "table" => ("<table><tbody>".to_string(), "</tbody></table>".to_string()),
+                "tableRow" => ("<tr>".to_string(), "</tr>".to_string()),
+                tag @ ("tableHeader" | "tableCell") => {
+                    let name = if tag == "tableHeader" { "th" } else { "td" };
+                    let mut open = format!("<{}", name);
+                    for key in ["colspan", "rowspan"] {
+                        let value = attr(key).and_then(|v| number(&v)).unwrap_or(1);
+                        open.push_str(&format!(" {}=\"{}\"", key, value));
+                    }
+                    if let Some(Out::Any(Any::Array(widths))) = attr("colwidth") {
+                        let widths: Vec<String> = widths.iter().filter_map(any_number).map(|w| w.to_string()).collect();
+                        open.push_str(&format!(" colwidth=\"{}\"", widths.join(",")));
+                    }
+                    open.push('>');
+                    (open, format!("</{}>", name))
+                }
+                // Unknown node types keep their content rather than losing it.
+                _ => (String::new(), String::new()),
+            };
+            html.push_str(&open);
+            for child in element.children(txn) {
+                render_node(txn, child, html);
+            }
+            html.push_str(&close);
+        }
+        XmlOut::Text(text) => {
+            for chunk in text.diff(txn, YChange::identity) {
+                let Out::Any(Any::String(s)) = &chunk.insert else { continue };
+                let marks = chunk.attributes.as_deref();
+                render_text(s, marks, html);
+            }
+        }
+        XmlOut::Fragment(fragment) => {
+            for child in fragment.children(txn) {
+                render_node(txn, child, html);
+            }
+        }
+    }
+}
+
+// Outermost first, matching the order tiptap registers the marks in.
+const MARK_ORDER: &[&str] = &["link", "wikiLink", "bold", "italic", "underline", "strike", "code"];
+
+fn render_text(text: &str, marks: Option<&Attrs>, html: &mut String) {
+    let active: Vec<(&str, &Any)> = MARK_ORDER
+        .iter()
+        .filter_map(|name| marks.and_then(|m| m.get(*name)).map(|attrs| (*name, attrs)))
+        .collect();
+
+    for (name, attrs) in &active {
+        let get = |key: &str| match attrs {
+            Any::Map(map) => map.get(key).and_then(any_string),
+            _ => None,
+        };
+        match *name {
+            "link" => match get("href") {
+                Some(href) => html.push_str(&format!("<a class=\"wiki-link\" href=\"{}\">", escape(&href))),
+                None => html.push_str("<a class=\"wiki-link\">"),
+            },
+            "wikiLink" => html.push_str(&format!(
+                "<span data-tab-id=\"{}\" class=\"wiki-link\">",
+                escape(&get("tabId").unwrap_or_default())
+            )),
+            "bold" => html.push_str("<strong>"),
+            "italic" => html.push_str("<em>"),
+            "underline" => html.push_str("<u>"),
+            "strike" => html.push_str("<s>"),
+            _ => html.push_str("<code>"),
+        }
+    }
+    html.push_str(&escape(text));
+    for (name, _) in active.iter().rev() {
+        html.push_str(match *name {
+            "link" => "</a>",
+            "wikiLink" => "</span>",
+            "bold" => "</strong>",
+            "italic" => "</em>",
+            "underline" => "</u>",
+            "strike" => "</s>",
+            _ => "</code>",
+        });
+    }
+}
+
+fn number(value: &Out) -> Option<i64> {
+    match value {
+        Out::Any(any) => any_number(any),
+        _ => None,
+    }
+}
+
+fn any_number(value: &Any) -> Option<i64> {
+    match value {
+        Any::Number(n) => n.as_i64(),
+        Any::String(s) => s.parse().ok(),
+        _ => None,
+    }
+}
+
+fn string(value: &Out) -> Option<String> {
+    match value {
+        Out::Any(any) => any_string(any),
+        _ => None,
+    }
+}
+
+fn any_string(value: &Any) -> Option<String> {
+    match value {
+        Any::String(s) => Some(s.to_string()),
+        _ => None,
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yrs::{Doc, Transact};
+
+    fn round_trip(html: &str) -> String {
+        let doc = Doc::new();
+        let fragment = doc.get_or_insert_xml_fragment(FRAGMENT);
+        let mut txn = doc.transact_mut();
+        seed(&mut txn, &fragment, html);
+        render(&txn, &fragment)
+    }
+
+    #[test]
+    fn editor_markup_survives_the_round_trip() {
+        for html in [
+            "<p>a<br>b</p><ul><li><p>one</p></li><li><p>two</p></li></ul><ol start=\"3\"><li><p>three</p></li></ol>",
+            "<pre><code class=\"language-rust\">fn main() {\n}</code></pre><blockquote><p>q</p></blockquote><hr>",
+            "<p><span data-tab-id=\"t1\" class=\"wiki-link\">Other</span> &lt;tag&gt; &amp;</p>",
+            "<h2>Title</h2><p><u>u</u> <s>s</s> <code>c</code></p>",
+            "",
+        ] {
+            assert_eq!(round_trip(html), html);
+        }
+    }
+
+    #[test]
+    fn output_is_normalized_the_way_tiptap_renders_it() {
+        // Marks split per text run, and links carry the class the editor gives them.
+        assert_eq!(
+            round_trip("<p><strong>bold <em>both</em></strong> <a href=\"https://x.org\">link</a></p>"),
+            "<p><strong>bold </strong><strong><em>both</em></strong> <a class=\"wiki-link\" href=\"https://x.org\">link</a></p>",
+        );
+        // Images are blocks, and loose text gets a paragraph.
+        assert_eq!(round_trip("<p><img src=\"/attachments/a\" alt=\"x\"></p>"), "<img src=\"/attachments/a\" alt=\"x\">");
+        assert_eq!(round_trip("loose text<p>then more</p>"), "<p>loose text</p><p>then more</p>");
+        assert_eq!(
+            round_trip("<table><tbody><tr><td colwidth=\"50\"><p>c</p></td></tr></tbody></table>"),
+            "<table><tbody><tr><td colspan=\"1\" rowspan=\"1\" colwidth=\"50\"><p>c</p></td></tr></tbody></table>",
+        );
+    }
+}
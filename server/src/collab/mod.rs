@@ -0,0 +1,308 @@
+//! Live co-editing of a single tab over WebSocket, speaking the y-websocket
+//! flavour of the Yjs sync protocol so tiptap's Collaboration extension can
+//! connect with a stock `WebsocketProvider` pointed at `/collab` (the provider
+//! appends the tab id as the room name).
+//!
+//! Each open tab gets a room holding the merged Y document, seeded from the
+//! stored HTML when the first editor joins. Edits are written back into
+//! `tabs.content` once the room has been quiet for a moment, and again when
+//! the last editor leaves. While a room is open it owns the tab's content, so
+//! clients in a session should stop posting `content` through `POST /tabs`.
+
+use axum::{
+    extract::{
+        ws::{Message as WsMessage, WebSocket, WebSocketUpgrade},
+        Path, State,
+    },
+    http::StatusCode,
+    response::Response,
+};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{broadcast, Notify};
+use yrs::encoding::read::Cursor;
+use yrs::sync::{Awareness, Error, Message, MessageReader, SyncMessage};
+use yrs::updates::decoder::{Decode, DecoderV1};
+use yrs::updates::encoder::Encode;
+use yrs::{ClientID, Doc, ReadTxn, Transact, Update, XmlFragmentRef};
+
+use crate::acl::{self, Role};
+use crate::auth::CurrentUser;
+use crate::events::ChangeEvent;
+use crate::sanitize::sanitize;
+use crate::store::SaveOutcome;
+use crate::{store_error, AppState, Tab};
+
+mod html;
+
+// How long a room must go without edits before it is written back.
+const DEBOUNCE: Duration = Duration::from_secs(2);
+
+// Messages buffered per connection; one that falls further behind is dropped
+// and resyncs when the provider reconnects.
+const BUFFER: usize = 512;
+
+static NEXT_CONNECTION: AtomicU64 = AtomicU64::new(1);
+
+struct Room {
+    tab_id: String,
+    awareness: Mutex<Awareness>,
+    fragment: XmlFragmentRef,
+    /// Encoded messages to relay, tagged with the connection they came from.
+    relay: broadcast::Sender<(u64, Arc<Vec<u8>>)>,
+    dirty: Notify,
+    closed: Notify,
+    /// HTML last written back (or seeded). Also serializes flushes.
+    persisted: tokio::sync::Mutex<String>,
+}
+
+impl Room {
+    fn seeded(tab: &Tab) -> Self {
+        let doc = Doc::new();
+        let fragment = doc.get_or_insert_xml_fragment(html::FRAGMENT);
+        let persisted = {
+            let mut txn = doc.transact_mut();
+            html::seed(&mut txn, &fragment, &tab.content);
+            html::render(&txn, &fragment)
+        };
+        let (relay, _) = broadcast::channel(BUFFER);
+        Room {
+            tab_id: tab.id.clone(),
+            awareness: Mutex::new(Awareness::new(doc)),
+            fragment,
+            relay,
+            dirty: Notify::new(),
+            closed: Notify::new(),
+            persisted: tokio::sync::Mutex::new(persisted),
+        }
+    }
+
+    fn awareness(&self) -> std::sync::MutexGuard<'_, Awareness> {
+        self.awareness.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// What the server says first: its state vector, so the client sends
+    /// back anything the room is missing, and who else is here.
+    fn greeting(&self) -> Result<Vec<u8>, Error> {
+        let awareness = self.awareness();
+        let state_vector = awareness.doc().transact().state_vector();
+        let mut greeting = Message::Sync(SyncMessage::SyncStep1(state_vector)).encode_v1();
+        greeting.extend(Message::Awareness(awareness.update()?).encode_v1());
+        Ok(greeting)
+    }
+
+    /// Applies one WebSocket frame from `connection` and returns the replies
+    /// meant for that connection alone. Document and awareness changes are
+    /// relayed to everyone else in the room.
+    fn handle(&self, connection: u64, data: &[u8], clients: &mut HashSet<ClientID>) -> Result<Vec<Vec<u8>>, Error> {
+        let mut awareness = self.awareness();
+        let mut replies = Vec::new();
+        let mut decoder = DecoderV1::new(Cursor::new(data));
+
+        for message in MessageReader::new(&mut decoder) {
+            match message? {
+                Message::Sync(SyncMessage::SyncStep1(state_vector)) => {
+                    let update = awareness.doc().transact().encode_state_as_update_v1(&state_vector);
+                    replies.push(Message::Sync(SyncMessage::SyncStep2(update)).encode_v1());
+                }
+                Message::Sync(SyncMessage::SyncStep2(update)) | Message::Sync(SyncMessage::Update(update)) => {
+                    awareness.doc().transact_mut().apply_update(Update::decode_v1(&update)?)?;
+                    let _ = self.relay.send((connection, Arc::new(Message::Sync(SyncMessage::Update(update)).encode_v1())));
+                    self.dirty.notify_one();
+                }
+                Message::Awareness(update) => {
+                    clients.extend(update.clients.keys().copied());
+                    let _ = self.relay.send((connection, Arc::new(Message::Awareness(update.clone()).encode_v1())));
+                    awareness.apply_update(update)?;
+                }
+                Message::AwarenessQuery => {
+                    replies.push(Message::Awareness(awareness.update()?).encode_v1());
+                }
+                // Access is checked before the upgrade; nothing else is spoken here.
+                Message::Auth(_) | Message::Custom(..) => {}
+            }
+        }
+        Ok(replies)
+    }
+
+    /// Clears the cursors and selections a departing connection announced.
+    fn forget(&self, clients: &HashSet<ClientID>) {
+        if clients.is_empty() {
+            return;
+        }
+        let mut awareness = self.awareness();
+        for client in clients {
+            awareness.remove_state(*client);
+        }
+        if let Ok(update) = awareness.update_with_clients(clients.iter().copied()) {
+            let _ = self.relay.send((0, Arc::new(Message::Awareness(update).encode_v1())));
+        }
+    }
+
+    /// Writes the merged document into `tabs.content` if it changed since the
+    /// last write. Goes through the same sanitizing and versioning as a save.
+    async fn flush(&self, state: &AppState) {
+        let mut persisted = self.persisted.lock().await;
+        let html = {
+            let awareness = self.awareness();
+            let txn = awareness.doc().transact();
+            html::render(&txn, &self.fragment)
+        };
+        if html == *persisted {
+            return;
+        }
+
+        let mut tab = match state.store.get_tab(&self.tab_id).await {
+            Ok(Some(tab)) => tab,
+            // Deleted mid-session; there is nothing left to write into.
+            Ok(None) => return,
+            Err(e) => return eprintln!("❌ Collab flush of {} failed: {:?}", self.tab_id, e),
+        };
+        tab.content = sanitize(&html).html;
+        match state.attachments.extract_inline(&tab.content).await {
+            Ok((content, _)) => tab.content = content,
+            Err(e) => return eprintln!("❌ Collab flush of {} failed: {:?}", self.tab_id, e),
+        }
+
+        match state.store.save_tab(&tab, None).await {
+            Ok(SaveOutcome::Saved { version, change }) => {
+                println!("🤝 Saved collaborative edits to {} (version {})", self.tab_id, version);
+                if change.is_some() {
+                    state.events.publish(ChangeEvent::Updated { id: self.tab_id.clone(), version }).await;
+                }
+                *persisted = html;
+            }
+            Ok(SaveOutcome::Conflict(_)) => {}
+            Err(e) => eprintln!("❌ Collab flush of {} failed: {:?}", self.tab_id, e),
+        }
+    }
+}
+
+/// Writes the room back whenever edits pause for [`DEBOUNCE`], until it closes.
+async fn persist_when_quiet(state: AppState, room: Arc<Room>) {
+    loop {
+        tokio::select! {
+            _ = room.dirty.notified() => {}
+            _ = room.closed.notified() => return,
+        }
+        while tokio::time::timeout(DEBOUNCE, room.dirty.notified()).await.is_ok() {}
+        room.flush(&state).await;
+    }
+}
+
+/// An open room and how many connections it has.
+type OpenRoom = (Arc<Room>, usize);
+
+/// Open rooms by tab id.
+#[derive(Clone, Default)]
+pub struct Rooms(Arc<tokio::sync::Mutex<HashMap<String, OpenRoom>>>);
+
+impl Rooms {
+    async fn join(&self, state: &AppState, tab: &Tab) -> Arc<Room> {
+        let mut rooms = self.0.lock().await;
+        let (room, connections) = rooms.entry(tab.id.clone()).or_insert_with(|| {
+            let room = Arc::new(Room::seeded(tab));
+            tokio::spawn(persist_when_quiet(state.clone(), room.clone()));
+            (room, 0)
+        });
+        *connections += 1;
+        room.clone()
+    }
+
+    async fn leave(&self, state: &AppState, room: &Arc<Room>) {
+        let last = {
+            let mut rooms = self.0.lock().await;
+            match rooms.get_mut(&room.tab_id) {
+                Some((_, connections)) => {
+                    *connections -= 1;
+                    *connections == 0
+                }
+                None => false,
+            }
+        };
+        if !last {
+            return;
+        }
+
+        room.flush(state).await;
+
+        // Somebody may have joined while the flush was running.
+        let mut rooms = self.0.lock().await;
+        if rooms.get(&room.tab_id).is_some_and(|(open, connections)| *connections == 0 && Arc::ptr_eq(open, room)) {
+            rooms.remove(&room.tab_id);
+            room.closed.notify_one();
+            println!("🤝 Closed collaboration room for {}", room.tab_id);
+        }
+    }
+}
+
+pub async fn collaborate(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<String>,
+    ws: WebSocketUpgrade,
+) -> Result<Response, (StatusCode, String)> {
+    // Every peer in a room can write, so joining takes edit access.
+    acl::require(&acl::load(&state).await?, &id, &user, Role::Editor)?;
+    let tab = state.store
+        .get_tab(&id)
+        .await
+        .map_err(|e| store_error("Collaborate", e))?
+        .ok_or((StatusCode::NOT_FOUND, format!("Tab {} not found", id)))?;
+
+    Ok(ws.on_upgrade(move |socket| async move {
+        let room = state.collab.join(&state, &tab).await;
+        session(&room, socket).await;
+        state.collab.leave(&state, &room).await;
+    }))
+}
+
+async fn session(room: &Room, mut socket: WebSocket) {
+    let connection = NEXT_CONNECTION.fetch_add(1, Ordering::Relaxed);
+    let mut relayed = room.relay.subscribe();
+    let mut clients = HashSet::new();
+
+    match room.greeting() {
+        Ok(greeting) => {
+            if socket.send(WsMessage::Binary(greeting)).await.is_err() {
+                return;
+            }
+        }
+        Err(e) => return eprintln!("❌ Collab greeting for {} failed: {}", room.tab_id, e),
+    }
+
+    'session: loop {
+        tokio::select! {
+            incoming = socket.recv() => match incoming {
+                Some(Ok(WsMessage::Binary(data))) => match room.handle(connection, &data, &mut clients) {
+                    Ok(replies) => {
+                        for reply in replies {
+                            if socket.send(WsMessage::Binary(reply)).await.is_err() {
+                                break 'session;
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("❌ Bad collab message for {}: {}", room.tab_id, e);
+                        break;
+                    }
+                },
+                Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+            outgoing = relayed.recv() => match outgoing {
+                Ok((from, _)) if from == connection => {}
+                Ok((_, data)) => {
+                    if socket.send(WsMessage::Binary(data.to_vec())).await.is_err() {
+                        break;
+                    }
+                }
+                Err(_) => break,
+            },
+        }
+    }
+
+    room.forget(&clients);
+}
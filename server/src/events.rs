@@ -0,0 +1,282 @@
+//! Live change feed. Mutating handlers publish a [`ChangeEvent`] and every
+//! browser subscribed to `/events` receives it as server-sent events.
+//!
+//! With Postgres the events travel through `NOTIFY`, so a change made on one
+//! server instance reaches clients connected to any other; each instance
+//! forwards what it hears on its `LISTEN` connection to its own subscribers.
+//! The single-process backends just use an in-process channel.
+//!
+//! Each subscriber only hears about tabs they can view, checked against the
+//! grants as they stand when the event goes out. The grants are read once per
+//! event and shared by every subscriber on the instance.
+
+use axum::{
+    extract::State,
+    response::sse::{Event, KeepAlive, Sse},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::{PgListener, PgPool, PgPoolOptions};
+use std::convert::Infallible;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, Mutex};
+use tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt};
+
+use crate::acl::{AclIndex, Role};
+use crate::auth::CurrentUser;
+use crate::store::{StoreResult, TabStore};
+use crate::AppState;
+
+const CHANNEL: &str = "tab_events";
+
+// Events buffered per subscriber before a slow one starts missing them.
+const BUFFER: usize = 256;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChangeEvent {
+    Created { id: String, parent_id: Option<String>, version: i64 },
+    Updated { id: String, version: i64 },
+    Moved { id: String, parent_id: Option<String>, version: i64 },
+    /// The sibling order in a window changed.
+    Reordered { parent_id: Option<String> },
+    /// The tab and `count` rows of its subtree went to the trash.
+    Deleted { id: String, count: u64 },
+    Restored { id: String, count: u64 },
+    Purged { count: u64 },
+    /// Events may have been missed; the client should reload from `/tabs`.
+    Resync,
+}
+
+impl ChangeEvent {
+    /// What of the event `user_id` may hear: all of it, nothing, or just
+    /// `Resync` for a move out of their sight, since the tab may have been
+    /// on their screen before.
+    pub fn for_user(self, index: &AclIndex, user_id: &str) -> Option<ChangeEvent> {
+        let can_view = |id: &str| index.can(id, user_id, Role::Viewer);
+        match &self {
+            ChangeEvent::Created { id, .. }
+            | ChangeEvent::Updated { id, .. }
+            | ChangeEvent::Deleted { id, .. }
+            | ChangeEvent::Restored { id, .. } => can_view(id).then_some(self),
+            ChangeEvent::Moved { id, .. } if can_view(id) => Some(self),
+            ChangeEvent::Moved { .. } => Some(ChangeEvent::Resync),
+            ChangeEvent::Reordered { parent_id } => parent_id.as_deref().is_none_or(can_view).then_some(self),
+            ChangeEvent::Purged { .. } | ChangeEvent::Resync => Some(self),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Events {
+    local: Relay,
+    // Set when events go through Postgres rather than straight to `local`.
+    notify: Option<PgPool>,
+    index: Arc<Mutex<Option<SharedIndex>>>,
+}
+
+/// The grants last read for filtering, and the number of the latest event
+/// they are current for.
+type SharedIndex = (u64, Arc<AclIndex>);
+
+/// Hands events to this instance's subscribers, numbered in the order they
+/// arrive so subscribers can tell whether the shared grants are fresh enough.
+#[derive(Clone)]
+struct Relay {
+    sender: broadcast::Sender<(u64, ChangeEvent)>,
+    sent: Arc<AtomicU64>,
+}
+
+impl Relay {
+    fn send(&self, event: ChangeEvent) {
+        let number = self.sent.fetch_add(1, Ordering::SeqCst) + 1;
+        let _ = self.sender.send((number, event));
+    }
+}
+
+impl Events {
+    pub fn local() -> Self {
+        let (sender, _) = broadcast::channel(BUFFER);
+        let local = Relay { sender, sent: Arc::new(AtomicU64::new(0)) };
+        Events { local, notify: None, index: Arc::default() }
+    }
+
+    /// Connects the `NOTIFY` side and starts the task relaying `LISTEN` to
+    /// local subscribers.
+    pub async fn postgres(database_url: &str) -> Result<Self, sqlx::Error> {
+        let notify = PgPoolOptions::new().max_connections(2).connect(database_url).await?;
+        let mut listener = PgListener::connect(database_url).await?;
+        listener.listen(CHANNEL).await?;
+
+        let events = Events { notify: Some(notify), ..Events::local() };
+        let local = events.local.clone();
+        tokio::spawn(async move {
+            loop {
+                match listener.try_recv().await {
+                    Ok(Some(notification)) => match serde_json::from_str::<ChangeEvent>(notification.payload()) {
+                        Ok(event) => local.send(event),
+                        Err(e) => eprintln!("❌ Unreadable change event: {}", e),
+                    },
+                    // The connection dropped and anything sent meanwhile is lost;
+                    // the next call reconnects.
+                    Ok(None) => local.send(ChangeEvent::Resync),
+                    Err(e) => {
+                        eprintln!("❌ Change feed listener error: {}", e);
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                    }
+                }
+            }
+        });
+
+        Ok(events)
+    }
+
+    /// Fire and forget: a lost event only costs subscribers a refresh, so
+    /// failures are logged rather than failing the mutation that caused them.
+    pub async fn publish(&self, event: ChangeEvent) {
+        let Some(pool) = &self.notify else {
+            self.local.send(event);
+            return;
+        };
+        let payload = match serde_json::to_string(&event) {
+            Ok(payload) => payload,
+            Err(e) => return eprintln!("❌ Could not encode change event: {}", e),
+        };
+        if let Err(e) = sqlx::query("SELECT pg_notify($1, $2)").bind(CHANNEL).bind(payload).execute(pool).await {
+            eprintln!("❌ Could not publish change event: {}", e);
+        }
+    }
+
+    /// Events as they arrive, each with its number.
+    pub fn subscribe(&self) -> broadcast::Receiver<(u64, ChangeEvent)> {
+        self.local.sender.subscribe()
+    }
+
+    /// Grants read no earlier than event `number` arrived. Whoever asks first
+    /// reads them; the other subscribers wait for that and share it.
+    async fn index_for(&self, number: u64, store: &dyn TabStore) -> StoreResult<Arc<AclIndex>> {
+        let mut cached = self.index.lock().await;
+        if let Some((read_at, index)) = cached.as_ref() {
+            if *read_at >= number {
+                return Ok(index.clone());
+            }
+        }
+        // Everything sent so far is already in the store, so this read is
+        // good for all of it.
+        let read_at = self.local.sent.load(Ordering::SeqCst);
+        let index = Arc::new(store.acl_index().await?);
+        *cached = Some((read_at, index.clone()));
+        Ok(index)
+    }
+}
+
+/// Same scheme dispatch as `store::connect`.
+pub async fn connect(database_url: &str) -> Result<Events, String> {
+    if crate::store::is_postgres_url(database_url) {
+        Events::postgres(database_url).await.map_err(|e| e.to_string())
+    } else {
+        Ok(Events::local())
+    }
+}
+
+pub async fn stream_events(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let (store, events) = (state.store.clone(), state.events.clone());
+    let stream = BroadcastStream::new(state.events.subscribe())
+        .then(move |received| {
+            let (store, events, user_id) = (store.clone(), events.clone(), user.id.clone());
+            async move {
+                // A subscriber that fell behind the buffer gets told to reload.
+                let (number, event) = received.unwrap_or((0, ChangeEvent::Resync));
+                if matches!(event, ChangeEvent::Resync | ChangeEvent::Purged { .. }) {
+                    return Some(event);
+                }
+                match events.index_for(number, store.as_ref()).await {
+                    Ok(index) => event.for_user(&index, &user_id),
+                    // Nothing may go out unchecked; a reload goes through the
+                    // filtered `/tabs`.
+                    Err(_) => Some(ChangeEvent::Resync),
+                }
+            }
+        })
+        .filter_map(|event| event.map(|event| Ok(Event::default().json_data(&event).unwrap_or_default())));
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acl::{AclNode, Grant};
+
+    /// `open` is visible to everyone; `secret` and its child only to ann.
+    fn index() -> AclIndex {
+        let mut index = AclIndex::default();
+        for (id, parent_id) in [("open", None), ("secret", None), ("inner", Some("secret"))] {
+            index.nodes.insert(id.to_string(), AclNode { parent_id: parent_id.map(str::to_string), live: true });
+        }
+        let grant = Grant { tab_id: "secret".to_string(), user_id: "ann".to_string(), username: "ann".to_string(), role: Role::Owner, granted_at: 1 };
+        index.grants.insert("secret".to_string(), vec![grant]);
+        index
+    }
+
+    fn updated(id: &str) -> ChangeEvent {
+        ChangeEvent::Updated { id: id.to_string(), version: 2 }
+    }
+
+    #[test]
+    fn events_on_restricted_tabs_only_reach_those_who_can_view_them() {
+        let index = index();
+        assert!(updated("inner").for_user(&index, "ann").is_some());
+        assert!(updated("inner").for_user(&index, "bob").is_none());
+        assert!(updated("open").for_user(&index, "bob").is_some());
+
+        let reordered = ChangeEvent::Reordered { parent_id: Some("secret".to_string()) };
+        assert!(reordered.for_user(&index, "bob").is_none());
+        assert!(ChangeEvent::Reordered { parent_id: None }.for_user(&index, "bob").is_some());
+    }
+
+    #[test]
+    fn moves_out_of_sight_become_a_resync() {
+        let moved = ChangeEvent::Moved { id: "inner".to_string(), parent_id: Some("secret".to_string()), version: 3 };
+        assert!(matches!(moved.clone().for_user(&index(), "bob"), Some(ChangeEvent::Resync)));
+        assert!(matches!(moved.for_user(&index(), "ann"), Some(ChangeEvent::Moved { .. })));
+    }
+
+    #[test]
+    fn events_travel_as_tagged_json() {
+        let moved = ChangeEvent::Moved { id: "a".to_string(), parent_id: None, version: 4 };
+        let json = serde_json::to_string(&moved).unwrap();
+        assert_eq!(json, r#"{"type":"moved","id":"a","parent_id":null,"version":4}"#);
+        assert!(matches!(serde_json::from_str(&json), Ok(ChangeEvent::Moved { version: 4, .. })));
+        assert_eq!(serde_json::to_string(&ChangeEvent::Resync).unwrap(), r#"{"type":"resync"}"#);
+    }
+
+    #[tokio::test]
+    async fn local_events_reach_every_subscriber() {
+        let events = connect("sqlite::memory:").await.unwrap();
+        let (mut first, mut second) = (events.subscribe(), events.subscribe());
+        events.publish(ChangeEvent::Purged { count: 2 }).await;
+        assert!(matches!(first.recv().await, Ok((1, ChangeEvent::Purged { count: 2 }))));
+        assert!(matches!(second.recv().await, Ok((1, ChangeEvent::Purged { count: 2 }))));
+    }
+
+    #[tokio::test]
+    async fn grants_are_read_once_per_event_and_shared() {
+        let store = crate::store::connect("memory://").await.unwrap();
+        let events = Events::local();
+        events.publish(updated("open")).await;
+
+        let first = events.index_for(1, store.as_ref()).await.unwrap();
+        let again = events.index_for(1, store.as_ref()).await.unwrap();
+        assert!(Arc::ptr_eq(&first, &again));
+
+        events.publish(updated("open")).await;
+        let after = events.index_for(2, store.as_ref()).await.unwrap();
+        assert!(!Arc::ptr_eq(&first, &after));
+        // An event that went out before the last read needs no new one.
+        assert!(Arc::ptr_eq(&after, &events.index_for(1, store.as_ref()).await.unwrap()));
+    }
+}
@@ -0,0 +1,260 @@
+//! `GET /export/epub`: the subtree as an EPUB 3 book. Every tab is a chapter
+//! in outline order, the table of contents nests like the tree (with an NCX
+//! copy for older readers), wiki links jump between chapters and images are
+//! packed into the book.
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::Response,
+};
+
+use super::html::{escape, rewrite, Syntax};
+use super::{assets, download, outline, zip, Asset, ExportParams, Layout, Outline, ASSET_DIR};
+use crate::auth::CurrentUser;
+use crate::{now_millis, AppState};
+
+// Image types every EPUB 3 reader must support. Other attachments stay links
+// to the server.
+const CORE_IMAGE_TYPES: [&str; 5] = ["image/png", "image/jpeg", "image/gif", "image/webp", "image/svg+xml"];
+
+const STYLESHEET: &str = "\
+body { font-family: serif; line-height: 1.5; }
+h1 { page-break-before: always; }
+img { max-width: 100%; }
+table { border-collapse: collapse; }
+td, th { border: 1px solid #888; padding: 2px 6px; }
+pre { white-space: pre-wrap; font-size: 0.85em; }
+blockquote { margin-left: 1em; padding-left: 0.8em; border-left: 2px solid #888; }
+.missing-image { font-style: italic; }
+";
+
+const CONTAINER: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>
+"#;
+
+pub async fn export_epub(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Query(params): Query<ExportParams>
+) -> Result<Response, (StatusCode, String)> {
+    let outline = outline(&state, &user, params.root.as_deref()).await?;
+    let mut assets = assets(&state.attachments, &outline).await?;
+    assets.retain(|a| CORE_IMAGE_TYPES.contains(&a.content_type.as_str()));
+
+    // Paths inside OEBPS/, where the package document lives.
+    let files = (1..=outline.entries.len()).map(|n| format!("text/ch{:04}.xhtml", n)).collect();
+    let layout = Layout::new(&outline, files, &assets);
+    let identifier = format!("urn:miller:{}", params.root.as_deref().unwrap_or("encyclopedia"));
+
+    let mut out = vec![
+        // Must come first and uncompressed so readers can sniff the type.
+        ("mimetype".to_string(), b"application/epub+zip".to_vec()),
+        ("META-INF/container.xml".to_string(), CONTAINER.as_bytes().to_vec()),
+        ("OEBPS/content.opf".to_string(), package(&outline, &layout, &assets, &identifier, &user.username).into_bytes()),
+        ("OEBPS/nav.xhtml".to_string(), nav(&outline, &layout).into_bytes()),
+        ("OEBPS/toc.ncx".to_string(), ncx(&outline, &layout, &identifier).into_bytes()),
+        ("OEBPS/style.css".to_string(), STYLESHEET.as_bytes().to_vec()),
+    ];
+    for (entry, file) in outline.entries.iter().zip(&layout.files) {
+        let links = layout.links(file);
+        let body = format!(
+            "<section epub:type=\"chapter\">\n<h1>{}</h1>\n{}\n</section>",
+            escape(&entry.tab.title),
+            rewrite(&entry.tab.content, &links, Syntax::Xhtml)
+        );
+        let stylesheet = links.file("style.css");
+        out.push((format!("OEBPS/{}", file), xhtml(&entry.tab.title, &stylesheet, &body).into_bytes()));
+    }
+    out.extend(assets.iter().map(|a| (format!("OEBPS/{}/{}", ASSET_DIR, a.name), a.bytes.clone())));
+    let bytes = zip(&out, &format!("OEBPS/{}", ASSET_DIR))?;
+
+    println!("📚 {} exported {} tabs as EPUB ({} bytes)", user.username, outline.entries.len(), bytes.len());
+    Ok(download(bytes, "application/epub+zip", &outline.title, "epub"))
+}
+
+fn xhtml(title: &str, stylesheet: &str, body: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!DOCTYPE html>\n\
+         <html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\" xml:lang=\"en\" lang=\"en\">\n\
+         <head>\n<meta charset=\"utf-8\"/>\n<title>{}</title>\n<link rel=\"stylesheet\" type=\"text/css\" href=\"{}\"/>\n</head>\n\
+         <body>\n{}\n</body>\n</html>\n",
+        escape(title),
+        stylesheet,
+        body
+    )
+}
+
+fn package(outline: &Outline, layout: &Layout, assets: &[Asset], identifier: &str, creator: &str) -> String {
+    let mut manifest = String::from(
+        "    <item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" properties=\"nav\"/>\n\
+         \x20   <item id=\"ncx\" href=\"toc.ncx\" media-type=\"application/x-dtbncx+xml\"/>\n\
+         \x20   <item id=\"css\" href=\"style.css\" media-type=\"text/css\"/>\n",
+    );
+    let mut spine = String::from("    <itemref idref=\"nav\"/>\n");
+    for (index, file) in layout.files.iter().enumerate() {
+        manifest.push_str(&format!("    <item id=\"ch{}\" href=\"{}\" media-type=\"application/xhtml+xml\"/>\n", index + 1, file));
+        spine.push_str(&format!("    <itemref idref=\"ch{}\"/>\n", index + 1));
+    }
+    for asset in assets {
+        manifest.push_str(&format!(
+            "    <item id=\"img-{}\" href=\"attachments/{}\" media-type=\"{}\"/>\n",
+            &asset.hash[..16],
+            asset.name,
+            asset.content_type
+        ));
+    }
+
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <package xmlns=\"http://www.idpf.org/2007/opf\" version=\"3.0\" unique-identifier=\"book-id\" xml:lang=\"en\">\n\
+         \x20 <metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n\
+         \x20   <dc:identifier id=\"book-id\">{}</dc:identifier>\n\
+         \x20   <dc:title>{}</dc:title>\n\
+         \x20   <dc:creator>{}</dc:creator>\n\
+         \x20   <dc:language>en</dc:language>\n\
+         \x20   <meta property=\"dcterms:modified\">{}</meta>\n\
+         \x20 </metadata>\n\
+         \x20 <manifest>\n{}  </manifest>\n\
+         \x20 <spine toc=\"ncx\">\n{}  </spine>\n\
+         </package>\n",
+        escape(identifier),
+        escape(&outline.title),
+        escape(creator),
+        utc_timestamp(now_millis()),
+        manifest,
+        spine
+    )
+}
+
+/// The EPUB 3 table of contents, nested like the tree.
+fn nav(outline: &Outline, layout: &Layout) -> String {
+    fn items(outline: &Outline, layout: &Layout, indexes: &[usize], out: &mut String) {
+        out.push_str("<ol>\n");
+        for &index in indexes {
+            let entry = &outline.entries[index];
+            out.push_str(&format!("<li><a href=\"{}\">{}</a>", layout.files[index], escape(&entry.tab.title)));
+            if !entry.children.is_empty() {
+                out.push('\n');
+                items(outline, layout, &entry.children, out);
+            }
+            out.push_str("</li>\n");
+        }
+        out.push_str("</ol>\n");
+    }
+
+    let mut body = String::from("<nav epub:type=\"toc\" id=\"toc\">\n<h1>Contents</h1>\n");
+    items(outline, layout, &outline.roots, &mut body);
+    body.push_str("</nav>");
+    xhtml(&outline.title, "style.css", &body)
+}
+
+/// The same table of contents in EPUB 2's format.
+fn ncx(outline: &Outline, layout: &Layout, identifier: &str) -> String {
+    fn points(outline: &Outline, layout: &Layout, indexes: &[usize], depth: usize, out: &mut String) {
+        let indent = "  ".repeat(depth + 2);
+        for &index in indexes {
+            let entry = &outline.entries[index];
+            // Pre-order position, which is also reading order.
+            let order = index + 1;
+            out.push_str(&format!(
+                "{}<navPoint id=\"np{}\" playOrder=\"{}\"><navLabel><text>{}</text></navLabel><content src=\"{}\"/>\n",
+                indent,
+                order,
+                order,
+                escape(&entry.tab.title),
+                layout.files[index]
+            ));
+            points(outline, layout, &entry.children, depth + 1, out);
+            out.push_str(&format!("{}</navPoint>\n", indent));
+        }
+    }
+
+    let depth = outline.entries.iter().map(|e| e.depth + 1).max().unwrap_or(1);
+    let mut map = String::new();
+    points(outline, layout, &outline.roots, 0, &mut map);
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <ncx xmlns=\"http://www.daisy.org/z3986/2005/ncx/\" version=\"2005-1\">\n\
+         \x20 <head>\n\
+         \x20   <meta name=\"dtb:uid\" content=\"{}\"/>\n\
+         \x20   <meta name=\"dtb:depth\" content=\"{}\"/>\n\
+         \x20 </head>\n\
+         \x20 <docTitle><text>{}</text></docTitle>\n\
+         \x20 <navMap>\n{}  </navMap>\n\
+         </ncx>\n",
+        escape(identifier),
+        depth,
+        escape(&outline.title),
+        map
+    )
+}
+
+/// `2024-05-01T12:00:00Z`, as `dcterms:modified` requires.
+fn utc_timestamp(millis: i64) -> String {
+    let secs = millis.div_euclid(1000);
+    let (days, rem) = (secs.div_euclid(86_400), secs.rem_euclid(86_400));
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, rem / 3600, rem % 3600 / 60, rem % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{state, stores, tab, user};
+    use std::io::{Cursor, Read};
+    use zip::{CompressionMethod, ZipArchive};
+
+    #[test]
+    fn timestamps_are_utc_calendar_dates() {
+        assert_eq!(utc_timestamp(0), "1970-01-01T00:00:00Z");
+        assert_eq!(utc_timestamp(951_782_400_000), "2000-02-29T00:00:00Z");
+        assert_eq!(utc_timestamp(1_714_564_800_999), "2024-05-01T12:00:00Z");
+        assert_eq!(utc_timestamp(4_107_542_399_000), "2100-02-28T23:59:59Z");
+        assert_eq!(utc_timestamp(-1), "1969-12-31T23:59:59Z");
+    }
+
+    #[tokio::test]
+    async fn books_open_with_an_uncompressed_mimetype_and_a_nested_toc() {
+        let store = stores().await.remove(0).1;
+        let ann = user(&store, "ann").await;
+        for (id, parent) in [("a", None), ("b", Some("a")), ("c", None)] {
+            store.save_tab(&tab(id, parent, "<p>x<br>y</p>"), None).await.unwrap();
+        }
+
+        let response = export_epub(State(state(store)), CurrentUser(ann), Query(ExportParams { root: None })).await.unwrap();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let mut book = ZipArchive::new(Cursor::new(bytes.to_vec())).unwrap();
+        let read = |book: &mut ZipArchive<Cursor<Vec<u8>>>, name: &str| {
+            let mut text = String::new();
+            book.by_name(name).unwrap().read_to_string(&mut text).unwrap();
+            text
+        };
+
+        let mimetype = book.by_index(0).unwrap();
+        assert_eq!((mimetype.name(), mimetype.compression()), ("mimetype", CompressionMethod::Stored));
+        drop(mimetype);
+
+        let nav = read(&mut book, "OEBPS/nav.xhtml");
+        assert!(nav.contains("<li><a href=\"text/ch0001.xhtml\">a</a>\n<ol>\n<li><a href=\"text/ch0002.xhtml\">b</a></li>\n</ol>\n</li>"), "{}", nav);
+        let ncx = read(&mut book, "OEBPS/toc.ncx");
+        assert!(ncx.contains("<meta name=\"dtb:depth\" content=\"2\"/>") && ncx.contains("playOrder=\"3\""), "{}", ncx);
+        let chapter = read(&mut book, "OEBPS/text/ch0002.xhtml");
+        assert!(chapter.contains("<p>x<br/>y</p>") && chapter.contains("href=\"../style.css\""), "{}", chapter);
+    }
+}
@@ -0,0 +1,127 @@
+//! Re-serializes stored tab HTML for a standalone export: wiki links become
+//! real `<a>` links between the exported pages and attachment URLs point at
+//! the bundled copies. Content was sanitized on save, so every element and
+//! attribute is passed through as is, apart from what XHTML can't hold.
+
+use scraper::{Html, Node};
+
+use super::Links;
+
+const VOID: &[&str] = &["br", "hr", "img", "col"];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Syntax {
+    Html,
+    /// Well-formed XML for EPUB: void elements self-close, the editor's
+    /// `colwidth` goes, and images that weren't bundled become their alt
+    /// text, since an e-book may not load them from the network.
+    Xhtml,
+}
+
+pub fn rewrite(content: &str, links: &Links, syntax: Syntax) -> String {
+    let document = Html::parse_fragment(content);
+    let mut out = String::with_capacity(content.len());
+    for child in document.root_element().children() {
+        write_node(child, links, syntax, &mut out);
+    }
+    out
+}
+
+fn write_node(node: ego_tree::NodeRef<Node>, links: &Links, syntax: Syntax, out: &mut String) {
+    let el = match node.value() {
+        Node::Text(text) => {
+            out.push_str(&escape(text));
+            return;
+        }
+        Node::Element(el) => el,
+        _ => return,
+    };
+
+    let name = el.name();
+    if syntax == Syntax::Xhtml && name == "img" && links.bundled(el.attr("src").unwrap_or_default()).is_none() {
+        out.push_str(&format!("<span class=\"missing-image\">{}</span>", escape(el.attr("alt").unwrap_or_default())));
+        return;
+    }
+
+    let mut tag = name;
+    let mut attrs: Vec<(&str, String)> = Vec::new();
+    if let Some(id) = el.attr("data-tab-id") {
+        // A link to a tab left out of the export stays as plain styled text.
+        match links.tab(id.trim()) {
+            Some(href) => {
+                tag = "a";
+                attrs.push(("href", href));
+            }
+            None => attrs.push(("title", "Not part of this export".to_string())),
+        }
+        attrs.push(("class", "wiki-link".to_string()));
+    } else {
+        for (key, value) in el.attrs() {
+            if syntax == Syntax::Xhtml && key == "colwidth" {
+                continue;
+            }
+            let value = match (name, key) {
+                ("img", "src") | ("a", "href") => links.url(value),
+                _ => value.to_string(),
+            };
+            attrs.push((key, value));
+        }
+    }
+
+    out.push('<');
+    out.push_str(tag);
+    for (key, value) in &attrs {
+        out.push_str(&format!(" {}=\"{}\"", key, escape(value)));
+    }
+    if VOID.contains(&name) {
+        out.push_str(if syntax == Syntax::Xhtml { "/>" } else { ">" });
+        return;
+    }
+    out.push('>');
+    for child in node.children() {
+        write_node(child, links, syntax, out);
+    }
+    out.push_str(&format!("</{}>", tag));
+}
+
+pub fn escape(text: &str) -> String {
+    // Control characters other than whitespace aren't allowed in XML at all.
+    let text: String = text.chars().filter(|c| !c.is_control() || matches!(c, '\t' | '\n' | '\r')).collect();
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::{Asset, Layout, Outline};
+    use crate::testing::tab;
+
+    fn rewritten(content: &str, syntax: Syntax) -> String {
+        let outline = Outline::build(vec![tab("a", None, ""), tab("b", Some("a"), "")], None, |_| true).unwrap();
+        let hash = "c".repeat(64);
+        let assets = [Asset { hash: hash.clone(), name: "c.png".to_string(), content_type: "image/png".to_string(), bytes: Vec::new() }];
+        let layout = Layout::new(&outline, vec!["a.html".to_string(), "a/b.html".to_string()], &assets);
+        rewrite(&content.replace("HASH", &hash), &layout.links("a/b.html"), syntax)
+    }
+
+    #[test]
+    fn wiki_links_become_anchors_to_the_exported_pages() {
+        assert_eq!(
+            rewritten("<p><span data-tab-id=\"a\" class=\"wiki-link\">Up</span> <span data-tab-id=\"z\">Out</span></p>", Syntax::Html),
+            "<p><a href=\"../a.html\" class=\"wiki-link\">Up</a> <span title=\"Not part of this export\" class=\"wiki-link\">Out</span></p>",
+        );
+    }
+
+    #[test]
+    fn xhtml_closes_voids_and_drops_what_cannot_travel() {
+        let content = "<p>1 &lt; 2<br><img src=\"/attachments/HASH\"><img src=\"https://x.org/y.png\" alt=\"Y\"></p><table><tr><td colwidth=\"5\">c</td></tr></table>";
+        let html = rewritten(content, Syntax::Html);
+        assert!(html.starts_with("<p>1 &lt; 2<br><img src=\"../attachments/c.png\"><img "), "{}", html);
+        assert!(html.contains("src=\"https://x.org/y.png\"") && html.contains("<td colwidth=\"5\">"), "{}", html);
+        assert_eq!(
+            rewritten(content, Syntax::Xhtml),
+            "<p>1 &lt; 2<br/><img src=\"../attachments/c.png\"/><span class=\"missing-image\">Y</span></p><table><tbody><tr><td>c</td></tr></tbody></table>",
+        );
+        assert_eq!(escape("a\u{0}\"b\"\n"), "a&quot;b&quot;\n");
+    }
+}
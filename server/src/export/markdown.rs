@@ -0,0 +1,395 @@
+//! `GET /export/markdown`: the subtree as a zip of Markdown files, one per
+//! tab, laid out the way Obsidian and most static site tools expect. A tab is
+//! `Title.md` and its children live in a `Title/` folder next to it; wiki
+//! links become relative links between those files and attachments are
+//! bundled under `attachments/`.
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::Response,
+};
+use scraper::{ElementRef, Html, Node};
+
+use super::{assets, download, outline, zip, ExportParams, Layout, Links, Outline, ASSET_DIR};
+use crate::auth::CurrentUser;
+use crate::AppState;
+
+pub async fn export_markdown(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Query(params): Query<ExportParams>
+) -> Result<Response, (StatusCode, String)> {
+    let outline = outline(&state, &user, params.root.as_deref()).await?;
+    let assets = assets(&state.attachments, &outline).await?;
+    let layout = Layout::new(&outline, files(&outline), &assets);
+
+    let mut out = Vec::new();
+    for (entry, file) in outline.entries.iter().zip(&layout.files) {
+        out.push((file.clone(), to_markdown(&entry.tab.content, &layout.links(file)).into_bytes()));
+    }
+    out.extend(assets.iter().map(|a| (format!("{}/{}", ASSET_DIR, a.name), a.bytes.clone())));
+    let bytes = zip(&out, ASSET_DIR)?;
+
+    println!("📦 {} exported {} tabs as Markdown ({} bytes)", user.username, outline.entries.len(), bytes.len());
+    Ok(download(bytes, "application/zip", &outline.title, "zip"))
+}
+
+/// `Title.md` for each tab, kept out of the attachments folder.
+fn files(outline: &Outline) -> Vec<String> {
+    outline.file_stems(&[ASSET_DIR]).into_iter().map(|stem| format!("{}.md", stem)).collect()
+}
+
+/// Converts tiptap HTML to CommonMark with GitHub tables and strikethrough.
+fn to_markdown(html: &str, links: &Links) -> String {
+    let document = Html::parse_fragment(html);
+    let mut out = blocks(document.root_element(), links).join("\n\n");
+    out.push('\n');
+    out
+}
+
+fn is_block(tag: &str) -> bool {
+    matches!(
+        tag,
+        "p" | "div" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "ul" | "ol" | "blockquote" | "pre" | "hr" | "table"
+    )
+}
+
+/// The children of a container as Markdown blocks. Loose inline content is
+/// treated as a paragraph.
+fn blocks(parent: ElementRef, links: &Links) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut pending = String::new();
+    for child in parent.children() {
+        match ElementRef::wrap(child) {
+            Some(el) if is_block(el.value().name()) => {
+                push_paragraph(&mut out, &mut pending);
+                out.extend(block(el, links));
+            }
+            _ => inline(child, links, &mut pending),
+        }
+    }
+    push_paragraph(&mut out, &mut pending);
+    out
+}
+
+fn push_paragraph(out: &mut Vec<String>, pending: &mut String) {
+    let text = std::mem::take(pending);
+    let text = text.trim();
+    if !text.is_empty() {
+        out.push(escape_line_starts(text));
+    }
+}
+
+fn block(el: ElementRef, links: &Links) -> Option<String> {
+    let tag = el.value().name();
+    match tag {
+        "p" => {
+            let text = inline_content(el, links);
+            (!text.is_empty()).then(|| escape_line_starts(&text))
+        }
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+            let level = usize::from(tag.as_bytes()[1] - b'0');
+            let text = inline_content(el, links).replace("\\\n", " ");
+            Some(format!("{} {}", "#".repeat(level), text))
+        }
+        "ul" | "ol" => Some(list(el, links)),
+        "blockquote" => {
+            let inner = blocks(el, links).join("\n\n");
+            Some(prefix_lines(&inner, "> ", ">"))
+        }
+        "pre" => Some(code_block(el)),
+        "hr" => Some("---".to_string()),
+        "table" => Some(table(el, links)),
+        _ => {
+            let inner = blocks(el, links);
+            (!inner.is_empty()).then(|| inner.join("\n\n"))
+        }
+    }
+}
+
+fn list(el: ElementRef, links: &Links) -> String {
+    let ordered = el.value().name() == "ol";
+    let start: usize = el.value().attr("start").and_then(|s| s.parse().ok()).unwrap_or(1);
+    let items = el.children().filter_map(ElementRef::wrap).filter(|c| c.value().name() == "li");
+
+    let mut lines = Vec::new();
+    for (number, li) in (start..).zip(items) {
+        let marker = if ordered { format!("{}. ", number) } else { "- ".to_string() };
+
+        // A nested list hugs the line above it so the outer list stays tight.
+        let mut body = String::new();
+        for (i, part) in blocks(li, links).iter().enumerate() {
+            if i > 0 {
+                body.push_str(if starts_list(part) { "\n" } else { "\n\n" });
+            }
+            body.push_str(part);
+        }
+        let indent = " ".repeat(marker.len());
+        lines.push(format!("{}{}", marker, prefix_lines(&body, &indent, "").trim_start()));
+    }
+    lines.join("\n")
+}
+
+/// Paragraph text that looks like a list marker is escaped, so this only
+/// matches lists.
+fn starts_list(block: &str) -> bool {
+    let digits = block.bytes().take_while(u8::is_ascii_digit).count();
+    block.starts_with("- ") || (digits > 0 && block[digits..].starts_with(". "))
+}
+
+fn code_block(pre: ElementRef) -> String {
+    let code: String = pre.text().collect();
+    let language = pre
+        .children()
+        .filter_map(ElementRef::wrap)
+        .find(|c| c.value().name() == "code")
+        .and_then(|c| c.value().attr("class"))
+        .and_then(|class| class.split_whitespace().find_map(|c| c.strip_prefix("language-")))
+        .unwrap_or_default();
+    let fence = "`".repeat(longest_run(&code, '`').max(2) + 1);
+    format!("{}{}\n{}\n{}", fence, language, code.trim_end_matches('\n'), fence)
+}
+
+fn table(el: ElementRef, links: &Links) -> String {
+    let rows: Vec<Vec<String>> = el
+        .descendants()
+        .filter_map(ElementRef::wrap)
+        .filter(|r| r.value().name() == "tr")
+        .map(|row| {
+            row.children()
+                .filter_map(ElementRef::wrap)
+                .filter(|c| matches!(c.value().name(), "td" | "th"))
+                .map(|cell| {
+                    blocks(cell, links)
+                        .join("<br>")
+                        .replace("\\\n", "<br>")
+                        .replace('\n', " ")
+                        .replace('|', "\\|")
+                })
+                .collect()
+        })
+        .collect();
+
+    let columns = rows.iter().map(Vec::len).max().unwrap_or(0).max(1);
+    let line = |cells: &[String]| {
+        let mut padded: Vec<&str> = cells.iter().map(String::as_str).collect();
+        padded.resize(columns, "");
+        format!("| {} |", padded.join(" | "))
+    };
+
+    // GFM needs a header row; the editor's first row plays that part.
+    let mut lines = vec![line(rows.first().map(Vec::as_slice).unwrap_or_default())];
+    lines.push(format!("|{}", " --- |".repeat(columns)));
+    lines.extend(rows.iter().skip(1).map(|r| line(r)));
+    lines.join("\n")
+}
+
+/// Inline Markdown for an element's children, whitespace collapsed.
+fn inline_content(el: ElementRef, links: &Links) -> String {
+    let mut out = String::new();
+    for child in el.children() {
+        inline(child, links, &mut out);
+    }
+    out.trim().to_string()
+}
+
+fn inline(node: ego_tree::NodeRef<Node>, links: &Links, out: &mut String) {
+    let el = match node.value() {
+        Node::Text(text) => {
+            push_text(out, text);
+            return;
+        }
+        Node::Element(_) => ElementRef::wrap(node).expect("element node"),
+        _ => return,
+    };
+
+    match el.value().name() {
+        "strong" | "b" => emphasis(el, links, "**", "**", out),
+        "em" | "i" => emphasis(el, links, "*", "*", out),
+        "s" | "del" | "strike" => emphasis(el, links, "~~", "~~", out),
+        "u" => emphasis(el, links, "<u>", "</u>", out),
+        "code" => {
+            let code: String = el.text().collect();
+            let ticks = "`".repeat(longest_run(&code, '`') + 1);
+            let pad = if code.starts_with('`') || code.ends_with('`') { " " } else { "" };
+            out.push_str(&format!("{}{}{}{}{}", ticks, pad, code, pad, ticks));
+        }
+        "br" => out.push_str("\\\n"),
+        "img" => out.push_str(&image(el, links)),
+        "a" => {
+            let text = inline_content(el, links);
+            match el.value().attr("href") {
+                Some(href) => out.push_str(&format!("[{}]({})", text, destination(&links.url(href)))),
+                None => out.push_str(&text),
+            }
+        }
+        "span" => {
+            let text = inline_content(el, links);
+            match el.value().attr("data-tab-id").and_then(|id| links.tab(id.trim())) {
+                Some(href) => out.push_str(&format!("[{}]({})", text, href)),
+                // Linked tab left out of the export (or gone): keep the words.
+                None => out.push_str(&text),
+            }
+        }
+        _ => {
+            for child in node.children() {
+                inline(child, links, out);
+            }
+        }
+    }
+}
+
+/// Wraps formatted text in markers, keeping surrounding spaces outside them
+/// (`** bold**` doesn't parse as bold).
+fn emphasis(el: ElementRef, links: &Links, open: &str, close: &str, out: &mut String) {
+    let mut inner = String::new();
+    for child in el.children() {
+        inline(child, links, &mut inner);
+    }
+    let trimmed = inner.trim();
+    if trimmed.is_empty() {
+        out.push_str(&inner);
+        return;
+    }
+    if inner.starts_with(char::is_whitespace) && !out.ends_with(' ') {
+        out.push(' ');
+    }
+    out.push_str(open);
+    out.push_str(trimmed);
+    out.push_str(close);
+    if inner.ends_with(char::is_whitespace) {
+        out.push(' ');
+    }
+}
+
+fn image(el: ElementRef, links: &Links) -> String {
+    let alt = escape(el.value().attr("alt").unwrap_or_default());
+    let src = destination(&links.url(el.value().attr("src").unwrap_or_default()));
+    match el.value().attr("title") {
+        Some(title) => format!("![{}]({} \"{}\")", alt, src, title.replace('"', "\\\"")),
+        None => format!("![{}]({})", alt, src),
+    }
+}
+
+/// A link destination that survives spaces and parentheses.
+fn destination(url: &str) -> String {
+    url.replace(' ', "%20").replace('(', "%28").replace(')', "%29")
+}
+
+/// Appends text with HTML whitespace rules applied and Markdown syntax escaped.
+fn push_text(out: &mut String, text: &str) {
+    for (i, word) in text.split(|c: char| c.is_ascii_whitespace()).enumerate() {
+        if i > 0 && !out.is_empty() && !out.ends_with([' ', '\n']) {
+            out.push(' ');
+        }
+        out.push_str(&escape(word));
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '*' | '_' | '`' | '[' | ']' | '<' | '>' | '~') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Stops a paragraph line from being read as a heading, list or quote.
+fn escape_line_starts(text: &str) -> String {
+    text.split('\n')
+        .map(|line| {
+            let digits = line.bytes().take_while(u8::is_ascii_digit).count();
+            if line.starts_with(['#', '+', '-', '=']) {
+                format!("\\{}", line)
+            } else if digits > 0 && line[digits..].starts_with(['.', ')']) {
+                format!("{}\\{}", &line[..digits], &line[digits..])
+            } else {
+                line.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn prefix_lines(text: &str, prefix: &str, blank: &str) -> String {
+    text.split('\n')
+        .map(|line| if line.is_empty() { blank.to_string() } else { format!("{}{}", prefix, line) })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn longest_run(text: &str, c: char) -> usize {
+    text.split(|ch| ch != c).map(str::len).max().unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::{Layout, Outline};
+    use crate::testing::tab;
+    use crate::Tab;
+
+    fn convert(html: &str) -> String {
+        let tabs = vec![
+            Tab { title: "Home".to_string(), ..tab("home", None, "") },
+            Tab { title: "Deep Dive".to_string(), ..tab("deep", Some("home"), "") },
+        ];
+        let outline = Outline::build(tabs, None, |_| true).unwrap();
+        let layout = Layout::new(&outline, files(&outline), &[]);
+        to_markdown(html, &layout.links("Home.md"))
+    }
+
+    #[test]
+    fn formatting_and_blocks_become_commonmark() {
+        assert_eq!(
+            convert("<h2>Title <em>x</em></h2><p><strong>bold </strong>and <s>gone</s> <u>u</u> <code>a`b</code></p>"),
+            "## Title *x*\n\n**bold** and ~~gone~~ <u>u</u> ``a`b``\n",
+        );
+        assert_eq!(
+            convert("<ul><li><p>one</p><ul><li><p>nested</p></li></ul></li><li><p>two</p></li></ul><ol start=\"3\"><li><p>three</p></li></ol>"),
+            "- one\n  - nested\n- two\n\n3. three\n",
+        );
+        assert_eq!(
+            convert("<pre><code class=\"language-rust\">let s = \"```\";\n</code></pre><blockquote><p>q1</p><p>q2</p></blockquote><hr>"),
+            "````rust\nlet s = \"```\";\n````\n\n> q1\n>\n> q2\n\n---\n",
+        );
+        assert_eq!(
+            convert("<table><tbody><tr><th><p>h|1</p></th><th><p>h2</p></th></tr><tr><td><p>c</p></td></tr></tbody></table>"),
+            "| h\\|1 | h2 |\n| --- | --- |\n| c |  |\n",
+        );
+    }
+
+    #[test]
+    fn text_that_looks_like_markdown_is_escaped() {
+        assert_eq!(
+            convert("<p># not a heading</p><p>1. not a list *nor* [link]</p><p>a<br>b</p>"),
+            "\\# not a heading\n\n1\\. not a list \\*nor\\* \\[link\\]\n\na\\\nb\n",
+        );
+        assert_eq!(
+            convert("<p><img src=\"/x.png\" alt=\"a*b\" title='say \"hi\"'></p>"),
+            "![a\\*b](/x.png \"say \\\"hi\\\"\")\n",
+        );
+    }
+
+    #[test]
+    fn wiki_links_point_at_the_exported_file_or_fall_back_to_text() {
+        assert_eq!(
+            convert("<p><span data-tab-id=\"deep\" class=\"wiki-link\">Deep</span> <span data-tab-id=\"nope\" class=\"wiki-link\">Nope</span> <a href=\"https://x.org/a (b)\">out</a></p>"),
+            "[Deep](Home/Deep%20Dive.md) Nope [out](https://x.org/a%20%28b%29)\n",
+        );
+    }
+
+    #[test]
+    fn no_tab_lands_in_the_attachments_folder() {
+        let tabs = vec![
+            Tab { title: "Attachments".to_string(), ..tab("a", None, "") },
+            Tab { title: "Scan".to_string(), ..tab("b", Some("a"), "") },
+        ];
+        let outline = Outline::build(tabs, None, |_| true).unwrap();
+        assert_eq!(files(&outline), ["Attachments (2).md", "Attachments (2)/Scan.md"]);
+    }
+}
@@ -0,0 +1,434 @@
+//! Server-side exports of a subtree (or the whole encyclopedia). Every format
+//! starts from the same [`Outline`]: the tabs the caller may read, parents
+//! before children, each column in its curated order.
+
+use axum::http::{header, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::io::{Cursor, Write};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::acl::{self, Role};
+use crate::attachments::{hash_in_url, Attachments};
+use crate::auth::User;
+use crate::{store_error, AppState, Tab};
+
+mod epub;
+mod html;
+mod markdown;
+mod pdf;
+mod site;
+
+pub use epub::export_epub;
+pub use markdown::export_markdown;
+pub use pdf::export_pdf;
+pub use site::{export_site, site_command};
+
+/// Where bundled attachments go, at the top of an export. Reserved so no tab
+/// can land its files among them.
+pub const ASSET_DIR: &str = "attachments";
+
+#[derive(Deserialize)]
+pub struct ExportParams {
+    /// Tab to export with everything below it; the whole tree when omitted.
+    root: Option<String>,
+}
+
+pub struct Entry {
+    pub tab: Tab,
+    /// 0 for the exported root(s).
+    pub depth: usize,
+    /// Index of the parent entry, `None` for the exported root(s).
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+}
+
+pub struct Outline {
+    /// Pre-order: every entry comes after its parent.
+    pub entries: Vec<Entry>,
+    pub roots: Vec<usize>,
+    pub by_id: HashMap<String, usize>,
+    /// Title of the export as a whole.
+    pub title: String,
+}
+
+impl Outline {
+    /// Lays `tabs` (in list order) out as a tree under `root`, or as a forest
+    /// of every top-level tab. A tab the caller can't read is left out along
+    /// with everything below it, so no path leads through a hidden branch.
+    fn build(tabs: Vec<Tab>, root: Option<&str>, readable: impl Fn(&str) -> bool) -> Option<Self> {
+        let ids: HashSet<String> = tabs.iter().map(|t| t.id.clone()).collect();
+        let is_root = |tab: &Tab| match root {
+            Some(root) => tab.id == root,
+            // Orphans whose parent is gone still belong somewhere.
+            None => tab.parent_id.as_deref().is_none_or(|p| !ids.contains(p)),
+        };
+
+        let mut children: HashMap<String, Vec<Tab>> = HashMap::new();
+        let mut tops = Vec::new();
+        for tab in tabs {
+            if is_root(&tab) {
+                tops.push(tab);
+            } else if let Some(parent_id) = tab.parent_id.clone() {
+                children.entry(parent_id).or_default().push(tab);
+            }
+        }
+
+        let mut outline = Outline { entries: Vec::new(), roots: Vec::new(), by_id: HashMap::new(), title: String::new() };
+        let mut stack: Vec<(Tab, Option<usize>)> = tops.into_iter().rev().map(|t| (t, None)).collect();
+        while let Some((tab, parent)) = stack.pop() {
+            if !readable(&tab.id) {
+                continue;
+            }
+            let index = outline.entries.len();
+            let depth = parent.map_or(0, |p| outline.entries[p].depth + 1);
+            match parent {
+                Some(p) => outline.entries[p].children.push(index),
+                None => outline.roots.push(index),
+            }
+            if let Some(below) = children.remove(&tab.id) {
+                stack.extend(below.into_iter().rev().map(|t| (t, Some(index))));
+            }
+            outline.by_id.insert(tab.id.clone(), index);
+            outline.entries.push(Entry { tab, depth, parent, children: Vec::new() });
+        }
+
+        if root.is_some() && outline.entries.is_empty() {
+            return None;
+        }
+        outline.title = match root {
+            Some(_) if !outline.entries[0].tab.title.trim().is_empty() => outline.entries[0].tab.title.clone(),
+            Some(_) => "Untitled".to_string(),
+            None => "Encyclopedia".to_string(),
+        };
+        Some(outline)
+    }
+
+    /// Attachment hashes referenced anywhere in the exported content, in order
+    /// of first appearance.
+    pub fn attachment_hashes(&self) -> Vec<String> {
+        let mut seen = HashSet::new();
+        let mut hashes = Vec::new();
+        for entry in &self.entries {
+            for (start, _) in entry.tab.content.match_indices("/attachments/") {
+                if let Some(hash) = hash_in_url(&entry.tab.content[start..]) {
+                    if seen.insert(hash) {
+                        hashes.push(hash.to_string());
+                    }
+                }
+            }
+        }
+        hashes
+    }
+
+    /// Relative file stems (no extension) mirroring the tree: a tab becomes
+    /// `Parent/Child`, and siblings with the same title get ` (2)`, ` (3)`….
+    /// `reserved` names are never handed to a tab.
+    pub fn file_stems(&self, reserved: &[&str]) -> Vec<String> {
+        let mut stems = vec![String::new(); self.entries.len()];
+        let mut taken: HashMap<Option<usize>, HashSet<String>> = HashMap::new();
+        for (index, entry) in self.entries.iter().enumerate() {
+            let used = taken.entry(entry.parent).or_insert_with(|| reserved.iter().map(|r| r.to_string()).collect());
+            let base = file_name(&entry.tab.title);
+            let mut name = base.clone();
+            let mut n = 2;
+            while !used.insert(name.to_lowercase()) {
+                name = format!("{} ({})", base, n);
+                n += 1;
+            }
+            stems[index] = match entry.parent {
+                Some(p) => format!("{}/{}", stems[p], name),
+                None => name,
+            };
+        }
+        stems
+    }
+}
+
+/// Where every tab and bundled attachment lands inside an export.
+pub struct Layout<'a> {
+    outline: &'a Outline,
+    /// The file for each outline entry, by index.
+    pub files: Vec<String>,
+    assets: HashMap<&'a str, String>,
+}
+
+impl<'a> Layout<'a> {
+    /// Attachments go in [`ASSET_DIR`] at the top.
+    pub fn new(outline: &'a Outline, files: Vec<String>, assets: &'a [Asset]) -> Self {
+        let assets = assets.iter().map(|a| (a.hash.as_str(), format!("{}/{}", ASSET_DIR, a.name))).collect();
+        Layout { outline, files, assets }
+    }
+
+    pub fn links(&'a self, from: &'a str) -> Links<'a> {
+        Links { layout: self, from }
+    }
+}
+
+/// Links as seen from one file of a [`Layout`].
+pub struct Links<'a> {
+    layout: &'a Layout<'a>,
+    from: &'a str,
+}
+
+impl Links<'_> {
+    /// Relative, encoded link to an exported tab; `None` when the target was
+    /// left out of the export.
+    pub fn tab(&self, id: &str) -> Option<String> {
+        let index = *self.layout.outline.by_id.get(id)?;
+        Some(encode_path(&relative_path(self.from, &self.layout.files[index])))
+    }
+
+    /// A `src`/`href` value with bundled attachments pointing inside the
+    /// export; anything else is left as it is.
+    pub fn url(&self, url: &str) -> String {
+        self.bundled(url).unwrap_or_else(|| url.to_string())
+    }
+
+    /// The relative link to an attachment's copy in the export, if `url`
+    /// names one that was bundled.
+    pub fn bundled(&self, url: &str) -> Option<String> {
+        let path = self.layout.assets.get(hash_in_url(url)?)?;
+        Some(encode_path(&relative_path(self.from, path)))
+    }
+
+    /// Relative, encoded link to any other file of the export.
+    pub fn file(&self, path: &str) -> String {
+        encode_path(&relative_path(self.from, path))
+    }
+}
+
+/// Loads what `user` may export under `root`.
+pub async fn outline(state: &AppState, user: &User, root: Option<&str>) -> Result<Outline, (StatusCode, String)> {
+    let index = acl::load(state).await?;
+    if let Some(root) = root {
+        acl::require(&index, root, user, Role::Viewer)?;
+    }
+    let tabs = state.store.list_tabs().await.map_err(|e| store_error("Export", e))?;
+    Outline::build(tabs, root, |id| index.can(id, &user.id, Role::Viewer))
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("Tab {} not found", root.unwrap_or_default())))
+}
+
+/// An attachment copied into an export.
+pub struct Asset {
+    pub hash: String,
+    /// `<hash>.<ext>`, the file name inside the export.
+    pub name: String,
+    pub content_type: String,
+    pub bytes: Vec<u8>,
+}
+
+/// Reads every attachment the outline refers to. Ones missing from disk are
+/// skipped; their links keep pointing at the server.
+pub async fn assets(attachments: &Attachments, outline: &Outline) -> Result<Vec<Asset>, (StatusCode, String)> {
+    let mut assets = Vec::new();
+    for hash in outline.attachment_hashes() {
+        let found = attachments.get(&hash).await.map_err(export_error)?;
+        if let Some((bytes, content_type)) = found {
+            let name = format!("{}.{}", hash, extension_for(&content_type));
+            assets.push(Asset { hash, name, content_type, bytes });
+        }
+    }
+    Ok(assets)
+}
+
+/// A title made safe to use as a file or folder name on any OS.
+pub fn file_name(title: &str) -> String {
+    let cleaned: String = title
+        .chars()
+        .map(|c| if c.is_control() || "/\\:*?\"<>|#^[]".contains(c) { '-' } else { c })
+        .collect();
+    let trimmed = cleaned.trim().trim_matches('.').trim();
+    let name: String = trimmed.chars().take(100).collect();
+    if name.is_empty() { "Untitled".to_string() } else { name }
+}
+
+/// The path from the directory holding `from` to `to`, both relative to the
+/// archive root and `/`-separated.
+pub fn relative_path(from: &str, to: &str) -> String {
+    let from_dir: Vec<&str> = from.split('/').collect();
+    let from_dir = &from_dir[..from_dir.len() - 1];
+    let target: Vec<&str> = to.split('/').collect();
+
+    let common = from_dir.iter().zip(&target).take_while(|(a, b)| a == b).count();
+    let mut parts: Vec<&str> = vec![".."; from_dir.len() - common];
+    parts.extend(&target[common..]);
+    parts.join("/")
+}
+
+/// Percent-encodes a relative path for use as a link target.
+pub fn encode_path(path: &str) -> String {
+    let mut out = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~/!$&'*+,;=:@".contains(&byte) {
+            out.push(byte as char);
+        } else {
+            out.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    out
+}
+
+/// File extension for an attachment's content type.
+pub fn extension_for(content_type: &str) -> &'static str {
+    match content_type {
+        "image/png" => "png",
+        "image/jpeg" => "jpg",
+        "image/gif" => "gif",
+        "image/webp" => "webp",
+        "image/svg+xml" => "svg",
+        _ => "bin",
+    }
+}
+
+/// Sends a generated file as a download named after the export.
+pub fn download(bytes: Vec<u8>, content_type: &'static str, title: &str, extension: &str) -> Response {
+    // Header values must stay ASCII; keep the readable part of the name.
+    let ascii: String = file_name(title).chars().map(|c| if c.is_ascii() && c != '"' { c } else { '_' }).collect();
+    let disposition = HeaderValue::from_str(&format!("attachment; filename=\"{}.{}\"", ascii, extension))
+        .unwrap_or(HeaderValue::from_static("attachment"));
+    (
+        [
+            (header::CONTENT_TYPE, HeaderValue::from_static(content_type)),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        bytes,
+    )
+        .into_response()
+}
+
+/// Packs `(path, bytes)` pairs into a zip, in order. Attachments (whatever
+/// sits under `assets`) are compressed already and an EPUB's `mimetype` must
+/// not be, so only the generated files are deflated.
+pub fn zip(files: &[(String, Vec<u8>)], assets: &str) -> Result<Vec<u8>, (StatusCode, String)> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    for (path, bytes) in files {
+        let stored = path == "mimetype" || path.strip_prefix(assets).is_some_and(|rest| rest.starts_with('/'));
+        let method = if stored { CompressionMethod::Stored } else { CompressionMethod::Deflated };
+        zip.start_file(path.as_str(), SimpleFileOptions::default().compression_method(method)).map_err(export_error)?;
+        zip.write_all(bytes).map_err(export_error)?;
+    }
+    Ok(zip.finish().map_err(export_error)?.into_inner())
+}
+
+fn export_error(e: impl std::fmt::Display) -> (StatusCode, String) {
+    eprintln!("❌ Export Error: {}", e);
+    (StatusCode::INTERNAL_SERVER_ERROR, format!("Export Error: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::tab;
+
+    fn titled(id: &str, title: &str, parent_id: Option<&str>) -> Tab {
+        Tab { title: title.to_string(), ..tab(id, parent_id, "") }
+    }
+
+    fn ids(outline: &Outline) -> Vec<(&str, usize)> {
+        outline.entries.iter().map(|e| (e.tab.id.as_str(), e.depth)).collect()
+    }
+
+    #[test]
+    fn outlines_run_parents_first_and_prune_hidden_branches() {
+        let tabs = vec![
+            titled("a", "A", None),
+            titled("a1", "A1", Some("a")),
+            titled("a2", "A2", Some("a")),
+            titled("a1x", "A1x", Some("a1")),
+            titled("b", "B", None),
+            titled("stray", "Stray", Some("gone")),
+        ];
+
+        let whole = Outline::build(tabs.clone(), None, |_| true).unwrap();
+        assert_eq!(ids(&whole), [("a", 0), ("a1", 1), ("a1x", 2), ("a2", 1), ("b", 0), ("stray", 0)]);
+        assert_eq!(whole.roots, [0, 4, 5]);
+        assert_eq!(whole.entries[0].children, [1, 3]);
+        assert_eq!(whole.title, "Encyclopedia");
+
+        let pruned = Outline::build(tabs.clone(), Some("a"), |id| id != "a1").unwrap();
+        assert_eq!(ids(&pruned), [("a", 0), ("a2", 1)]);
+        assert_eq!(pruned.title, "A");
+        assert!(Outline::build(tabs, Some("missing"), |_| true).is_none());
+    }
+
+    #[test]
+    fn file_stems_mirror_the_tree_and_number_clashes() {
+        let tabs = vec![
+            titled("a", "Notes", None),
+            titled("b", "notes", None),
+            titled("c", "Index", None),
+            titled("d", "Notes", Some("a")),
+            titled("e", "a/b: c?", Some("a")),
+            titled("f", "  ", Some("b")),
+        ];
+        let outline = Outline::build(tabs, None, |_| true).unwrap();
+        assert_eq!(
+            outline.file_stems(&["index"]),
+            ["Notes", "Notes/Notes", "Notes/a-b- c-", "notes (2)", "notes (2)/Untitled", "Index (2)"],
+        );
+    }
+
+    #[test]
+    fn relative_paths_climb_out_of_the_source_folder() {
+        assert_eq!(relative_path("A.md", "B.md"), "B.md");
+        assert_eq!(relative_path("A.md", "A/B.md"), "A/B.md");
+        assert_eq!(relative_path("A/B/C.md", "A/D.md"), "../D.md");
+        assert_eq!(relative_path("A/B/C.md", "attachments/x.png"), "../../attachments/x.png");
+        assert_eq!(encode_path("../Über uns (2).md"), "../%C3%9Cber%20uns%20%282%29.md");
+    }
+
+    #[test]
+    fn file_names_lose_what_no_filesystem_takes() {
+        assert_eq!(file_name("..a<b>|c.."), "a-b--c");
+        assert_eq!(file_name("a\tb"), "a-b");
+        assert_eq!(file_name(" . "), "Untitled");
+        assert_eq!(file_name(&"x".repeat(150)).len(), 100);
+    }
+
+    #[test]
+    fn attachment_hashes_are_collected_once_in_order() {
+        let (first, second) = ("a".repeat(64), "b".repeat(64));
+        let content = |hashes: &[&str]| hashes.iter().map(|h| format!("<img src=\"http://x/attachments/{}\">", h)).collect::<String>();
+        let tabs = vec![
+            Tab { content: content(&[&second, &first]), ..tab("p", None, "") },
+            Tab { content: content(&[&first, "short"]), ..tab("q", None, "") },
+        ];
+        let outline = Outline::build(tabs, None, |_| true).unwrap();
+        assert_eq!(outline.attachment_hashes(), [second, first]);
+    }
+
+    #[test]
+    fn layout_links_are_relative_to_the_file_they_sit_in() {
+        let outline = Outline::build(vec![titled("a", "A", None), titled("b", "B b", Some("a"))], None, |_| true).unwrap();
+        let hash = "c".repeat(64);
+        let assets = [Asset { hash: hash.clone(), name: format!("{}.png", hash), content_type: "image/png".to_string(), bytes: Vec::new() }];
+        let layout = Layout::new(&outline, vec!["A.html".to_string(), "A/B b.html".to_string()], &assets);
+
+        let from_b = layout.links("A/B b.html");
+        assert_eq!(from_b.tab("a").as_deref(), Some("../A.html"));
+        assert_eq!(layout.links("A.html").tab("b").as_deref(), Some("A/B%20b.html"));
+        assert_eq!(from_b.tab("elsewhere"), None);
+
+        let bundled = format!("../attachments/{}.png", hash);
+        assert_eq!(from_b.url(&format!("http://old/attachments/{}?w=512", hash)), bundled);
+        assert_eq!(from_b.bundled(&format!("/attachments/{}", "d".repeat(64))), None);
+        assert_eq!(from_b.url("https://example.com/x.png"), "https://example.com/x.png");
+        assert_eq!(from_b.file("style.css"), "../style.css");
+    }
+
+    #[test]
+    fn only_bundled_assets_and_the_mimetype_are_stored_uncompressed() {
+        use zip::ZipArchive;
+
+        let files: Vec<(String, Vec<u8>)> = ["mimetype", "attachments/x.png", "My attachments/Note.md", "attachments.md"]
+            .iter()
+            .map(|path| (path.to_string(), b"bytes".to_vec()))
+            .collect();
+        let mut archive = ZipArchive::new(Cursor::new(zip(&files, ASSET_DIR).unwrap())).unwrap();
+        let methods: Vec<CompressionMethod> = (0..archive.len()).map(|i| archive.by_index(i).unwrap().compression()).collect();
+        assert_eq!(methods, [CompressionMethod::Stored, CompressionMethod::Stored, CompressionMethod::Deflated, CompressionMethod::Deflated]);
+    }
+}
@@ -0,0 +1,1056 @@
+//! `GET /export/pdf`: the subtree as a print-ready A4 PDF, typeset here
+//! rather than in a headless browser. Tabs become sections numbered by depth
+//! (`2.1.3`), a table of contents with page numbers comes first, and every
+//! wiki link is followed by the section and page it points to.
+//!
+//! Text is set in DejaVu, read from `PDF_FONT_DIR` (default
+//! `/usr/share/fonts/truetype/dejavu`), so any script the fonts cover prints.
+//! Page numbers are only known once everything is laid out, so layout runs
+//! again until the cross-references stop moving.
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::Response,
+};
+use owned_ttf_parser::{AsFaceRef, OwnedFace};
+use printpdf::{
+    Actions, BorderArray, Color, ColorArray, ColorBits, ColorSpace, HighlightingMode, Image, ImageTransform, ImageXObject,
+    IndirectFontRef, Line, LinkAnnotation, Mm, PdfDocument, PdfLayerReference, Point, Pt, Px, Rect, Rgb,
+};
+use scraper::{ElementRef, Html, Node};
+use std::collections::{HashMap, HashSet};
+use std::io::Cursor;
+use std::path::PathBuf;
+
+use super::{assets, download, export_error, outline, Asset, ExportParams, Outline};
+use crate::attachments::hash_in_url;
+use crate::auth::CurrentUser;
+use crate::AppState;
+
+// A4 in points, with 20 mm margins.
+const PAGE_WIDTH: f32 = 595.28;
+const PAGE_HEIGHT: f32 = 841.89;
+const MARGIN: f32 = 56.7;
+const CONTENT_WIDTH: f32 = PAGE_WIDTH - 2.0 * MARGIN;
+
+const BODY_SIZE: f32 = 10.5;
+const CODE_SIZE: f32 = 9.0;
+const LINE_HEIGHT: f32 = 1.4;
+const INDENT: f32 = 18.0;
+/// Section heading sizes by depth; deeper sections use the last one.
+const SECTION_SIZES: [f32; 4] = [20.0, 16.0, 13.5, 12.0];
+/// Widest an embedded image is kept, in pixels.
+const MAX_IMAGE_PIXELS: u32 = 1600;
+
+const DEFAULT_FONT_DIR: &str = "/usr/share/fonts/truetype/dejavu";
+// Regular, bold, italic, bold italic, monospace. The italics ship in a
+// separate package on some systems; upright faces stand in when missing.
+const FONT_FILES: [&str; 5] = [
+    "DejaVuSans.ttf",
+    "DejaVuSans-Bold.ttf",
+    "DejaVuSans-Oblique.ttf",
+    "DejaVuSans-BoldOblique.ttf",
+    "DejaVuSansMono.ttf",
+];
+
+pub async fn export_pdf(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Query(params): Query<ExportParams>
+) -> Result<Response, (StatusCode, String)> {
+    let outline = outline(&state, &user, params.root.as_deref()).await?;
+    let assets = assets(&state.attachments, &outline).await?;
+    let fonts = Fonts::load().await?;
+
+    let title = outline.title.clone();
+    let sections = outline.entries.len();
+    // printpdf's document isn't Send, and typesetting is all CPU anyway.
+    let bytes = tokio::task::spawn_blocking(move || render(&outline, &assets, &fonts))
+        .await
+        .map_err(export_error)??;
+
+    println!("🖨️ {} exported {} tabs as PDF ({} bytes)", user.username, sections, bytes.len());
+    Ok(download(bytes, "application/pdf", &title, "pdf"))
+}
+
+fn render(outline: &Outline, assets: &[Asset], fonts: &Fonts) -> Result<Vec<u8>, (StatusCode, String)> {
+    let numbers = section_numbers(outline);
+    let images = decode_images(assets);
+    let content: Vec<Vec<Block>> = outline.entries.iter().map(|e| parse(&e.tab.content, outline)).collect();
+
+    // The contents pages and the cross-references both print page numbers,
+    // which can push text onto other pages; a couple of rounds settle it.
+    let mut starts = vec![0; outline.entries.len()];
+    let mut pages = Vec::new();
+    for _ in 0..4 {
+        let mut setter = Typesetter::new(fonts, &images, &numbers, &starts);
+        setter.document(outline, &content);
+        let (found, laid_out) = (setter.starts, setter.pages);
+        let settled = found == starts;
+        starts = found;
+        pages = laid_out;
+        if settled {
+            break;
+        }
+    }
+
+    draw(outline, &numbers, &starts, &pages, &images, fonts)
+}
+
+/// `1`, `1.1`, `1.2`, `2`… following the outline.
+fn section_numbers(outline: &Outline) -> Vec<String> {
+    let mut numbers = vec![String::new(); outline.entries.len()];
+    for (position, &index) in outline.roots.iter().enumerate() {
+        numbers[index] = (position + 1).to_string();
+    }
+    for (index, entry) in outline.entries.iter().enumerate() {
+        for (position, &child) in entry.children.iter().enumerate() {
+            numbers[child] = format!("{}.{}", numbers[index], position + 1);
+        }
+    }
+    numbers
+}
+
+// ---------------------------------------------------------------------------
+// Fonts
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Face {
+    Regular,
+    Bold,
+    Italic,
+    BoldItalic,
+    Mono,
+}
+
+impl Face {
+    const ALL: [Face; 5] = [Face::Regular, Face::Bold, Face::Italic, Face::BoldItalic, Face::Mono];
+}
+
+struct Fonts {
+    faces: Vec<OwnedFace>,
+}
+
+impl Fonts {
+    async fn load() -> Result<Self, (StatusCode, String)> {
+        let dir = PathBuf::from(std::env::var("PDF_FONT_DIR").unwrap_or_else(|_| DEFAULT_FONT_DIR.to_string()));
+        let mut faces: Vec<OwnedFace> = Vec::new();
+        for (i, file) in FONT_FILES.iter().enumerate() {
+            let bytes = match tokio::fs::read(dir.join(file)).await {
+                Ok(bytes) => bytes,
+                // Italic falls back to its upright face.
+                Err(_) if matches!(i, 2 | 3) => faces[i - 2].as_slice().to_vec(),
+                Err(e) => {
+                    eprintln!("❌ PDF fonts missing from {}: {}", dir.display(), e);
+                    return Err((
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        format!("PDF export needs the DejaVu fonts ({} not found; set PDF_FONT_DIR)", file),
+                    ));
+                }
+            };
+            faces.push(OwnedFace::from_vec(bytes, 0).map_err(export_error)?);
+        }
+        Ok(Fonts { faces })
+    }
+
+    fn face(&self, face: Face) -> &OwnedFace {
+        &self.faces[face as usize]
+    }
+
+    fn width(&self, face: Face, text: &str, size: f32) -> f32 {
+        let face = self.face(face).as_face_ref();
+        let units: u32 = text
+            .chars()
+            .filter_map(|c| face.glyph_index(c))
+            .filter_map(|g| face.glyph_hor_advance(g))
+            .map(u32::from)
+            .sum();
+        units as f32 / f32::from(face.units_per_em()) * size
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Content model
+
+#[derive(Clone, Copy, PartialEq)]
+enum Tint {
+    Text,
+    Link,
+    Muted,
+}
+
+impl Tint {
+    fn color(self) -> Color {
+        let (r, g, b) = match self {
+            Tint::Text => (0.1, 0.1, 0.1),
+            Tint::Link => (0.0, 0.32, 0.62),
+            Tint::Muted => (0.45, 0.45, 0.45),
+        };
+        Color::Rgb(Rgb::new(r, g, b, None))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct Style {
+    bold: bool,
+    italic: bool,
+    mono: bool,
+    underline: bool,
+    strike: bool,
+    tint: Tint,
+}
+
+impl Style {
+    const PLAIN: Style = Style { bold: false, italic: false, mono: false, underline: false, strike: false, tint: Tint::Text };
+
+    fn face(self) -> Face {
+        match (self.mono, self.bold, self.italic) {
+            (true, _, _) => Face::Mono,
+            (_, true, true) => Face::BoldItalic,
+            (_, true, false) => Face::Bold,
+            (_, false, true) => Face::Italic,
+            _ => Face::Regular,
+        }
+    }
+}
+
+enum Inline {
+    Text { text: String, style: Style, url: Option<String> },
+    Break,
+    /// Where a wiki link to this outline entry ends.
+    Ref(usize),
+}
+
+enum Block {
+    Heading(usize, Vec<Inline>),
+    Paragraph(Vec<Inline>),
+    List { ordered: bool, start: usize, items: Vec<Vec<Block>> },
+    Quote(Vec<Block>),
+    Code(String),
+    Rule,
+    Table(Vec<Vec<Vec<Inline>>>),
+    /// Attachment hash, or the alt text when the image wasn't bundled.
+    Image(Result<String, String>),
+}
+
+fn parse(html: &str, outline: &Outline) -> Vec<Block> {
+    let document = Html::parse_fragment(html);
+    blocks(document.root_element(), outline)
+}
+
+fn blocks(parent: ElementRef, outline: &Outline) -> Vec<Block> {
+    let mut out = Vec::new();
+    let mut pending = Vec::new();
+    for child in parent.children() {
+        let Some(el) = ElementRef::wrap(child) else {
+            inline(child, Style::PLAIN, None, outline, &mut pending);
+            continue;
+        };
+        let block = match el.value().name() {
+            // The editor wraps a picture on its own in a paragraph.
+            "p" if is_lone_image(el) => image_block(el.child_elements().next().expect("checked above")),
+            "p" => Block::Paragraph(inlines(el, Style::PLAIN, outline)),
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                let level = usize::from(el.value().name().as_bytes()[1] - b'0');
+                Block::Heading(level, inlines(el, Style { bold: true, ..Style::PLAIN }, outline))
+            }
+            "ul" | "ol" => Block::List {
+                ordered: el.value().name() == "ol",
+                start: el.value().attr("start").and_then(|s| s.parse().ok()).unwrap_or(1),
+                items: el
+                    .children()
+                    .filter_map(ElementRef::wrap)
+                    .filter(|li| li.value().name() == "li")
+                    .map(|li| blocks(li, outline))
+                    .collect(),
+            },
+            "blockquote" => Block::Quote(blocks(el, outline)),
+            "pre" => Block::Code(el.text().collect::<String>().trim_end_matches('\n').to_string()),
+            "hr" => Block::Rule,
+            "table" => Block::Table(
+                el.descendants()
+                    .filter_map(ElementRef::wrap)
+                    .filter(|r| r.value().name() == "tr")
+                    .map(|row| {
+                        row.children()
+                            .filter_map(ElementRef::wrap)
+                            .filter(|c| matches!(c.value().name(), "td" | "th"))
+                            .map(|cell| {
+                                let bold = cell.value().name() == "th";
+                                cell_inlines(cell, Style { bold, ..Style::PLAIN }, outline)
+                            })
+                            .collect()
+                    })
+                    .collect(),
+            ),
+            "img" => image_block(el),
+            "div" => {
+                flush(&mut out, &mut pending);
+                out.extend(blocks(el, outline));
+                continue;
+            }
+            _ => {
+                inline(child, Style::PLAIN, None, outline, &mut pending);
+                continue;
+            }
+        };
+        flush(&mut out, &mut pending);
+        out.push(block);
+    }
+    flush(&mut out, &mut pending);
+    out
+}
+
+fn flush(out: &mut Vec<Block>, pending: &mut Vec<Inline>) {
+    let has_text = pending.iter().any(|i| matches!(i, Inline::Text { text, .. } if !text.trim().is_empty()));
+    if has_text {
+        out.push(Block::Paragraph(std::mem::take(pending)));
+    }
+    pending.clear();
+}
+
+fn is_lone_image(el: ElementRef) -> bool {
+    let mut children = el.children().filter(|c| !c.value().as_text().is_some_and(|t| t.trim().is_empty()));
+    matches!((children.next().and_then(ElementRef::wrap), children.next()), (Some(img), None) if img.value().name() == "img")
+}
+
+fn image_block(el: ElementRef) -> Block {
+    let src = el.value().attr("src").unwrap_or_default();
+    match hash_in_url(src) {
+        Some(hash) => Block::Image(Ok(hash.to_string())),
+        None => Block::Image(Err(el.value().attr("alt").unwrap_or_default().to_string())),
+    }
+}
+
+fn inlines(el: ElementRef, style: Style, outline: &Outline) -> Vec<Inline> {
+    let mut out = Vec::new();
+    for child in el.children() {
+        inline(child, style, None, outline, &mut out);
+    }
+    out
+}
+
+/// A table cell's paragraphs run together with line breaks between them.
+fn cell_inlines(cell: ElementRef, style: Style, outline: &Outline) -> Vec<Inline> {
+    let mut out = Vec::new();
+    for child in cell.children() {
+        if !out.is_empty() && ElementRef::wrap(child).is_some_and(|el| el.value().name() == "p") {
+            out.push(Inline::Break);
+        }
+        inline(child, style, None, outline, &mut out);
+    }
+    out
+}
+
+fn inline(node: ego_tree::NodeRef<Node>, style: Style, url: Option<&str>, outline: &Outline, out: &mut Vec<Inline>) {
+    let el = match node.value() {
+        Node::Text(text) => {
+            out.push(Inline::Text { text: text.to_string(), style, url: url.map(str::to_string) });
+            return;
+        }
+        Node::Element(_) => ElementRef::wrap(node).expect("element node"),
+        _ => return,
+    };
+
+    let mut style = style;
+    let mut url = url;
+    match el.value().name() {
+        "strong" | "b" | "th" => style.bold = true,
+        "em" | "i" => style.italic = true,
+        "u" => style.underline = true,
+        "s" | "del" | "strike" => style.strike = true,
+        "code" => style.mono = true,
+        "br" => {
+            out.push(Inline::Break);
+            return;
+        }
+        "img" => {
+            // Inline images don't fit a line of text; keep their description.
+            let alt = el.value().attr("alt").unwrap_or("image");
+            out.push(Inline::Text { text: format!("[{}]", alt), style: Style { tint: Tint::Muted, ..style }, url: None });
+            return;
+        }
+        "a" => {
+            url = el.value().attr("href");
+            style.tint = Tint::Link;
+        }
+        "span" => {
+            if let Some(&target) = el.value().attr("data-tab-id").and_then(|id| outline.by_id.get(id.trim())) {
+                style.tint = Tint::Link;
+                for child in node.children() {
+                    inline(child, style, url, outline, out);
+                }
+                out.push(Inline::Ref(target));
+                return;
+            }
+        }
+        _ => {}
+    }
+    for child in node.children() {
+        inline(child, style, url, outline, out);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Layout
+
+struct DecodedImage {
+    width: u32,
+    height: u32,
+    rgb: Vec<u8>,
+}
+
+fn decode_images(assets: &[Asset]) -> HashMap<String, DecodedImage> {
+    let mut images = HashMap::new();
+    for asset in assets {
+        let Ok(mut image) = image::load_from_memory(&asset.bytes) else { continue };
+        if image.width() > MAX_IMAGE_PIXELS {
+            image = image.resize(MAX_IMAGE_PIXELS, u32::MAX, image::imageops::FilterType::Triangle);
+        }
+        let rgb = image.to_rgb8();
+        images.insert(asset.hash.clone(), DecodedImage { width: rgb.width(), height: rgb.height(), rgb: rgb.into_raw() });
+    }
+    images
+}
+
+/// Something to paint, in points from the page's bottom left corner.
+enum Item {
+    Text { x: f32, y: f32, face: Face, size: f32, tint: Tint, text: String },
+    Line { from: (f32, f32), to: (f32, f32), width: f32, tint: Tint },
+    Image { hash: String, x: f32, y: f32, width: f32, height: f32 },
+    Link { x: f32, y: f32, width: f32, height: f32, url: String },
+}
+
+/// A run of same-styled text on one line.
+struct Run {
+    x: f32,
+    width: f32,
+    text: String,
+    style: Style,
+    url: Option<String>,
+}
+
+struct TextLine {
+    runs: Vec<Run>,
+}
+
+enum Token {
+    /// Pieces of one word that differ in style, e.g. `**bold**,`.
+    Word(Vec<(String, Style, Option<String>)>),
+    Space(Style),
+    Break,
+}
+
+struct Typesetter<'a> {
+    fonts: &'a Fonts,
+    images: &'a HashMap<String, DecodedImage>,
+    numbers: &'a [String],
+    /// Page each section started on in the previous round.
+    known: &'a [usize],
+    pages: Vec<Vec<Item>>,
+    /// Top of the free space on the current page.
+    y: f32,
+    starts: Vec<usize>,
+}
+
+impl<'a> Typesetter<'a> {
+    fn new(fonts: &'a Fonts, images: &'a HashMap<String, DecodedImage>, numbers: &'a [String], known: &'a [usize]) -> Self {
+        Typesetter { fonts, images, numbers, known, pages: vec![Vec::new()], y: PAGE_HEIGHT - MARGIN, starts: vec![0; known.len()] }
+    }
+
+    fn page(&self) -> usize {
+        self.pages.len()
+    }
+
+    fn new_page(&mut self) {
+        self.pages.push(Vec::new());
+        self.y = PAGE_HEIGHT - MARGIN;
+    }
+
+    /// Starts a new page unless `height` still fits on this one.
+    fn ensure(&mut self, height: f32) {
+        if self.y - height < MARGIN && self.y < PAGE_HEIGHT - MARGIN {
+            self.new_page();
+        }
+    }
+
+    fn push(&mut self, item: Item) {
+        self.pages.last_mut().expect("there is always a page").push(item);
+    }
+
+    fn document(&mut self, outline: &Outline, content: &[Vec<Block>]) {
+        self.title_and_contents(outline);
+        for (index, entry) in outline.entries.iter().enumerate() {
+            if entry.depth == 0 {
+                self.new_page();
+            }
+            let size = SECTION_SIZES[entry.depth.min(SECTION_SIZES.len() - 1)];
+            self.ensure(size * 4.0);
+            self.y -= if self.y < PAGE_HEIGHT - MARGIN { size } else { 0.0 };
+            self.starts[index] = self.page();
+
+            let heading = format!("{} {}", self.numbers[index], entry.tab.title);
+            let style = Style { bold: true, ..Style::PLAIN };
+            self.paragraph(&[Inline::Text { text: heading, style, url: None }], MARGIN, CONTENT_WIDTH, size);
+            self.blocks(&content[index], MARGIN, CONTENT_WIDTH);
+        }
+    }
+
+    fn title_and_contents(&mut self, outline: &Outline) {
+        let bold = Style { bold: true, ..Style::PLAIN };
+        self.paragraph(&[Inline::Text { text: outline.title.clone(), style: bold, url: None }], MARGIN, CONTENT_WIDTH, 24.0);
+        self.y -= 12.0;
+        self.paragraph(&[Inline::Text { text: "Contents".to_string(), style: bold, url: None }], MARGIN, CONTENT_WIDTH, 14.0);
+
+        let size = BODY_SIZE;
+        let number_width = self.fonts.width(Face::Regular, "0000", size);
+        for (index, entry) in outline.entries.iter().enumerate() {
+            let x = MARGIN + INDENT * entry.depth as f32;
+            let style = if entry.depth == 0 { bold } else { Style::PLAIN };
+            let text = format!("{} {}", self.numbers[index], entry.tab.title);
+            let tokens = self.tokens(&[Inline::Text { text, style, url: None }]);
+            let lines = self.wrap(&tokens, CONTENT_WIDTH - (x - MARGIN) - number_width, size);
+            let last = lines.len() - 1;
+            for (i, line) in lines.into_iter().enumerate() {
+                let baseline = self.line(line, x, size);
+                if i == last {
+                    self.contents_page_number(x, baseline, index, size);
+                }
+            }
+        }
+    }
+
+    /// Dot leaders and the right-aligned page number of a contents line.
+    fn contents_page_number(&mut self, x: f32, baseline: f32, index: usize, size: f32) {
+        let page = self.known[index].to_string();
+        let page_width = self.fonts.width(Face::Regular, &page, size);
+        let right = MARGIN + CONTENT_WIDTH;
+        self.push(Item::Text { x: right - page_width, y: baseline, face: Face::Regular, size, tint: Tint::Text, text: page });
+
+        let line_end = match self.pages.last().and_then(|p| p.iter().rev().nth(1)) {
+            Some(Item::Text { x, text, face, size, .. }) => x + self.fonts.width(*face, text, *size),
+            _ => x,
+        };
+        let dot = self.fonts.width(Face::Regular, ". ", size);
+        let dots = ((right - page_width - line_end - 8.0) / dot).floor();
+        if dots >= 2.0 {
+            let text = ". ".repeat(dots as usize);
+            let start = right - page_width - 4.0 - dot * dots;
+            self.push(Item::Text { x: start, y: baseline, face: Face::Regular, size, tint: Tint::Muted, text });
+        }
+    }
+
+    fn blocks(&mut self, blocks: &[Block], x: f32, width: f32) {
+        for block in blocks {
+            self.block(block, x, width);
+        }
+    }
+
+    fn block(&mut self, block: &Block, x: f32, width: f32) {
+        match block {
+            Block::Paragraph(inlines) => {
+                self.paragraph(inlines, x, width, BODY_SIZE);
+                self.y -= BODY_SIZE * 0.5;
+            }
+            Block::Heading(level, inlines) => {
+                let size = match level {
+                    1 => 14.0,
+                    2 => 12.5,
+                    _ => 11.5,
+                };
+                // Keep a heading with at least two lines of what follows.
+                self.ensure(size * LINE_HEIGHT + 2.0 * BODY_SIZE * LINE_HEIGHT + size * 0.5);
+                self.y -= size * 0.5;
+                self.paragraph(inlines, x, width, size);
+                self.y -= size * 0.25;
+            }
+            Block::List { ordered, start, items } => {
+                for (n, item) in (*start..).zip(items) {
+                    let marker = if *ordered { format!("{}.", n) } else { "•".to_string() };
+                    self.ensure(BODY_SIZE * LINE_HEIGHT);
+                    let baseline = self.y - BODY_SIZE * 1.05;
+                    let marker_x = x + INDENT - 5.0 - self.fonts.width(Face::Regular, &marker, BODY_SIZE);
+                    let tint = Tint::Text;
+                    self.push(Item::Text { x: marker_x, y: baseline, face: Face::Regular, size: BODY_SIZE, tint, text: marker });
+                    self.blocks(item, x + INDENT, width - INDENT);
+                }
+            }
+            Block::Quote(inner) => {
+                let start = (self.page(), self.y);
+                self.blocks(inner, x + 12.0, width - 12.0);
+                self.bar(start, x + 2.0);
+            }
+            Block::Code(code) => {
+                let start = (self.page(), self.y);
+                for text in code.split('\n') {
+                    let tokens = vec![Token::Word(vec![(text.to_string(), Style { mono: true, ..Style::PLAIN }, None)])];
+                    for line in self.wrap(&tokens, width - 12.0, CODE_SIZE) {
+                        self.line(line, x + 12.0, CODE_SIZE);
+                    }
+                }
+                self.bar(start, x + 2.0);
+                self.y -= BODY_SIZE * 0.5;
+            }
+            Block::Rule => {
+                self.ensure(12.0);
+                self.y -= 6.0;
+                self.push(Item::Line { from: (x, self.y), to: (x + width, self.y), width: 0.5, tint: Tint::Muted });
+                self.y -= 6.0;
+            }
+            Block::Table(rows) => self.table(rows, x, width),
+            Block::Image(Ok(hash)) if self.images.contains_key(hash) => {
+                let image = &self.images[hash];
+                let scale = (width / image.width as f32).min((PAGE_HEIGHT - 2.0 * MARGIN) * 0.6 / image.height as f32).min(1.0);
+                let (w, h) = (image.width as f32 * scale, image.height as f32 * scale);
+                self.ensure(h);
+                self.push(Item::Image { hash: hash.clone(), x, y: self.y - h, width: w, height: h });
+                self.y -= h + BODY_SIZE * 0.5;
+            }
+            Block::Image(Ok(_)) => {}
+            Block::Image(Err(alt)) => {
+                let text = format!("[image{}{}]", if alt.is_empty() { "" } else { ": " }, alt);
+                let style = Style { italic: true, tint: Tint::Muted, ..Style::PLAIN };
+                self.paragraph(&[Inline::Text { text, style, url: None }], x, width, BODY_SIZE);
+                self.y -= BODY_SIZE * 0.5;
+            }
+        }
+    }
+
+    /// A thin rule down the left of a quote or code block, across pages.
+    fn bar(&mut self, (start_page, start_y): (usize, f32), x: f32) {
+        for page in start_page..=self.page() {
+            let top = if page == start_page { start_y } else { PAGE_HEIGHT - MARGIN };
+            let bottom = if page == self.page() { self.y } else { MARGIN };
+            let item = Item::Line { from: (x, top), to: (x, bottom), width: 1.5, tint: Tint::Muted };
+            self.pages[page - 1].push(item);
+        }
+    }
+
+    fn table(&mut self, rows: &[Vec<Vec<Inline>>], x: f32, width: f32) {
+        let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+        if columns == 0 {
+            return;
+        }
+        let column_width = width / columns as f32;
+        let pad = 4.0;
+        let line_height = BODY_SIZE * LINE_HEIGHT;
+
+        for row in rows {
+            let cells: Vec<Vec<TextLine>> = row
+                .iter()
+                .map(|cell| {
+                    let tokens = self.tokens(cell);
+                    self.wrap(&tokens, column_width - 2.0 * pad, BODY_SIZE)
+                })
+                .collect();
+            let height = cells.iter().map(Vec::len).max().unwrap_or(1).max(1) as f32 * line_height + 2.0 * pad;
+            self.ensure(height);
+
+            let top = self.y;
+            for (column, lines) in cells.into_iter().enumerate() {
+                self.y = top - pad;
+                for line in lines {
+                    self.line(line, x + column as f32 * column_width + pad, BODY_SIZE);
+                }
+            }
+            self.y = top - height;
+
+            let tint = Tint::Muted;
+            self.push(Item::Line { from: (x, top), to: (x + width, top), width: 0.5, tint });
+            self.push(Item::Line { from: (x, self.y), to: (x + width, self.y), width: 0.5, tint });
+            for column in 0..=columns {
+                let cx = x + column as f32 * column_width;
+                self.push(Item::Line { from: (cx, top), to: (cx, self.y), width: 0.5, tint });
+            }
+        }
+        self.y -= BODY_SIZE * 0.5;
+    }
+
+    fn paragraph(&mut self, inlines: &[Inline], x: f32, width: f32, size: f32) {
+        let tokens = self.tokens(inlines);
+        for line in self.wrap(&tokens, width, size) {
+            self.line(line, x, size);
+        }
+    }
+
+    /// Splits text into words with HTML whitespace rules. A wiki link is
+    /// followed by where its target is, e.g. `(§2.1, p. 7)`.
+    fn tokens(&self, inlines: &[Inline]) -> Vec<Token> {
+        let mut tokens = Vec::new();
+        let mut word = Vec::new();
+        for inline in inlines {
+            match inline {
+                Inline::Text { text, style, url } => split_words(&mut tokens, &mut word, text, *style, url),
+                Inline::Ref(target) => {
+                    let text = format!(" (§{}, p. {})", self.numbers[*target], self.known[*target]);
+                    split_words(&mut tokens, &mut word, &text, Style { tint: Tint::Muted, ..Style::PLAIN }, &None);
+                }
+                Inline::Break => {
+                    if !word.is_empty() {
+                        tokens.push(Token::Word(std::mem::take(&mut word)));
+                    }
+                    while matches!(tokens.last(), Some(Token::Space(_))) {
+                        tokens.pop();
+                    }
+                    tokens.push(Token::Break);
+                }
+            }
+        }
+        if !word.is_empty() {
+            tokens.push(Token::Word(word));
+        }
+        while matches!(tokens.last(), Some(Token::Space(_))) {
+            tokens.pop();
+        }
+        tokens
+    }
+
+    /// Greedy line breaking. A word wider than the line is split between
+    /// characters.
+    fn wrap(&self, tokens: &[Token], width: f32, size: f32) -> Vec<TextLine> {
+        let mut lines = Vec::new();
+        let mut runs: Vec<Run> = Vec::new();
+        let mut cursor = 0.0;
+        let mut space = 0.0;
+
+        for token in tokens {
+            match token {
+                Token::Space(style) => {
+                    if !runs.is_empty() {
+                        space = self.fonts.width(style.face(), " ", size);
+                    }
+                }
+                Token::Break => {
+                    lines.push(TextLine { runs: std::mem::take(&mut runs) });
+                    cursor = 0.0;
+                    space = 0.0;
+                }
+                Token::Word(pieces) => {
+                    let word_width: f32 = pieces.iter().map(|(t, s, _)| self.fonts.width(s.face(), t, size)).sum();
+                    if cursor + space + word_width > width && !runs.is_empty() {
+                        lines.push(TextLine { runs: std::mem::take(&mut runs) });
+                        cursor = 0.0;
+                        space = 0.0;
+                    }
+                    for (text, style, url) in pieces {
+                        for chunk in self.split_to_fit(text, style.face(), size, width, cursor + space) {
+                            let w = self.fonts.width(style.face(), &chunk, size);
+                            if cursor + space + w > width && !runs.is_empty() {
+                                lines.push(TextLine { runs: std::mem::take(&mut runs) });
+                                cursor = 0.0;
+                                space = 0.0;
+                            }
+                            let x = cursor + space;
+                            match runs.last_mut() {
+                                Some(run) if run.style == *style && run.url == *url => {
+                                    if space > 0.0 {
+                                        run.text.push(' ');
+                                    }
+                                    run.text.push_str(&chunk);
+                                    run.width = x + w - run.x;
+                                }
+                                _ => runs.push(Run { x, width: w, text: chunk, style: *style, url: url.clone() }),
+                            }
+                            cursor = x + w;
+                            space = 0.0;
+                        }
+                    }
+                }
+            }
+        }
+        if !runs.is_empty() || lines.is_empty() {
+            lines.push(TextLine { runs });
+        }
+        lines
+    }
+
+    /// `text` as is when it fits on a line, otherwise cut into pieces that do
+    /// (the first one fitting after `used`).
+    fn split_to_fit(&self, text: &str, face: Face, size: f32, width: f32, used: f32) -> Vec<String> {
+        if self.fonts.width(face, text, size) <= width {
+            return vec![text.to_string()];
+        }
+        let mut chunks = Vec::new();
+        let mut chunk = String::new();
+        let mut available = (width - used).max(size);
+        for c in text.chars() {
+            chunk.push(c);
+            if self.fonts.width(face, &chunk, size) > available && chunk.chars().count() > 1 {
+                chunk.pop();
+                chunks.push(std::mem::take(&mut chunk));
+                chunk.push(c);
+                available = width;
+            }
+        }
+        chunks.push(chunk);
+        chunks
+    }
+
+    /// Places one line below the cursor and returns its baseline.
+    fn line(&mut self, line: TextLine, x: f32, size: f32) -> f32 {
+        let height = size * LINE_HEIGHT;
+        self.ensure(height);
+        let baseline = self.y - size * 1.05;
+        self.y -= height;
+
+        for run in line.runs {
+            let left = x + run.x;
+            let tint = run.style.tint;
+            if run.style.underline {
+                let y = baseline - size * 0.12;
+                self.push(Item::Line { from: (left, y), to: (left + run.width, y), width: size * 0.05, tint });
+            }
+            if run.style.strike {
+                let y = baseline + size * 0.3;
+                self.push(Item::Line { from: (left, y), to: (left + run.width, y), width: size * 0.05, tint });
+            }
+            if let Some(url) = run.url {
+                self.push(Item::Link { x: left, y: baseline - size * 0.25, width: run.width, height: size * 1.1, url });
+            }
+            self.push(Item::Text { x: left, y: baseline, face: run.style.face(), size, tint, text: run.text });
+        }
+        baseline
+    }
+}
+
+/// Adds `text` to the word being collected, ending it at whitespace.
+/// Whitespace collapses to one space, and none at the start of a line.
+fn split_words(tokens: &mut Vec<Token>, word: &mut Vec<(String, Style, Option<String>)>, text: &str, style: Style, url: &Option<String>) {
+    for c in text.chars() {
+        if c.is_whitespace() {
+            if !word.is_empty() {
+                tokens.push(Token::Word(std::mem::take(word)));
+            }
+            if !matches!(tokens.last(), Some(Token::Space(_)) | Some(Token::Break) | None) {
+                tokens.push(Token::Space(style));
+            }
+            continue;
+        }
+        match word.last_mut() {
+            Some((piece, s, u)) if *s == style && u == url => piece.push(c),
+            _ => word.push((c.to_string(), style, url.clone())),
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Output
+
+fn draw(
+    outline: &Outline,
+    numbers: &[String],
+    starts: &[usize],
+    pages: &[Vec<Item>],
+    images: &HashMap<String, DecodedImage>,
+    fonts: &Fonts
+) -> Result<Vec<u8>, (StatusCode, String)> {
+    let mm = |pt: f32| Mm::from(Pt(pt));
+    let (doc, first_page, first_layer) = PdfDocument::new(outline.title.as_str(), mm(PAGE_WIDTH), mm(PAGE_HEIGHT), "Page");
+
+    // Fonts are embedded whole, so only the faces actually used go in.
+    let used: HashSet<Face> = pages
+        .iter()
+        .flatten()
+        .filter_map(|item| match item {
+            Item::Text { face, .. } => Some(*face),
+            _ => None,
+        })
+        .chain([Face::Regular])
+        .collect();
+    let mut font_refs: HashMap<Face, IndirectFontRef> = HashMap::new();
+    for face in Face::ALL.into_iter().filter(|f| used.contains(f)) {
+        let font = doc.add_external_font(Cursor::new(fonts.face(face).as_slice())).map_err(export_error)?;
+        font_refs.insert(face, font);
+    }
+
+    let mut page_indexes = Vec::new();
+    for (number, items) in pages.iter().enumerate() {
+        let (page, layer) = if number == 0 {
+            (first_page, first_layer)
+        } else {
+            doc.add_page(mm(PAGE_WIDTH), mm(PAGE_HEIGHT), "Page")
+        };
+        page_indexes.push(page);
+        let layer = doc.get_page(page).get_layer(layer);
+        for item in items {
+            draw_item(&layer, item, &font_refs, images);
+        }
+
+        // Footer: the page number.
+        let label = (number + 1).to_string();
+        let x = (PAGE_WIDTH - fonts.width(Face::Regular, &label, 9.0)) / 2.0;
+        layer.set_fill_color(Tint::Muted.color());
+        layer.use_text(label, 9.0, mm(x), mm(MARGIN / 2.0), &font_refs[&Face::Regular]);
+    }
+
+    // The viewer's outline panel takes one entry per page, so it lists the
+    // top-level sections, each of which starts a page.
+    for &index in &outline.roots {
+        let title = format!("{} {}", numbers[index], outline.entries[index].tab.title);
+        doc.add_bookmark(title, page_indexes[starts[index] - 1]);
+    }
+
+    doc.save_to_bytes().map_err(export_error)
+}
+
+fn draw_item(layer: &PdfLayerReference, item: &Item, fonts: &HashMap<Face, IndirectFontRef>, images: &HashMap<String, DecodedImage>) {
+    let mm = |pt: f32| Mm::from(Pt(pt));
+    match item {
+        Item::Text { x, y, face, size, tint, text } => {
+            layer.set_fill_color(tint.color());
+            layer.use_text(text.as_str(), *size, mm(*x), mm(*y), &fonts[face]);
+        }
+        Item::Line { from, to, width, tint } => {
+            layer.set_outline_color(tint.color());
+            layer.set_outline_thickness(*width);
+            layer.add_line(Line {
+                points: vec![(Point::new(mm(from.0), mm(from.1)), false), (Point::new(mm(to.0), mm(to.1)), false)],
+                is_closed: false,
+            });
+        }
+        Item::Image { hash, x, y, width, height } => {
+            let image = &images[hash];
+            let xobject = ImageXObject {
+                width: Px(image.width as usize),
+                height: Px(image.height as usize),
+                color_space: ColorSpace::Rgb,
+                bits_per_component: ColorBits::Bit8,
+                interpolate: true,
+                image_data: image.rgb.clone(),
+                image_filter: None,
+                smask: None,
+                clipping_bbox: None,
+            };
+            // At 72 dpi a pixel is a point, so the scale is the size in points.
+            let transform = ImageTransform {
+                translate_x: Some(mm(*x)),
+                translate_y: Some(mm(*y)),
+                scale_x: Some(width / image.width as f32),
+                scale_y: Some(height / image.height as f32),
+                dpi: Some(72.0),
+                rotate: None,
+            };
+            Image::from(xobject).add_to_layer(layer.clone(), transform);
+        }
+        Item::Link { x, y, width, height, url } => {
+            layer.add_link_annotation(LinkAnnotation::new(
+                Rect::new(mm(*x), mm(*y), mm(x + width), mm(y + height)),
+                Some(BorderArray::Solid([0.0, 0.0, 0.0])),
+                Some(ColorArray::Transparent),
+                Actions::uri(url.clone()),
+                Some(HighlightingMode::Invert),
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::tab;
+    use crate::Tab;
+
+    fn outline(tabs: &[(&str, Option<&str>, &str)]) -> Outline {
+        let tabs: Vec<Tab> = tabs.iter().map(|(id, parent, content)| tab(id, *parent, content)).collect();
+        Outline::build(tabs, None, |_| true).unwrap()
+    }
+
+    /// Words as they are, spaces as `_` and breaks as `/`.
+    fn show(tokens: &[Token]) -> String {
+        tokens
+            .iter()
+            .map(|token| match token {
+                Token::Word(pieces) => pieces.iter().map(|(text, _, _)| text.as_str()).collect::<Vec<_>>().join("|"),
+                Token::Space(_) => "_".to_string(),
+                Token::Break => "/".to_string(),
+            })
+            .collect()
+    }
+
+    /// The fonts the export uses. Tests that lay out text fail rather than
+    /// pass untested where they aren't installed.
+    async fn fonts() -> Fonts {
+        match Fonts::load().await {
+            Ok(fonts) => fonts,
+            Err((_, message)) => panic!("{}; install fonts-dejavu-core to run the PDF layout tests", message),
+        }
+    }
+
+    #[test]
+    fn sections_are_numbered_by_position_in_the_outline() {
+        let outline = outline(&[("a", None, ""), ("a1", Some("a"), ""), ("a2", Some("a"), ""), ("a2x", Some("a2"), ""), ("b", None, "")]);
+        assert_eq!(section_numbers(&outline), ["1", "1.1", "1.2", "1.2.1", "2"]);
+    }
+
+    #[test]
+    fn whitespace_collapses_and_styles_split_a_word_into_pieces() {
+        let bold = Style { bold: true, ..Style::PLAIN };
+        let mut tokens = Vec::new();
+        let mut word = Vec::new();
+        split_words(&mut tokens, &mut word, "  one \n two", Style::PLAIN, &None);
+        split_words(&mut tokens, &mut word, "three  ", bold, &None);
+        split_words(&mut tokens, &mut word, "four", Style::PLAIN, &Some("https://x.org".to_string()));
+        assert_eq!(show(&tokens), "one_two|three_");
+        assert_eq!(word.len(), 1);
+    }
+
+    #[test]
+    fn content_parses_into_blocks_with_wiki_link_references() {
+        let outline = outline(&[("a", None, ""), ("b", None, "")]);
+        let hash = "c".repeat(64);
+        let html = format!(
+            "<p> <img src=\"/attachments/{}\"> </p><p>see <span data-tab-id=\"b\">B</span> and <span data-tab-id=\"z\">Z</span></p><div><hr>loose</div>",
+            hash
+        );
+        let blocks = parse(&html, &outline);
+        assert!(matches!(&blocks[0], Block::Image(Ok(h)) if *h == hash));
+        let Block::Paragraph(inlines) = &blocks[1] else { panic!("expected a paragraph") };
+        let refs: Vec<usize> = inlines.iter().filter_map(|i| if let Inline::Ref(target) = i { Some(*target) } else { None }).collect();
+        assert_eq!(refs, [1]);
+        assert!(matches!(blocks[2], Block::Rule));
+        assert!(matches!(&blocks[3], Block::Paragraph(_)));
+        assert_eq!(blocks.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn lines_wrap_within_the_measure() {
+        let fonts = fonts().await;
+        let images = HashMap::new();
+        let setter = Typesetter::new(&fonts, &images, &[], &[]);
+
+        let text = format!("{} {}", "word ".repeat(60), "x".repeat(200));
+        let tokens = setter.tokens(&[Inline::Text { text, style: Style::PLAIN, url: None }, Inline::Break]);
+        let lines = setter.wrap(&tokens, CONTENT_WIDTH, BODY_SIZE);
+        assert!(lines.len() >= 4, "{} lines", lines.len());
+        for line in &lines {
+            let end = line.runs.last().map_or(0.0, |run| run.x + run.width);
+            assert!(end <= CONTENT_WIDTH + 0.01, "line runs to {}", end);
+        }
+    }
+
+    #[tokio::test]
+    async fn cross_references_settle_on_the_target_page() {
+        let fonts = fonts().await;
+        let long = "<p>filler</p>".repeat(120);
+        let outline = outline(&[("a", None, "<p>see <span data-tab-id=\"b\">B</span></p>"), ("filler", Some("a"), &long), ("b", None, "")]);
+
+        let bytes = render(&outline, &[], &fonts).unwrap();
+        assert!(bytes.starts_with(b"%PDF"));
+
+        let numbers = section_numbers(&outline);
+        let content: Vec<Vec<Block>> = outline.entries.iter().map(|e| parse(&e.tab.content, &outline)).collect();
+        let images = HashMap::new();
+        let mut starts = vec![0; outline.entries.len()];
+        for _ in 0..4 {
+            let mut setter = Typesetter::new(&fonts, &images, &numbers, &starts);
+            setter.document(&outline, &content);
+            starts = setter.starts;
+        }
+        assert!(starts[2] > starts[1] + 1, "{:?}", starts);
+        let mut setter = Typesetter::new(&fonts, &images, &numbers, &starts);
+        setter.document(&outline, &content);
+        let printed = setter.pages.iter().flatten().any(|item| matches!(item, Item::Text { text, .. } if text.contains(&format!("p. {})", starts[2]))));
+        assert!(printed, "no reference to page {}", starts[2]);
+    }
+}
@@ -0,0 +1,266 @@
+//! A static, offline copy of the encyclopedia: one HTML page per tab plus an
+//! `index.html` per window that shows the Miller columns leading to it. It is
+//! served as a zip from `GET /export/site`, or written straight to a
+//! directory by `miller-backend export-site <dir> [--root <id>]`.
+//!
+//! Files sit where the Markdown export puts them: `Title.html` for a tab and
+//! `Title/index.html` for the window holding its children.
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::Response,
+};
+use std::path::Path;
+use std::sync::Arc;
+
+use super::html::{escape, rewrite, Syntax};
+use super::{assets, download, outline, zip, Asset, ExportParams, Layout, Links, Outline, ASSET_DIR};
+use crate::attachments::Attachments;
+use crate::auth::CurrentUser;
+use crate::store::TabStore;
+use crate::AppState;
+
+const STYLESHEET: &str = "\
+body { margin: 0; font-family: system-ui, sans-serif; background: #1e1e1e; color: #d4d4d4; }
+a { color: #4fc1ff; }
+nav.breadcrumbs { padding: 10px 20px; background: #252526; border-bottom: 1px solid #333; font-size: 14px; }
+nav.breadcrumbs span.sep { margin: 0 6px; color: #777; }
+main { max-width: 860px; margin: 0 auto; padding: 20px; }
+main.columns { max-width: none; display: flex; gap: 0; padding: 0; overflow-x: auto; }
+.column { min-width: 240px; max-width: 320px; border-right: 1px solid #333; min-height: calc(100vh - 42px); }
+.column ul { list-style: none; margin: 0; padding: 0; }
+.column li { display: flex; justify-content: space-between; border-bottom: 1px solid #2d2d2d; }
+.column li a { padding: 10px 14px; text-decoration: none; color: #d4d4d4; }
+.column li a.open { color: #777; }
+.column li.selected { background: #094771; }
+.wiki-link { color: #4fc1ff; }
+span.wiki-link { text-decoration: underline dotted; }
+img { max-width: 100%; }
+table { border-collapse: collapse; }
+td, th { border: 1px solid #444; padding: 4px 8px; }
+pre { background: #111; padding: 10px; overflow-x: auto; }
+blockquote { border-left: 3px solid #555; margin-left: 0; padding-left: 12px; }
+";
+
+pub async fn export_site(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Query(params): Query<ExportParams>
+) -> Result<Response, (StatusCode, String)> {
+    let outline = outline(&state, &user, params.root.as_deref()).await?;
+    let assets = assets(&state.attachments, &outline).await?;
+    let bytes = zip(&render(&outline, assets), ASSET_DIR)?;
+
+    println!("📦 {} exported {} tabs as a static site ({} bytes)", user.username, outline.entries.len(), bytes.len());
+    Ok(download(bytes, "application/zip", &format!("{} site", outline.title), "zip"))
+}
+
+/// `export-site <dir> [--root <id>]`. Run by whoever administers the server,
+/// so it sees every tab regardless of grants.
+pub async fn site_command(store: &Arc<dyn TabStore>, attachments: &Attachments, args: &[String]) -> Result<(), String> {
+    let usage = || "Usage: miller-backend export-site <dir> [--root <tab id>]".to_string();
+    let (dir, root) = match args {
+        [dir] => (dir, None),
+        [dir, flag, root] if flag == "--root" => (dir, Some(root.as_str())),
+        _ => return Err(usage()),
+    };
+
+    let tabs = store.list_tabs().await.map_err(|e| format!("Could not read tabs: {:?}", e))?;
+    let outline = Outline::build(tabs, root, |_| true).ok_or_else(|| format!("Tab {} not found", root.unwrap_or_default()))?;
+    let assets = assets(attachments, &outline).await.map_err(|(_, e)| e)?;
+
+    let files = render(&outline, assets);
+    for (path, bytes) in &files {
+        let path = Path::new(dir).join(path);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await.map_err(|e| format!("{}: {}", parent.display(), e))?;
+        }
+        tokio::fs::write(&path, bytes).await.map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+
+    println!("📦 Wrote {} tabs ({} files) to {}", outline.entries.len(), files.len(), dir);
+    Ok(())
+}
+
+/// Every file of the site, paths relative to its top directory.
+fn render(outline: &Outline, assets: Vec<Asset>) -> Vec<(String, Vec<u8>)> {
+    let stems = outline.file_stems(&["index", ASSET_DIR]);
+    let files = stems.iter().map(|stem| format!("{}.html", stem)).collect();
+    let layout = Layout::new(outline, files, &assets);
+    let site = Site { outline, stems: &stems, layout: &layout };
+
+    let mut out = vec![
+        ("style.css".to_string(), STYLESHEET.as_bytes().to_vec()),
+        ("index.html".to_string(), site.window_page(None).into_bytes()),
+    ];
+    for (index, entry) in outline.entries.iter().enumerate() {
+        out.push((layout.files[index].clone(), site.tab_page(index).into_bytes()));
+        if !entry.children.is_empty() {
+            out.push((site.window_file(Some(index)), site.window_page(Some(index)).into_bytes()));
+        }
+    }
+    out.extend(assets.into_iter().map(|a| (format!("{}/{}", ASSET_DIR, a.name), a.bytes)));
+    out
+}
+
+struct Site<'a> {
+    outline: &'a Outline,
+    stems: &'a [String],
+    layout: &'a Layout<'a>,
+}
+
+impl Site<'_> {
+    /// The index page of the window holding `owner`'s children; the top
+    /// window when `None`.
+    fn window_file(&self, owner: Option<usize>) -> String {
+        match owner {
+            Some(index) => format!("{}/index.html", self.stems[index]),
+            None => "index.html".to_string(),
+        }
+    }
+
+    /// `index` and its ancestors, top first.
+    fn lineage(&self, index: usize) -> Vec<usize> {
+        let mut chain = vec![index];
+        while let Some(parent) = self.outline.entries[chain[chain.len() - 1]].parent {
+            chain.push(parent);
+        }
+        chain.reverse();
+        chain
+    }
+
+    fn tab_page(&self, index: usize) -> String {
+        let file = &self.layout.files[index];
+        let links = self.layout.links(file);
+        let entry = &self.outline.entries[index];
+
+        let mut body = format!("<main>\n<h1>{}</h1>\n<article>{}</article>\n", escape(&entry.tab.title), rewrite(&entry.tab.content, &links, Syntax::Html));
+        if !entry.children.is_empty() {
+            body.push_str(&format!(
+                "<nav class=\"children\">\n<h2><a href=\"{}\">In this section</a></h2>\n<ul>\n",
+                links.file(&self.window_file(Some(index)))
+            ));
+            for &child in &entry.children {
+                body.push_str(&self.item(&links, child, false));
+            }
+            body.push_str("</ul>\n</nav>\n");
+        }
+        body.push_str("</main>");
+
+        let lineage = self.lineage(index);
+        page(&entry.tab.title, &links, &self.breadcrumbs(&links, &lineage[..lineage.len() - 1], Some(&entry.tab.title)), &body)
+    }
+
+    /// The Miller columns from the top window down to `owner`'s, with the
+    /// path through them highlighted.
+    fn window_page(&self, owner: Option<usize>) -> String {
+        let file = self.window_file(owner);
+        let links = self.layout.links(&file);
+        let path = owner.map(|index| self.lineage(index)).unwrap_or_default();
+
+        let mut body = String::from("<main class=\"columns\">\n");
+        let mut columns = vec![&self.outline.roots];
+        columns.extend(path.iter().map(|&index| &self.outline.entries[index].children));
+        for (depth, column) in columns.iter().enumerate() {
+            body.push_str("<div class=\"column\"><ul>\n");
+            for &index in column.iter() {
+                body.push_str(&self.item(&links, index, path.get(depth) == Some(&index)));
+            }
+            body.push_str("</ul></div>\n");
+        }
+        body.push_str("</main>");
+
+        let title = owner.map_or(self.outline.title.as_str(), |index| &self.outline.entries[index].tab.title);
+        page(title, &links, &self.breadcrumbs(&links, &path, None), &body)
+    }
+
+    /// One row of a column: the tab's page, plus a link into its own column
+    /// when it has children.
+    fn item(&self, links: &Links, index: usize, selected: bool) -> String {
+        let entry = &self.outline.entries[index];
+        let class = if selected { " class=\"selected\"" } else { "" };
+        let open = if entry.children.is_empty() {
+            String::new()
+        } else {
+            format!("<a class=\"open\" href=\"{}\" title=\"Open column\">›</a>", links.file(&self.window_file(Some(index))))
+        };
+        format!(
+            "<li{}><a href=\"{}\">{}</a>{}</li>\n",
+            class,
+            links.file(&self.layout.files[index]),
+            escape(&entry.tab.title),
+            open
+        )
+    }
+
+    /// Home, then each tab in `path` linked to its page, then the current
+    /// page's title if it is a tab.
+    fn breadcrumbs(&self, links: &Links, path: &[usize], current: Option<&str>) -> String {
+        let mut crumbs = vec![format!("<a href=\"{}\">{}</a>", links.file("index.html"), escape(&self.outline.title))];
+        for &index in path {
+            let href = links.file(&self.layout.files[index]);
+            crumbs.push(format!("<a href=\"{}\">{}</a>", href, escape(&self.outline.entries[index].tab.title)));
+        }
+        if let Some(title) = current {
+            crumbs.push(format!("<span>{}</span>", escape(title)));
+        }
+        format!("<nav class=\"breadcrumbs\">{}</nav>", crumbs.join("<span class=\"sep\">›</span>"))
+    }
+}
+
+fn page(title: &str, links: &Links, breadcrumbs: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <title>{}</title>\n<link rel=\"stylesheet\" href=\"{}\">\n</head>\n<body>\n{}\n{}\n</body>\n</html>\n",
+        escape(title),
+        links.file("style.css"),
+        breadcrumbs,
+        body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::tab;
+    use crate::Tab;
+
+    fn titled(id: &str, title: &str, parent_id: Option<&str>) -> Tab {
+        Tab { title: title.to_string(), ..tab(id, parent_id, "") }
+    }
+
+    fn site() -> Vec<(String, String)> {
+        let tabs = vec![titled("i", "Index", None), titled("a", "A", None), titled("b", "B", Some("a")), titled("c", "C", Some("b")), titled("z", "attachments", None)];
+        let outline = Outline::build(tabs, None, |_| true).unwrap();
+        render(&outline, Vec::new()).into_iter().map(|(path, bytes)| (path, String::from_utf8(bytes).unwrap())).collect()
+    }
+
+    #[test]
+    fn every_tab_gets_a_page_and_every_window_an_index() {
+        let paths: Vec<String> = site().into_iter().map(|(path, _)| path).collect();
+        assert_eq!(paths, ["style.css", "index.html", "Index (2).html", "A.html", "A/index.html", "A/B.html", "A/B/index.html", "A/B/C.html", "attachments (2).html"]);
+    }
+
+    #[test]
+    fn pages_link_back_up_through_breadcrumbs_and_columns() {
+        let files = site();
+        let file = |path: &str| files.iter().find(|(p, _)| p == path).map(|(_, body)| body.as_str()).unwrap();
+
+        let c = file("A/B/C.html");
+        assert!(c.contains("href=\"../../style.css\""), "{}", c);
+        assert!(c.contains(concat!(
+            "<nav class=\"breadcrumbs\"><a href=\"../../index.html\">Encyclopedia</a><span class=\"sep\">›</span>",
+            "<a href=\"../../A.html\">A</a><span class=\"sep\">›</span><a href=\"../B.html\">B</a>",
+            "<span class=\"sep\">›</span><span>C</span></nav>",
+        )), "{}", c);
+
+        // B's window shows the top column, A's children and B's, with the path selected.
+        let window = file("A/B/index.html");
+        assert_eq!(window.matches("<div class=\"column\">").count(), 3, "{}", window);
+        assert!(window.contains("<li class=\"selected\"><a href=\"../../A.html\">A</a><a class=\"open\" href=\"../index.html\""), "{}", window);
+        assert!(window.contains("<li><a href=\"C.html\">C</a></li>"), "{}", window);
+        assert!(file("A.html").contains("<h2><a href=\"A/index.html\">In this section</a></h2>"));
+    }
+}
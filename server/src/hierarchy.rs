@@ -0,0 +1,163 @@
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+
+use crate::acl::{self, Role};
+use crate::auth::CurrentUser;
+use crate::events::ChangeEvent;
+use crate::store::StoreError;
+use crate::{etag, store_error, AppState, Tab};
+
+#[derive(Deserialize)]
+pub struct MoveRequest {
+    /// New parent tab, or `null` for the root column.
+    parent_id: Option<String>,
+    /// Index among the new siblings; appended at the end when omitted.
+    position: Option<usize>,
+}
+
+/// The UI calls the top-level column `root`; in storage those rows have no parent.
+pub fn window_parent(window_id: &str) -> Option<&str> {
+    (window_id != "root").then_some(window_id)
+}
+
+pub fn cycle_error(id: &str, parent_id: &str) -> StoreError {
+    StoreError::Invalid(format!(
+        "Cannot place {} under {}: the parent is the tab itself or one of its descendants",
+        id, parent_id
+    ))
+}
+
+/// Checks that `order` is a permutation of the live tabs in a column.
+pub fn check_order(current: &HashSet<String>, order: &[String]) -> Result<(), StoreError> {
+    let requested: HashSet<&String> = order.iter().collect();
+    if requested.len() != order.len() {
+        return Err(StoreError::Invalid("Order contains duplicate ids".to_string()));
+    }
+    let current_refs: HashSet<&String> = current.iter().collect();
+    if requested != current_refs {
+        let mut missing: Vec<&&String> = current_refs.difference(&requested).collect();
+        let mut unknown: Vec<&&String> = requested.difference(&current_refs).collect();
+        missing.sort();
+        unknown.sort();
+        return Err(StoreError::Invalid(format!(
+            "Order must list every tab in the window exactly once (missing: {:?}, not in window: {:?})",
+            missing, unknown
+        )));
+    }
+    Ok(())
+}
+
+/// Inserts `id` into an ordered column at `position` (clamped to the end).
+pub fn splice(mut siblings: Vec<String>, id: &str, position: Option<usize>) -> Vec<String> {
+    let index = position.unwrap_or(siblings.len()).min(siblings.len());
+    siblings.insert(index, id.to_string());
+    siblings
+}
+
+/// `root_id` and everything below it, parents before children and each column
+/// in the order `tabs` lists it. Empty when `root_id` isn't among `tabs`.
+pub fn subtree(tabs: Vec<Tab>, root_id: &str) -> Vec<Tab> {
+    let mut children: HashMap<Option<String>, Vec<Tab>> = HashMap::new();
+    let mut root = None;
+    for tab in tabs {
+        if tab.id == root_id {
+            root = Some(tab);
+        } else {
+            children.entry(tab.parent_id.clone()).or_default().push(tab);
+        }
+    }
+
+    let mut ordered = Vec::new();
+    let mut stack: Vec<Tab> = root.into_iter().collect();
+    while let Some(tab) = stack.pop() {
+        if let Some(mut below) = children.remove(&Some(tab.id.clone())) {
+            below.reverse();
+            stack.extend(below);
+        }
+        ordered.push(tab);
+    }
+    ordered
+}
+
+pub async fn move_tab(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<String>,
+    Json(req): Json<MoveRequest>
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let index = acl::load(&state).await?;
+    acl::require(&index, &id, &user, Role::Editor)?;
+    acl::require_parent(&index, req.parent_id.as_deref(), &user)?;
+
+    let tab = state.store
+        .move_tab(&id, req.parent_id.as_deref(), req.position)
+        .await
+        .map_err(|e| store_error("Move", e))?;
+
+    println!("🚚 Moved {} under {:?}", id, req.parent_id);
+    let version = tab.version.unwrap_or_default();
+    state.events.publish(ChangeEvent::Moved { id, parent_id: tab.parent_id.clone(), version }).await;
+    Ok(([(header::ETAG, etag(version))], Json(tab)))
+}
+
+/// Persists a hand-curated order for one column. The body must list every live
+/// tab in the window exactly once.
+pub async fn reorder_window(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Path(window_id): Path<String>,
+    Json(order): Json<Vec<String>>
+) -> Result<StatusCode, (StatusCode, String)> {
+    acl::require_parent(&acl::load(&state).await?, window_parent(&window_id), &user)?;
+
+    state.store
+        .reorder_window(window_parent(&window_id), &order)
+        .await
+        .map_err(|e| store_error("Reorder", e))?;
+
+    println!("↕️ Reordered {} tabs in window {}", order.len(), window_id);
+    state.events.publish(ChangeEvent::Reordered { parent_id: window_parent(&window_id).map(str::to_string) }).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn the_root_window_has_no_parent() {
+        assert_eq!(window_parent("root"), None);
+        assert_eq!(window_parent("abc"), Some("abc"));
+    }
+
+    #[test]
+    fn an_order_must_be_a_permutation_of_the_column() {
+        let current: HashSet<String> = ids(&["a", "b", "c"]).into_iter().collect();
+        assert!(check_order(&current, &ids(&["c", "a", "b"])).is_ok());
+
+        for order in [ids(&["a", "b"]), ids(&["a", "b", "c", "d"]), ids(&["a", "a", "b", "c"]), ids(&["a", "b", "d"])] {
+            assert!(matches!(check_order(&current, &order), Err(StoreError::Invalid(_))), "{:?}", order);
+        }
+        let Err(StoreError::Invalid(message)) = check_order(&current, &ids(&["a", "b", "d"])) else { panic!("expected Invalid") };
+        assert!(message.contains("missing: [\"c\"]") && message.contains("not in window: [\"d\"]"), "{}", message);
+    }
+
+    #[test]
+    fn splicing_clamps_to_the_end() {
+        assert_eq!(splice(ids(&["a", "b"]), "x", Some(0)), ["x", "a", "b"]);
+        assert_eq!(splice(ids(&["a", "b"]), "x", Some(1)), ["a", "x", "b"]);
+        assert_eq!(splice(ids(&["a", "b"]), "x", Some(7)), ["a", "b", "x"]);
+        assert_eq!(splice(ids(&["a", "b"]), "x", None), ["a", "b", "x"]);
+        assert_eq!(splice(Vec::new(), "x", None), ["x"]);
+    }
+}
@@ -0,0 +1,292 @@
+//! `POST /import/json`: the file the UI's JSON export downloads, a flat list
+//! walked in pre-order where `depth` says how far down each tab sits.
+//!
+//! By default the tabs keep their ids and the import is refused if any of
+//! them already exist. `regenerate_ids=true` stores a fresh copy instead, with
+//! wiki links between the imported tabs following it. `merge=true` updates
+//! tabs that are already there: matched by id, or by title among the
+//! existing children of the same parent when ids are regenerated.
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+
+use super::{apply, new_tab_id, require_target, ImportResponse};
+use crate::acl;
+use crate::auth::CurrentUser;
+use crate::links::retarget_links;
+use crate::{now_millis, store_error, AppState, Tab};
+
+/// Tabs the file names by id but that are already there are listed up to
+/// this many in the error.
+const LISTED_CLASHES: usize = 5;
+
+/// One entry of the export. Its `fromParent` (the parent's title) is only
+/// there for people reading the file; `depth` and the order place the tab.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportedTab {
+    id: String,
+    title: String,
+    #[serde(default)]
+    content: String,
+    #[serde(default)]
+    depth: usize,
+    created_at: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct ImportParams {
+    /// Tab to import under; the file's top-level tabs go to the root column
+    /// when omitted.
+    parent: Option<String>,
+    #[serde(default)]
+    regenerate_ids: bool,
+    #[serde(default)]
+    merge: bool,
+}
+
+pub async fn import_json(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Query(params): Query<ImportParams>,
+    Json(items): Json<Vec<ExportedTab>>
+) -> Result<Json<ImportResponse>, (StatusCode, String)> {
+    if items.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "Nothing to import".to_string()));
+    }
+    let parents = parents_from_depths(&items)?;
+
+    let index = acl::load(&state).await?;
+    require_target(&index, params.parent.as_deref(), &user)?;
+
+    // Existing columns by parent, for matching titles when merging a copy.
+    let mut columns: HashMap<Option<String>, Vec<(String, String)>> = HashMap::new();
+    if params.merge && params.regenerate_ids {
+        for node in state.store.list_tree().await.map_err(|e| store_error("Import", e))? {
+            columns.entry(node.parent_id).or_default().push((node.title, node.id));
+        }
+    }
+
+    let mut ids: Vec<String> = Vec::with_capacity(items.len());
+    let mut parent_ids: Vec<Option<String>> = Vec::with_capacity(items.len());
+    for (item, parent) in items.iter().zip(&parents) {
+        let kept = item.id.trim().to_string();
+        let mut parent_id = match parent {
+            Some(p) => Some(ids[*p].clone()),
+            None => params.parent.clone(),
+        };
+        let id = if !params.regenerate_ids {
+            kept
+        } else if let Some(found) = claim_by_title(&mut columns, &parent_id, &item.title) {
+            found
+        } else {
+            new_tab_id(&index)
+        };
+        // A top-level tab merged back in stays where it is unless a parent
+        // was asked for.
+        if parent.is_none() && params.parent.is_none() && params.merge {
+            if let Some(node) = index.nodes.get(&id).filter(|node| node.live) {
+                parent_id = node.parent_id.clone();
+            }
+        }
+        ids.push(id);
+        parent_ids.push(parent_id);
+    }
+
+    if !params.regenerate_ids {
+        check_ids(&ids, |id| params.merge || !index.nodes.contains_key(id))?;
+    }
+
+    let renamed: HashMap<String, String> = items
+        .iter()
+        .zip(&ids)
+        .filter(|(item, id)| item.id.trim() != id.as_str())
+        .map(|(item, id)| (item.id.trim().to_string(), id.clone()))
+        .collect();
+    let now = now_millis();
+    let tabs = items
+        .into_iter()
+        .zip(ids.into_iter().zip(parent_ids))
+        .map(|(item, (id, parent_id))| Tab {
+            content: if renamed.is_empty() { item.content } else { retarget_links(&item.content, &renamed) },
+            title: item.title,
+            child_window_id: Some(id.clone()),
+            parent_id,
+            created_at: item.created_at.unwrap_or(now),
+            version: None,
+            id,
+        })
+        .collect();
+
+    Ok(Json(apply(&state, &user, &index, tabs).await?))
+}
+
+/// The parent of each item, by index, from the depths of a pre-order walk.
+fn parents_from_depths(items: &[ExportedTab]) -> Result<Vec<Option<usize>>, (StatusCode, String)> {
+    let mut path: Vec<usize> = Vec::new();
+    let mut parents = Vec::with_capacity(items.len());
+    for (index, item) in items.iter().enumerate() {
+        if item.depth > path.len() {
+            let message = match index {
+                0 => format!("The first entry ({}) must have depth 0", item.title),
+                _ => format!("Entry {} ({}) is at depth {}, more than one below the entry before it", index, item.title, item.depth),
+            };
+            return Err((StatusCode::BAD_REQUEST, message));
+        }
+        path.truncate(item.depth);
+        parents.push(path.last().copied());
+        path.push(index);
+    }
+    Ok(parents)
+}
+
+/// Takes the id of an existing tab titled `title` under `parent_id`, so no
+/// two imported tabs merge into the same one.
+fn claim_by_title(columns: &mut HashMap<Option<String>, Vec<(String, String)>>, parent_id: &Option<String>, title: &str) -> Option<String> {
+    let column = columns.get_mut(parent_id)?;
+    let position = column.iter().position(|(existing, _)| existing.trim().eq_ignore_ascii_case(title.trim()))?;
+    Some(column.remove(position).1)
+}
+
+/// Kept ids must be present, unique within the file, and (unless merging)
+/// not taken by tabs already stored, including ones in the trash.
+fn check_ids(ids: &[String], available: impl Fn(&str) -> bool) -> Result<(), (StatusCode, String)> {
+    let mut seen = HashSet::new();
+    for id in ids {
+        if id.is_empty() {
+            return Err((StatusCode::BAD_REQUEST, "Every entry needs an id unless regenerate_ids is set".to_string()));
+        }
+        if !seen.insert(id.as_str()) {
+            return Err((StatusCode::BAD_REQUEST, format!("Tab {} appears more than once in the file", id)));
+        }
+    }
+
+    let taken: Vec<&str> = ids.iter().map(String::as_str).filter(|id| !available(id)).collect();
+    if taken.is_empty() {
+        return Ok(());
+    }
+    let listed = taken.iter().take(LISTED_CLASHES).copied().collect::<Vec<_>>().join(", ");
+    let more = taken.len().saturating_sub(LISTED_CLASHES);
+    Err((
+        StatusCode::CONFLICT,
+        format!(
+            "{} tabs in the file already exist ({}{}); import with merge=true to update them or regenerate_ids=true to add a copy",
+            taken.len(),
+            listed,
+            if more > 0 { format!(" and {} more", more) } else { String::new() }
+        ),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acl::Role;
+    use crate::auth::User;
+    use crate::testing::{state, stores, tab, user};
+
+    fn item(id: &str, title: &str, depth: usize, content: &str) -> ExportedTab {
+        ExportedTab { id: id.to_string(), title: title.to_string(), content: content.to_string(), depth, created_at: None }
+    }
+
+    fn params(parent: Option<&str>, regenerate_ids: bool, merge: bool) -> Query<ImportParams> {
+        Query(ImportParams { parent: parent.map(str::to_string), regenerate_ids, merge })
+    }
+
+    async fn import(state: &AppState, user: &User, params: Query<ImportParams>, items: Vec<ExportedTab>) -> Result<ImportResponse, StatusCode> {
+        import_json(State(state.clone()), CurrentUser(user.clone()), params, Json(items)).await.map(|Json(r)| r).map_err(|(status, _)| status)
+    }
+
+    #[test]
+    fn depths_give_each_entry_its_parent() {
+        let items = [item("a", "A", 0, ""), item("b", "B", 1, ""), item("c", "C", 2, ""), item("d", "D", 1, ""), item("e", "E", 0, "")];
+        assert_eq!(parents_from_depths(&items).unwrap(), [None, Some(0), Some(1), Some(0), None]);
+
+        for items in [vec![item("a", "A", 1, "")], vec![item("a", "A", 0, ""), item("b", "B", 2, "")]] {
+            assert_eq!(parents_from_depths(&items).err().map(|(status, _)| status), Some(StatusCode::BAD_REQUEST));
+        }
+    }
+
+    #[test]
+    fn kept_ids_must_be_present_unique_and_free() {
+        let ids = |ids: &[&str]| ids.iter().map(|id| id.to_string()).collect::<Vec<_>>();
+        let status = |result: Result<(), (StatusCode, String)>| result.err().map(|(status, _)| status);
+        assert_eq!(status(check_ids(&ids(&["a", "b"]), |_| true)), None);
+        assert_eq!(status(check_ids(&ids(&["a", ""]), |_| true)), Some(StatusCode::BAD_REQUEST));
+        assert_eq!(status(check_ids(&ids(&["a", "a"]), |_| true)), Some(StatusCode::BAD_REQUEST));
+
+        let many = ids(&["a", "b", "c", "d", "e", "f", "g"]);
+        let Err((status, message)) = check_ids(&many, |id| id == "a") else { panic!("expected a conflict") };
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert!(message.starts_with("6 tabs in the file already exist (b, c, d, e, f and 1 more)"), "{}", message);
+    }
+
+    #[test]
+    fn each_existing_tab_is_claimed_by_title_only_once() {
+        let mut columns: HashMap<Option<String>, Vec<(String, String)>> = HashMap::new();
+        columns.insert(None, vec![("Notes".to_string(), "n1".to_string()), ("notes ".to_string(), "n2".to_string())]);
+        assert_eq!(claim_by_title(&mut columns, &None, " NOTES").as_deref(), Some("n1"));
+        assert_eq!(claim_by_title(&mut columns, &None, "Notes").as_deref(), Some("n2"));
+        assert_eq!(claim_by_title(&mut columns, &None, "Notes"), None);
+        assert_eq!(claim_by_title(&mut columns, &Some("p".to_string()), "Notes"), None);
+    }
+
+    #[tokio::test]
+    async fn files_import_as_is_as_a_copy_or_merged() {
+        for (name, store) in stores().await {
+            let ann = user(&store, "ann").await;
+            let state = state(store.clone());
+            let file = || vec![
+                item("a", "A", 0, "<p><span data-tab-id=\"b\" class=\"wiki-link\">B</span></p>"),
+                item("b", "B", 1, "<p>b<script>x</script></p>"),
+            ];
+
+            let kept = import(&state, &ann, params(None, false, false), file()).await.unwrap();
+            assert_eq!((kept.created, kept.updated, kept.ids.clone()), (2, 0, vec!["a".to_string(), "b".to_string()]), "{}", name);
+            assert_eq!(store.get_tab("b").await.unwrap().unwrap().content, "<p>b</p>", "{}", name);
+            assert_eq!(import(&state, &ann, params(None, false, false), file()).await.err(), Some(StatusCode::CONFLICT), "{}", name);
+
+            // A copy gets fresh ids, and its links follow it.
+            let copy = import(&state, &ann, params(Some("a"), true, false), file()).await.unwrap();
+            let (top, below) = (&copy.ids[0], &copy.ids[1]);
+            assert!(top.starts_with("tab-") && below != "b", "{}", name);
+            let stored = store.get_tab(top).await.unwrap().unwrap();
+            assert_eq!(stored.parent_id.as_deref(), Some("a"), "{}", name);
+            assert!(stored.content.contains(&format!("data-tab-id=\"{}\"", below)), "{}: {}", name, stored.content);
+
+            // Merging the copy again by title lands on the same tabs.
+            let mut edited = file();
+            edited[1].content = "<p>edited</p>".to_string();
+            let merged = import(&state, &ann, params(Some("a"), true, true), edited).await.unwrap();
+            assert_eq!((merged.created, merged.updated, &merged.ids), (0, 2, &copy.ids), "{}", name);
+            assert_eq!(store.get_tab(below).await.unwrap().unwrap().content, "<p>edited</p>", "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn imports_need_edit_access_where_they_land() {
+        for (name, store) in stores().await {
+            let ann = user(&store, "ann").await;
+            let bob = user(&store, "bob").await;
+            store.save_tab(&tab("mine", None, ""), None).await.unwrap();
+            store.save_tab(&tab("read", None, ""), None).await.unwrap();
+            store.grant_access("mine", &ann.id, Role::Owner, 1).await.unwrap();
+            store.grant_access("read", &ann.id, Role::Owner, 1).await.unwrap();
+            store.grant_access("read", &bob.id, Role::Viewer, 1).await.unwrap();
+            let state = state(store.clone());
+
+            let file = || vec![item("x", "X", 0, "")];
+            assert_eq!(import(&state, &bob, params(Some("mine"), false, false), file()).await.err(), Some(StatusCode::NOT_FOUND), "{}", name);
+            assert_eq!(import(&state, &bob, params(Some("read"), false, false), file()).await.err(), Some(StatusCode::FORBIDDEN), "{}", name);
+            // Merging over a tab bob can only read is refused as well.
+            let over = vec![item("read", "Read", 0, "<p>overwritten</p>")];
+            assert_eq!(import(&state, &bob, params(None, false, true), over).await.err(), Some(StatusCode::FORBIDDEN), "{}", name);
+            assert!(store.get_tab("x").await.unwrap().is_none(), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,104 @@
+//! Bulk imports. Each format is turned into a list of tabs, parents before
+//! children, and [`write`] stores them in one transaction, so a file that
+//! fails halfway leaves nothing behind.
+
+use axum::http::StatusCode;
+use serde::Serialize;
+use std::sync::Arc;
+
+use crate::acl::{self, AclIndex, Role};
+use crate::attachments::Attachments;
+use crate::auth::{random_hex, User};
+use crate::events::{ChangeEvent, Events};
+use crate::store::TabStore;
+use crate::{sanitize, store_error, AppState, Tab};
+
+mod json;
+mod vault;
+
+pub use json::import_json;
+pub use vault::{import_vault, vault_command};
+
+const DEFAULT_MAX_IMPORT_MB: usize = 100;
+
+#[derive(Serialize)]
+pub struct ImportResponse {
+    pub created: usize,
+    pub updated: usize,
+    /// The stored id of every imported tab, in the order they were imported.
+    pub ids: Vec<String>,
+}
+
+/// Largest accepted import file, from `IMPORT_MAX_MB`.
+pub fn max_import_bytes() -> usize {
+    std::env::var("IMPORT_MAX_MB")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_MAX_IMPORT_MB)
+        * 1024
+        * 1024
+}
+
+/// A fresh id in the style the UI gives new tabs.
+pub fn new_tab_id(index: &AclIndex) -> String {
+    loop {
+        let id = format!("tab-{}", random_hex(6));
+        if !index.nodes.contains_key(&id) {
+            return id;
+        }
+    }
+}
+
+/// Checks that imported tabs may go under `parent_id`: it must be a live tab
+/// the caller can edit, or the root column.
+pub fn require_target(index: &AclIndex, parent_id: Option<&str>, user: &User) -> Result<(), (StatusCode, String)> {
+    if let Some(parent_id) = parent_id {
+        if !index.nodes.get(parent_id).is_some_and(|node| node.live) {
+            return Err((StatusCode::NOT_FOUND, format!("Tab {} not found", parent_id)));
+        }
+    }
+    acl::require_parent(index, parent_id, user)
+}
+
+/// Stores `tabs` (parents first) with the same checks as saving them one at a
+/// time.
+pub async fn apply(state: &AppState, user: &User, index: &AclIndex, mut tabs: Vec<Tab>) -> Result<ImportResponse, (StatusCode, String)> {
+    let mut updated = 0;
+    for tab in &tabs {
+        let stored_parent = index.nodes.get(&tab.id).map(|node| node.parent_id.as_deref());
+        if stored_parent.is_some() {
+            acl::require(index, &tab.id, user, Role::Editor)?;
+            updated += 1;
+        }
+        if stored_parent != Some(tab.parent_id.as_deref()) {
+            acl::require_parent(index, tab.parent_id.as_deref(), user)?;
+        }
+    }
+
+    write(&state.store, &state.attachments, &state.events, &mut tabs).await?;
+
+    println!("📥 {} imported {} tabs ({} updated)", user.username, tabs.len(), updated);
+    Ok(ImportResponse {
+        created: tabs.len() - updated,
+        updated,
+        ids: tabs.into_iter().map(|tab| tab.id).collect(),
+    })
+}
+
+/// Cleans `tabs` as a save would and writes them in one transaction, then
+/// tells connected clients to reload.
+pub async fn write(store: &Arc<dyn TabStore>, attachments: &Attachments, events: &Events, tabs: &mut [Tab]) -> Result<(), (StatusCode, String)> {
+    for tab in tabs.iter_mut() {
+        tab.content = sanitize::sanitize(&tab.content).html;
+        let (content, _) = attachments
+            .extract_inline(&tab.content)
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Attachment Error: {}", e)))?;
+        tab.content = content;
+    }
+
+    store.import_tabs(tabs).await.map_err(|e| store_error("Import", e))?;
+    // One reload beats thousands of per-tab events overflowing the feed.
+    events.publish(ChangeEvent::Resync).await;
+    Ok(())
+}
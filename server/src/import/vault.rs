@@ -0,0 +1,588 @@
+//! `POST /import/vault`: a zip of Markdown notes and folders, such as an
+//! Obsidian vault, added as new tabs. The same runs from the command line as
+//! `miller-backend import-vault <vault.zip> [--parent <tab id>]`.
+//!
+//! Every folder holding notes becomes a tab with its contents below it, and
+//! takes the text of a note named like it (`Topic.md` next to `Topic/`, or
+//! `Topic/Topic.md`), as Obsidian's folder notes do. `[[Wiki Links]]` and
+//! links to other notes resolve by title or path; images are moved into the
+//! attachment store. Hidden folders like `.obsidian/` are skipped.
+
+use axum::{
+    body::Bytes,
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use pulldown_cmark::{html, Event, LinkType, Options, Parser, Tag, TagEnd};
+use serde::Deserialize;
+use std::collections::{BTreeSet, HashMap};
+use std::io::{Cursor, Read};
+use std::path::Component;
+use std::sync::Arc;
+use zip::ZipArchive;
+
+use super::{apply, max_import_bytes, new_tab_id, require_target, write, ImportResponse};
+use crate::acl::{self, AclIndex};
+use crate::attachments::Attachments;
+use crate::auth::CurrentUser;
+use crate::events::Events;
+use crate::store::TabStore;
+use crate::{now_millis, AppState, Tab};
+
+const NOTE_EXTENSIONS: [&str; 2] = ["md", "markdown"];
+
+#[derive(Deserialize)]
+pub struct VaultParams {
+    /// Tab to import under; the vault's top level goes to the root column
+    /// when omitted.
+    parent: Option<String>,
+}
+
+/// Takes the archive as the request body.
+pub async fn import_vault(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Query(params): Query<VaultParams>,
+    body: Bytes
+) -> Result<Json<ImportResponse>, (StatusCode, String)> {
+    let index = acl::load(&state).await?;
+    require_target(&index, params.parent.as_deref(), &user)?;
+    let vault = Vault::read(&body).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+    let tabs = vault.into_tabs(&state.attachments, params.parent.as_deref(), &index).await?;
+    Ok(Json(apply(&state, &user, &index, tabs).await?))
+}
+
+/// `import-vault <vault.zip> [--parent <id>]`. Run by whoever administers
+/// the server, so it may import anywhere.
+pub async fn vault_command(store: &Arc<dyn TabStore>, attachments: &Attachments, events: &Events, args: &[String]) -> Result<(), String> {
+    let usage = || "Usage: miller-backend import-vault <vault.zip> [--parent <tab id>]".to_string();
+    let (path, parent) = match args {
+        [path] => (path, None),
+        [path, flag, parent] if flag == "--parent" => (path, Some(parent.as_str())),
+        _ => return Err(usage()),
+    };
+
+    let bytes = tokio::fs::read(path).await.map_err(|e| format!("{}: {}", path, e))?;
+    let vault = Vault::read(&bytes)?;
+    let index = store.acl_index().await.map_err(|e| format!("Could not read tabs: {:?}", e))?;
+    if let Some(parent) = parent {
+        if !index.nodes.get(parent).is_some_and(|node| node.live) {
+            return Err(format!("Tab {} not found", parent));
+        }
+    }
+
+    let mut tabs = vault.into_tabs(attachments, parent, &index).await.map_err(|(_, e)| e)?;
+    write(store, attachments, events, &mut tabs).await.map_err(|(_, e)| e)?;
+
+    println!("📥 Imported {} tabs from {}", tabs.len(), path);
+    Ok(())
+}
+
+/// A folder or note that becomes a tab.
+struct Node {
+    title: String,
+    parent: Option<usize>,
+    /// The note supplying the tab's text, if any.
+    note: Option<String>,
+}
+
+struct Vault {
+    /// Every file by its `/`-separated path.
+    files: HashMap<String, Vec<u8>>,
+    /// Pre-order, parents before children.
+    nodes: Vec<Node>,
+    /// Lowercased titles, for `[[Title]]`; the shallowest tab wins.
+    by_title: HashMap<String, usize>,
+    /// Lowercased note paths without extension, for `[[Folder/Title]]` and
+    /// `[text](Folder/Title.md)`.
+    by_path: HashMap<String, usize>,
+    /// Lowercased file names, for `![[image.png]]` from anywhere.
+    by_name: HashMap<String, String>,
+}
+
+impl Vault {
+    fn read(bytes: &[u8]) -> Result<Self, String> {
+        let files = unzip(bytes)?;
+
+        let notes: Vec<&String> = files.keys().filter(|path| is_note(path)).collect();
+        if notes.is_empty() {
+            return Err("The archive has no Markdown notes".to_string());
+        }
+        // Folders that hold notes somewhere below them; image folders don't
+        // become tabs.
+        let folders: BTreeSet<String> = notes.iter().flat_map(|path| ancestors(path)).collect();
+
+        // Items of each folder ("" for the top), and the note each folder
+        // takes its text from.
+        let mut items: HashMap<String, Vec<(String, Item)>> = HashMap::new();
+        let mut folder_notes: HashMap<String, String> = HashMap::new();
+        for folder in &folders {
+            let (dir, name) = split(folder);
+            let beside = notes.iter().find(|n| split(n).0 == dir && stem(split(n).1).eq_ignore_ascii_case(name));
+            let inside = notes.iter().find(|n| split(n).0 == folder && stem(split(n).1).eq_ignore_ascii_case(name));
+            if let Some(note) = beside.or(inside) {
+                folder_notes.insert(folder.clone(), note.to_string());
+            }
+            items.entry(dir.to_string()).or_default().push((name.to_string(), Item::Folder(folder.clone())));
+        }
+        let taken: BTreeSet<&str> = folder_notes.values().map(String::as_str).collect();
+        for note in notes.iter().filter(|n| !taken.contains(n.as_str())) {
+            let (dir, name) = split(note);
+            items.entry(dir.to_string()).or_default().push((stem(name).to_string(), Item::Note(note.to_string())));
+        }
+
+        let mut vault = Vault { files, nodes: Vec::new(), by_title: HashMap::new(), by_path: HashMap::new(), by_name: HashMap::new() };
+        vault.add_items("", None, &mut items, &folder_notes);
+
+        let mut by_depth: Vec<(usize, usize)> = (0..vault.nodes.len()).map(|i| (vault.depth(i), i)).collect();
+        by_depth.sort();
+        for (_, i) in by_depth {
+            vault.by_title.entry(vault.nodes[i].title.to_lowercase()).or_insert(i);
+            if let Some(note) = &vault.nodes[i].note {
+                vault.by_path.insert(strip_extension(note).to_lowercase(), i);
+            }
+        }
+        let mut paths: Vec<&String> = vault.files.keys().collect();
+        paths.sort_by_key(|path| (path.matches('/').count(), path.as_str()));
+        for path in paths {
+            vault.by_name.entry(split(path).1.to_lowercase()).or_insert_with(|| path.clone());
+        }
+        Ok(vault)
+    }
+
+    /// Adds the items of `dir` under `parent`, sorted by title as Obsidian
+    /// lists them, each folder followed by its own contents.
+    fn add_items(&mut self, dir: &str, parent: Option<usize>, items: &mut HashMap<String, Vec<(String, Item)>>, folder_notes: &HashMap<String, String>) {
+        let Some(mut here) = items.remove(dir) else { return };
+        here.sort_by_key(|(title, _)| title.to_lowercase());
+        for (title, item) in here {
+            let index = self.nodes.len();
+            match item {
+                Item::Note(path) => self.nodes.push(Node { title, parent, note: Some(path) }),
+                Item::Folder(path) => {
+                    self.nodes.push(Node { title, parent, note: folder_notes.get(&path).cloned() });
+                    self.add_items(&path, Some(index), items, folder_notes);
+                }
+            }
+        }
+    }
+
+    fn depth(&self, mut index: usize) -> usize {
+        let mut depth = 0;
+        while let Some(parent) = self.nodes[index].parent {
+            depth += 1;
+            index = parent;
+        }
+        depth
+    }
+
+    /// Stores the images the notes embed, then converts every note.
+    async fn into_tabs(self, attachments: &Attachments, parent_id: Option<&str>, index: &AclIndex) -> Result<Vec<Tab>, (StatusCode, String)> {
+        let ids: Vec<String> = self.nodes.iter().map(|_| new_tab_id(index)).collect();
+
+        let mut images: HashMap<String, String> = HashMap::new();
+        for node in &self.nodes {
+            let Some(note) = &node.note else { continue };
+            for path in self.embedded_images(note) {
+                if images.contains_key(&path) {
+                    continue;
+                }
+                let content_type = image_type(&path).expect("only images are collected");
+                let stored = attachments
+                    .put(&self.files[&path], content_type)
+                    .await
+                    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Attachment Error: {}", e)))?;
+                images.insert(path, stored.url);
+            }
+        }
+
+        let now = now_millis();
+        Ok(self
+            .nodes
+            .iter()
+            .zip(&ids)
+            .map(|(node, id)| Tab {
+                id: id.clone(),
+                title: node.title.clone(),
+                content: node.note.as_ref().map(|note| self.to_html(note, &ids, &images)).unwrap_or_default(),
+                child_window_id: Some(id.clone()),
+                parent_id: node.parent.map(|p| ids[p].clone()).or(parent_id.map(str::to_string)),
+                created_at: now,
+                version: None,
+            })
+            .collect())
+    }
+
+    fn markdown(&self, note: &str) -> String {
+        String::from_utf8_lossy(&self.files[note]).into_owned()
+    }
+
+    /// Vault paths of the images `note` shows.
+    fn embedded_images(&self, note: &str) -> Vec<String> {
+        let markdown = self.markdown(note);
+        Parser::new_ext(&markdown, options())
+            .filter_map(|event| match event {
+                Event::Start(Tag::Image { dest_url, .. }) => self.resolve_file(note, &dest_url),
+                _ => None,
+            })
+            .filter(|path| image_type(path).is_some())
+            .collect()
+    }
+
+    /// Renders a note as the HTML the editor produces, with links to other
+    /// notes as wiki links and images pointing at `images`.
+    fn to_html(&self, note: &str, ids: &[String], images: &HashMap<String, String>) -> String {
+        let markdown = self.markdown(note);
+        let mut events: Vec<Event> = Vec::new();
+        // How each open link or image is closed.
+        let mut closing: Vec<Option<Event>> = Vec::new();
+        let mut in_metadata = false;
+        let mut replaced_alt = false;
+        let mut bare_wiki_link = false;
+
+        for event in Parser::new_ext(&markdown, options()) {
+            match event {
+                // Front matter is Obsidian's, not content.
+                Event::Start(Tag::MetadataBlock(_)) => in_metadata = true,
+                Event::End(TagEnd::MetadataBlock(_)) => in_metadata = false,
+                _ if in_metadata => {}
+
+                Event::Start(Tag::Link { link_type, dest_url, title, id }) => {
+                    let is_wiki = matches!(link_type, LinkType::WikiLink { .. });
+                    bare_wiki_link = matches!(link_type, LinkType::WikiLink { has_pothole: false });
+                    if is_wiki || !has_scheme(&dest_url) {
+                        match self.resolve_note(note, &dest_url) {
+                            Some(target) => {
+                                events.push(Event::InlineHtml(wiki_link_start(&ids[target]).into()));
+                                closing.push(Some(Event::InlineHtml("</span>".into())));
+                            }
+                            // Left as text: a note that doesn't exist yet,
+                            // or a file that wasn't imported.
+                            None if is_wiki || !dest_url.starts_with('#') => closing.push(None),
+                            None => {
+                                events.push(Event::Start(Tag::Link { link_type, dest_url, title, id }));
+                                closing.push(Some(Event::End(TagEnd::Link)));
+                            }
+                        }
+                    } else {
+                        events.push(Event::Start(Tag::Link { link_type, dest_url, title, id }));
+                        closing.push(Some(Event::End(TagEnd::Link)));
+                    }
+                }
+                Event::End(TagEnd::Link) => {
+                    bare_wiki_link = false;
+                    events.extend(closing.pop().flatten());
+                }
+                // `[[Note#Heading]]` reads as the note's name.
+                Event::Text(text) if bare_wiki_link => {
+                    events.push(Event::Text(text.split(['#', '^']).next().unwrap_or_default().to_string().into()));
+                }
+
+                // The editor marks strikethrough as `<s>`, which the sanitizer keeps.
+                Event::Start(Tag::Strikethrough) => events.push(Event::InlineHtml("<s>".into())),
+                Event::End(TagEnd::Strikethrough) => events.push(Event::InlineHtml("</s>".into())),
+
+                Event::Start(Tag::Image { link_type, dest_url, title, id }) => {
+                    let stored = self.resolve_file(note, &dest_url).and_then(|path| images.get(&path));
+                    if let Some(url) = stored {
+                        events.push(Event::Start(Tag::Image { link_type, dest_url: url.clone().into(), title, id }));
+                        // `![[photo.png|300]]` gives a display width, not a description.
+                        if matches!(link_type, LinkType::WikiLink { .. }) {
+                            events.push(Event::Text(stem(split(&dest_url).1).to_string().into()));
+                            replaced_alt = true;
+                        }
+                        closing.push(Some(Event::End(TagEnd::Image)));
+                    } else if has_scheme(&dest_url) {
+                        events.push(Event::Start(Tag::Image { link_type, dest_url, title, id }));
+                        closing.push(Some(Event::End(TagEnd::Image)));
+                    } else if let Some(target) = self.resolve_note(note, &dest_url) {
+                        // `![[Other note]]` embeds a note; link to it instead.
+                        events.push(Event::InlineHtml(wiki_link_start(&ids[target]).into()));
+                        closing.push(Some(Event::InlineHtml("</span>".into())));
+                    } else {
+                        closing.push(None);
+                    }
+                }
+                Event::End(TagEnd::Image) => {
+                    replaced_alt = false;
+                    events.extend(closing.pop().flatten());
+                }
+                _ if replaced_alt => {}
+
+                event => events.push(event),
+            }
+        }
+
+        let mut out = String::new();
+        html::push_html(&mut out, events.into_iter());
+        out
+    }
+
+    /// The note a link points at: by path relative to `from` or to the vault,
+    /// then by title. Headings and block references (`#…`, `^…`) are
+    /// dropped, as tabs have no anchors.
+    fn resolve_note(&self, from: &str, target: &str) -> Option<usize> {
+        let target = percent_decode(target);
+        let target = target.split(['#', '^']).next().unwrap_or_default().trim();
+        if target.is_empty() {
+            return None;
+        }
+        let target = strip_extension(target);
+        join(split(from).0, target)
+            .into_iter()
+            .chain([target.to_string()])
+            .find_map(|path| self.by_path.get(&path.to_lowercase()))
+            .or_else(|| self.by_title.get(&split(target).1.to_lowercase()))
+            .copied()
+    }
+
+    /// The file a link or embed points at: relative to `from`, then from the
+    /// top of the vault, then by name anywhere in it.
+    fn resolve_file(&self, from: &str, target: &str) -> Option<String> {
+        if has_scheme(target) {
+            return None;
+        }
+        let target = percent_decode(target);
+        let target = target.split('|').next().unwrap_or_default().trim();
+        join(split(from).0, target)
+            .into_iter()
+            .chain([target.trim_start_matches('/').to_string()])
+            .find(|path| self.files.contains_key(path))
+            .or_else(|| self.by_name.get(&split(target).1.to_lowercase()).cloned())
+    }
+}
+
+enum Item {
+    Note(String),
+    Folder(String),
+}
+
+fn options() -> Options {
+    Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_WIKILINKS | Options::ENABLE_YAML_STYLE_METADATA_BLOCKS
+}
+
+fn wiki_link_start(id: &str) -> String {
+    format!("<span data-tab-id=\"{}\" class=\"wiki-link\">", id)
+}
+
+/// Every file in the archive, minus hidden ones (`.obsidian/`, `.trash/`)
+/// and macOS resource forks. A vault zipped as its folder is unwrapped.
+fn unzip(bytes: &[u8]) -> Result<HashMap<String, Vec<u8>>, String> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes)).map_err(|e| format!("Not a zip archive: {}", e))?;
+    let limit = max_import_bytes() as u64;
+    let mut total = 0;
+    let mut files = Vec::new();
+    for i in 0..archive.len() {
+        let file = archive.by_index(i).map_err(|e| format!("Unreadable archive: {}", e))?;
+        let Some(path) = file.enclosed_name().filter(|_| file.is_file()) else { continue };
+        let parts: Vec<String> = path
+            .components()
+            .filter_map(|c| match c {
+                Component::Normal(part) => Some(part.to_string_lossy().into_owned()),
+                _ => None,
+            })
+            .collect();
+        if parts.is_empty() || parts.iter().any(|p| p.starts_with('.') || p == "__MACOSX") {
+            continue;
+        }
+
+        // Sizes in the directory can lie; count what actually comes out.
+        let mut data = Vec::new();
+        file.take(limit - total + 1).read_to_end(&mut data).map_err(|e| format!("Unreadable archive: {}", e))?;
+        total += data.len() as u64;
+        if total > limit {
+            return Err(format!("The archive unpacks to more than {} MB", limit / 1024 / 1024));
+        }
+        files.push((parts.join("/"), data));
+    }
+
+    let top = files.first().and_then(|(path, _)| path.split_once('/')).map(|(top, _)| format!("{}/", top));
+    if let Some(top) = top.filter(|top| files.iter().all(|(path, _)| path.starts_with(top.as_str()))) {
+        for (path, _) in &mut files {
+            path.drain(..top.len());
+        }
+    }
+    Ok(files.into_iter().collect())
+}
+
+fn is_note(path: &str) -> bool {
+    path.rsplit_once('.').is_some_and(|(_, ext)| NOTE_EXTENSIONS.iter().any(|n| ext.eq_ignore_ascii_case(n)))
+}
+
+fn image_type(path: &str) -> Option<&'static str> {
+    let (_, ext) = path.rsplit_once('.')?;
+    match ext.to_lowercase().as_str() {
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "gif" => Some("image/gif"),
+        "webp" => Some("image/webp"),
+        "svg" => Some("image/svg+xml"),
+        _ => None,
+    }
+}
+
+/// `(folder, name)`; the folder is `""` at the top of the vault.
+fn split(path: &str) -> (&str, &str) {
+    path.rsplit_once('/').unwrap_or(("", path))
+}
+
+fn stem(name: &str) -> &str {
+    name.rsplit_once('.').map_or(name, |(stem, _)| stem)
+}
+
+/// Drops a note extension; anything else (`Chapter 1.2`) is part of the name.
+fn strip_extension(path: &str) -> &str {
+    if is_note(path) { stem(path) } else { path }
+}
+
+/// The folders above `path`, e.g. `a` and `a/b` for `a/b/c.md`.
+fn ancestors(path: &str) -> Vec<String> {
+    let parts: Vec<&str> = path.split('/').collect();
+    (1..parts.len()).map(|n| parts[..n].join("/")).collect()
+}
+
+/// `target` resolved against `dir`; `None` if it climbs out of the vault.
+fn join(dir: &str, target: &str) -> Option<String> {
+    let mut parts: Vec<&str> = dir.split('/').filter(|p| !p.is_empty()).collect();
+    for part in target.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop()?;
+            }
+            _ => parts.push(part),
+        }
+    }
+    Some(parts.join("/"))
+}
+
+fn has_scheme(url: &str) -> bool {
+    url.split_once(':').is_some_and(|(scheme, _)| {
+        !scheme.is_empty() && scheme.chars().all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+    })
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = (bytes[i] == b'%').then(|| text.get(i + 1..i + 3)).flatten();
+        match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+            Some(byte) => {
+                out.push(byte);
+                i += 3;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acl::Role;
+    use crate::auth::User;
+    use crate::testing::{state, stores, tab, user};
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    fn zip_of(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        for (path, text) in files {
+            zip.start_file(*path, SimpleFileOptions::default()).unwrap();
+            zip.write_all(text.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    fn vault() -> Vault {
+        Vault::read(&zip_of(&[
+            ("Vault/.obsidian/app.json", "{}"),
+            ("Vault/Topic.md", "---\ntags: [a]\n---\n# Topic\n\nSee [[Child]], [[child#Part|the part]] and [[Missing]].\n"),
+            ("Vault/Topic/Child.md", "[Up](../Topic.md), [[Inbox/Later/Child]], ~~old~~ and [web](https://example.com).\n\n![[pic.png]]\n"),
+            ("Vault/Topic/img/pic.png", "png"),
+            ("Vault/Inbox/Inbox.md", "Inbox text"),
+            ("Vault/Inbox/Later/Child.md", "Deeper"),
+        ]))
+        .unwrap()
+    }
+
+    #[test]
+    fn paths_join_decode_and_spot_schemes() {
+        assert_eq!(join("a/b", "../c/./d").as_deref(), Some("a/c/d"));
+        assert_eq!(join("", "/c").as_deref(), Some("c"));
+        assert_eq!(join("a", "../../c"), None);
+        assert_eq!(percent_decode("My%20Note%2x%"), "My Note%2x%");
+        assert_eq!(percent_decode("caf%C3%A9"), "café");
+        assert!(has_scheme("https://example.com") && has_scheme("mailto:a@b.c"));
+        assert!(!has_scheme("Notes/a.md") && !has_scheme(":x") && !has_scheme("a b:c"));
+        assert_eq!(strip_extension("Chapter 1.2"), "Chapter 1.2");
+        assert_eq!(strip_extension("a/Note.MD"), "a/Note");
+        assert_eq!(ancestors("a/b/c.md"), ["a", "a/b"]);
+    }
+
+    #[test]
+    fn folders_become_tabs_that_take_their_folder_notes() {
+        let vault = vault();
+        assert!(vault.files.keys().all(|path| !path.starts_with(".obsidian") && !path.starts_with("Vault/")));
+        let nodes: Vec<(&str, Option<usize>, Option<&str>)> = vault.nodes.iter().map(|n| (n.title.as_str(), n.parent, n.note.as_deref())).collect();
+        assert_eq!(
+            nodes,
+            [
+                ("Inbox", None, Some("Inbox/Inbox.md")),
+                ("Later", Some(0), None),
+                ("Child", Some(1), Some("Inbox/Later/Child.md")),
+                ("Topic", None, Some("Topic.md")),
+                ("Child", Some(3), Some("Topic/Child.md")),
+            ]
+        );
+
+        assert!(Vault::read(&zip_of(&[("a.txt", "")])).is_err());
+        assert!(Vault::read(b"not a zip").is_err());
+    }
+
+    #[test]
+    fn links_resolve_by_path_then_the_shallowest_title() {
+        let vault = vault();
+        let ids: Vec<String> = (0..vault.nodes.len()).map(|i| format!("n{}", i)).collect();
+        assert_eq!(
+            vault.to_html("Topic.md", &ids, &HashMap::new()),
+            "<h1>Topic</h1>\n<p>See <span data-tab-id=\"n4\" class=\"wiki-link\">Child</span>, \
+             <span data-tab-id=\"n4\" class=\"wiki-link\">the part</span> and Missing.</p>\n"
+        );
+
+        assert_eq!(vault.embedded_images("Topic/Child.md"), ["Topic/img/pic.png"]);
+        let images: HashMap<String, String> = [("Topic/img/pic.png".to_string(), "http://test/pic".to_string())].into_iter().collect();
+        assert_eq!(
+            vault.to_html("Topic/Child.md", &ids, &images),
+            "<p><span data-tab-id=\"n3\" class=\"wiki-link\">Up</span>, <span data-tab-id=\"n2\" class=\"wiki-link\">Inbox/Later/Child</span>, \
+             <s>old</s> and <a href=\"https://example.com\">web</a>.</p>\n<p><img src=\"http://test/pic\" alt=\"pic\" /></p>\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn the_target_is_checked_before_the_archive_is_read() {
+        for (name, store) in stores().await {
+            let ann = user(&store, "ann").await;
+            let bob = user(&store, "bob").await;
+            store.save_tab(&tab("mine", None, ""), None).await.unwrap();
+            store.grant_access("mine", &ann.id, Role::Owner, 1).await.unwrap();
+            let state = state(store);
+
+            let import = |user: &User, parent: &str| {
+                let params = Query(VaultParams { parent: Some(parent.to_string()) });
+                import_vault(State(state.clone()), CurrentUser(user.clone()), params, Bytes::from_static(b"not a zip"))
+            };
+            assert_eq!(import(&bob, "mine").await.err().map(|(status, _)| status), Some(StatusCode::NOT_FOUND), "{}", name);
+            assert_eq!(import(&ann, "mine").await.err().map(|(status, _)| status), Some(StatusCode::BAD_REQUEST), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use scraper::{Html, Selector};
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::acl::{self, Role};
+use crate::auth::CurrentUser;
+use crate::{store_error, AppState};
+
+/// A `<span data-tab-id="...">` produced by the tiptap `WikiLink` mark.
+#[derive(Clone)]
+pub struct WikiLink {
+    pub target_id: String,
+    pub text: String,
+}
+
+#[derive(Serialize)]
+pub struct LinkEntry {
+    /// The other end of the link: the target for outgoing links, the source for backlinks.
+    pub id: String,
+    /// `None` when the tab no longer exists.
+    pub title: Option<String>,
+    pub text: String,
+}
+
+#[derive(Serialize)]
+pub struct BrokenLink {
+    pub target_id: String,
+    pub text: String,
+}
+
+#[derive(Serialize)]
+pub struct BrokenLinkReport {
+    pub id: String,
+    pub title: String,
+    pub parent_id: Option<String>,
+    pub links: Vec<BrokenLink>,
+}
+
+pub fn extract_links(html: &str) -> Vec<WikiLink> {
+    let selector = Selector::parse("span[data-tab-id]").expect("static selector");
+    Html::parse_fragment(html)
+        .select(&selector)
+        .filter_map(|el| {
+            let target_id = el.value().attr("data-tab-id")?.trim();
+            if target_id.is_empty() {
+                return None;
+            }
+            Some(WikiLink {
+                target_id: target_id.to_string(),
+                text: el.text().collect::<String>(),
+            })
+        })
+        .collect()
+}
+
+/// Points wiki links in sanitized HTML at new ids, for tabs that were copied
+/// under fresh ids. Links to tabs not in `ids` are left as they are.
+pub fn retarget_links(html: &str, ids: &HashMap<String, String>) -> String {
+    const MARKER: &str = "data-tab-id=\"";
+
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find(MARKER) {
+        let value_start = start + MARKER.len();
+        let Some(len) = rest[value_start..].find('"') else { break };
+        let target = &rest[value_start..value_start + len];
+
+        out.push_str(&rest[..value_start]);
+        out.push_str(ids.get(target.trim()).map_or(target, String::as_str));
+        rest = &rest[value_start + len..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Groups `(source id, title, parent, dead link)` rows, already sorted by
+/// source, into one report entry per source tab.
+pub fn group_broken_links(
+    rows: impl IntoIterator<Item = (String, String, Option<String>, BrokenLink)>,
+) -> Vec<BrokenLinkReport> {
+    let mut report: Vec<BrokenLinkReport> = Vec::new();
+    for (id, title, parent_id, link) in rows {
+        match report.last_mut() {
+            Some(entry) if entry.id == id => entry.links.push(link),
+            _ => report.push(BrokenLinkReport { id, title, parent_id, links: vec![link] }),
+        }
+    }
+    report
+}
+
+pub async fn get_links(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<String>
+) -> Result<Json<Vec<LinkEntry>>, (StatusCode, String)> {
+    let index = acl::load(&state).await?;
+    acl::require(&index, &id, &user, Role::Viewer)?;
+    let mut links = state.store.links(&id).await.map_err(|e| store_error("Links", e))?;
+    links.retain(|link| index.can(&link.id, &user.id, Role::Viewer));
+    Ok(Json(links))
+}
+
+pub async fn get_backlinks(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<String>
+) -> Result<Json<Vec<LinkEntry>>, (StatusCode, String)> {
+    let index = acl::load(&state).await?;
+    acl::require(&index, &id, &user, Role::Viewer)?;
+    let mut backlinks = state.store.backlinks(&id).await.map_err(|e| store_error("Backlinks", e))?;
+    backlinks.retain(|link| index.can(&link.id, &user.id, Role::Viewer));
+    Ok(Json(backlinks))
+}
+
+/// Every tab that links to a tab which no longer exists (or sits in the trash),
+/// with the dead links in document order.
+pub async fn broken_links_report(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser
+) -> Result<Json<Vec<BrokenLinkReport>>, (StatusCode, String)> {
+    let index = acl::load(&state).await?;
+    let mut report = state.store.broken_links().await.map_err(|e| store_error("Broken Links", e))?;
+    report.retain(|entry| index.can(&entry.id, &user.id, Role::Viewer));
+    Ok(Json(report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wiki_links_are_read_with_their_text_and_blank_targets_skipped() {
+        let html = r#"<p>See <span data-tab-id="a" class="wiki-link">Alpha <b>bold</b></span>,
+            <span data-tab-id=" ">nothing</span> and <span data-tab-id=" b ">B</span>.</p>"#;
+        let links: Vec<(String, String)> = extract_links(html).into_iter().map(|l| (l.target_id, l.text)).collect();
+        assert_eq!(links, [("a".to_string(), "Alpha bold".to_string()), ("b".to_string(), "B".to_string())]);
+    }
+
+    #[test]
+    fn broken_link_rows_group_by_source() {
+        let row = |id: &str, target: &str| {
+            (id.to_string(), id.to_uppercase(), None, BrokenLink { target_id: target.to_string(), text: String::new() })
+        };
+        let report = group_broken_links([row("a", "x"), row("a", "y"), row("b", "x")]);
+        let grouped: Vec<(&str, usize)> = report.iter().map(|entry| (entry.id.as_str(), entry.links.len())).collect();
+        assert_eq!(grouped, [("a", 2), ("b", 1)]);
+    }
+
+    #[test]
+    fn retargeting_rewrites_only_the_renamed_links() {
+        let ids: HashMap<String, String> = [("a".to_string(), "tab-1".to_string())].into_iter().collect();
+        let html = r#"<span data-tab-id=" a " class="wiki-link">A</span><span data-tab-id="b">B</span><p data-tab-id="a"#;
+        assert_eq!(
+            retarget_links(html, &ids),
+            r#"<span data-tab-id="tab-1" class="wiki-link">A</span><span data-tab-id="b">B</span><p data-tab-id="a"#,
+        );
+    }
+}
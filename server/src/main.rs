@@ -1,13 +1,37 @@
 use axum::{
-    routing::{get, post, delete}, // Added delete here
-    Router, Json, extract::{State, Path}, 
-    http::StatusCode
+    routing::{delete, get, post, put},
+    Router, Json, extract::{State, Path}, middleware,
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    response::{IntoResponse, Response}
 };
 use axum::extract::DefaultBodyLimit;
-use sqlx::{postgres::PgPoolOptions, Pool, Postgres, Row};
 use serde::{Deserialize, Serialize};
-use std::time::Duration;
-use tower_http::cors::{Any, CorsLayer};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+use acl::Role;
+use auth::CurrentUser;
+use events::{ChangeEvent, Events};
+use store::{SaveOutcome, StoreError, TabChange, TabStore};
+
+mod acl;
+mod attachments;
+mod auth;
+mod collab;
+mod events;
+mod export;
+mod hierarchy;
+mod import;
+mod links;
+mod revisions;
+mod sanitize;
+mod search;
+mod share;
+mod store;
+#[cfg(test)]
+mod testing;
+mod trash;
 
 #[derive(Serialize, Deserialize, Clone)]
 struct Tab {
@@ -17,6 +41,45 @@ struct Tab {
     child_window_id: Option<String>,
     parent_id: Option<String>,
     created_at: i64,
+    // Bumped by the server on every change. Clients echo it back on save (or
+    // send it as If-Match) so a stale copy can't overwrite a newer one.
+    #[serde(default)]
+    version: Option<i64>,
+}
+
+// Content-free view of a tab, enough to lay out the Miller columns.
+#[derive(Serialize, Clone)]
+struct TreeNode {
+    id: String,
+    title: String,
+    parent_id: Option<String>,
+    child_window_id: Option<String>,
+    created_at: i64,
+}
+
+// A tab as listed to one user, with what they may do to it.
+#[derive(Serialize)]
+struct ListedTab {
+    #[serde(flatten)]
+    tab: Tab,
+    role: Role,
+}
+
+#[derive(Serialize)]
+struct SaveResponse {
+    id: String,
+    version: i64,
+    // What the sanitizer removed from the submitted content; empty for
+    // anything the editor itself produced.
+    stripped: Vec<sanitize::Stripped>,
+}
+
+#[derive(Clone)]
+struct AppState {
+    store: Arc<dyn TabStore>,
+    events: Events,
+    collab: collab::Rooms,
+    attachments: attachments::Attachments,
 }
 
 #[tokio::main]
@@ -25,128 +88,380 @@ async fn main() {
     
     let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
 
-    let pool = PgPoolOptions::new()
-        .max_connections(5)
-        .connect(&database_url)
+    // The backend is chosen by the URL scheme (postgres://, sqlite:, memory://)
+    // and brings its own migrations. A database we can't bring up to date is
+    // not one we should be writing to.
+    let store = store::connect(&database_url)
         .await
-        .expect("Failed to connect to Postgres");
-
-    println!("✅ Connected and verifying table...");
-
-    let _ = sqlx::query(
-        "CREATE TABLE IF NOT EXISTS tabs (
-            id TEXT PRIMARY KEY,
-            title TEXT NOT NULL,
-            content TEXT DEFAULT '',
-            child_window_id TEXT,
-            parent_id TEXT,
-            created_at BIGINT NOT NULL
-        );"
-    )
-    .execute(&pool)
-    .await;
-    
-    let _ = sqlx::query("CREATE INDEX IF NOT EXISTS idx_tabs_parent_id ON tabs(parent_id);")
-    .execute(&pool)
-    .await;
+        .expect("Failed to open the database");
+
+    println!("✅ Connected and migrated");
+
+    let events = events::connect(&database_url)
+        .await
+        .expect("Failed to start the change feed");
+
+    match store.backfill_links().await {
+        Ok(0) => {}
+        Ok(n) => println!("🔗 Indexed wiki links for {} existing tabs", n),
+        Err(e) => eprintln!("❌ Link backfill failed: {:?}", e),
+    }
 
+    let attachments = attachments::Attachments::from_env();
+
+    // Admin commands run against the same database and attachment store as
+    // the server, then exit instead of serving.
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(command) = args.first() {
+        let result = match command.as_str() {
+            "export-site" => export::site_command(&store, &attachments, &args[1..]).await,
+            "import-vault" => import::vault_command(&store, &attachments, &events, &args[1..]).await,
+            _ => Err(format!(
+                "Unknown command {}. Usage: miller-backend [export-site <dir> [--root <tab id>] | import-vault <vault.zip> [--parent <tab id>]]",
+                command
+            )),
+        };
+        if let Err(e) = result {
+            eprintln!("❌ {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // Sessions ride on a cookie, so the UI's origin has to be named
+    // explicitly; a wildcard can't be combined with credentials.
+    let origins = std::env::var("CORS_ORIGINS").unwrap_or_else(|_| "http://localhost:5173".to_string());
+    let origins: Vec<HeaderValue> = origins
+        .split(',')
+        .filter_map(|origin| HeaderValue::from_str(origin.trim()).ok())
+        .collect();
     let cors = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_headers(Any)
-        .allow_methods(Any);
+        .allow_origin(AllowOrigin::list(origins))
+        .allow_credentials(true)
+        .allow_headers([header::CONTENT_TYPE, header::AUTHORIZATION, header::IF_MATCH])
+        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
+        .expose_headers([header::ETAG]);
+
+    let state = AppState {
+        store,
+        events,
+        collab: collab::Rooms::default(),
+        attachments,
+    };
 
-    // Added the DELETE route with a path parameter :id
-    let app = Router::new()
+    let public = Router::new()
         .route("/health", get(|| async { "Backend is healthy!" }))
+        .route("/auth/register", post(auth::register))
+        .route("/auth/login", post(auth::login))
+        .route("/auth/logout", post(auth::logout))
+        .route("/shared/:token", get(share::get_shared))
+        .route("/attachments/:hash", get(attachments::download))
+        .route("/attachments/:hash/thumbnail", get(attachments::thumbnail));
+
+    // Everything else needs a session cookie or an API token.
+    let protected = Router::new()
+        .route("/auth/me", get(auth::me))
+        .route("/auth/tokens", get(auth::list_api_tokens).post(auth::create_api_token))
+        .route("/auth/tokens/:id", delete(auth::revoke_api_token))
         .route("/tabs", get(get_tabs).post(save_tab))
-        .route("/tabs/:id", delete(delete_tab))
+        .route("/attachments", post(attachments::upload).layer(DefaultBodyLimit::max(attachments::max_upload_bytes())))
+        .route("/tree", get(get_tree))
+        .route("/tabs/:id", get(get_tab).delete(delete_tab))
+        .route("/tabs/:id/move", post(hierarchy::move_tab))
+        .route("/tabs/:id/acl", get(acl::get_acl))
+        .route("/tabs/:id/acl/:username", put(acl::grant_access).delete(acl::revoke_access))
+        .route("/tabs/:id/share", get(share::list_shares).post(share::create_share))
+        .route("/tabs/:id/share/:share_id", delete(share::revoke_share))
+        .route("/tabs/:id/links", get(links::get_links))
+        .route("/tabs/:id/backlinks", get(links::get_backlinks))
+        .route("/trash", get(trash::list_trash))
+        .route("/trash/purge", post(trash::purge_trash))
+        .route("/trash/:id/restore", post(trash::restore_from_trash))
+        .route("/windows/:id/order", put(hierarchy::reorder_window))
+        .route("/collab/:id", get(collab::collaborate))
+        .route("/events", get(events::stream_events))
+        .route("/search", get(search::search))
+        .route("/reports/broken-links", get(links::broken_links_report))
+        .route("/export/markdown", get(export::export_markdown))
+        .route("/export/site", get(export::export_site))
+        .route("/export/epub", get(export::export_epub))
+        .route("/export/pdf", get(export::export_pdf))
+        .route("/import/json", post(import::import_json).layer(DefaultBodyLimit::max(import::max_import_bytes())))
+        .route("/import/vault", post(import::import_vault).layer(DefaultBodyLimit::max(import::max_import_bytes())))
+        .route("/tabs/:id/revisions", get(revisions::list_revisions))
+        .route("/tabs/:id/revisions/:rev", get(revisions::get_revision))
+        .route("/tabs/:id/revisions/:rev/restore", post(revisions::restore_revision))
+        .route_layer(middleware::from_extractor_with_state::<auth::CurrentUser, _>(state.clone()));
+
+    let app = public
+        .merge(protected)
         .layer(DefaultBodyLimit::max(10 * 1024 * 1024)) // Allows up to 10MB
         .layer(cors)
-        .with_state(pool);
+        .with_state(state);
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:8080").await.unwrap();
     println!("🚀 Server running on 0.0.0.0:8080");
     axum::serve(listener, app).await.unwrap();
 }
 
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+fn etag(version: i64) -> HeaderValue {
+    HeaderValue::from_str(&format!("\"{}\"", version)).expect("version is always a valid header value")
+}
+
+/// Reads the version a client expects to overwrite from `If-Match`. `*` (or no
+/// header at all) means the client doesn't care which version it replaces.
+fn if_match_version(headers: &HeaderMap) -> Result<Option<i64>, (StatusCode, String)> {
+    let Some(value) = headers.get(header::IF_MATCH) else {
+        return Ok(None);
+    };
+    let raw = value.to_str().unwrap_or_default().trim();
+    if raw == "*" {
+        return Ok(None);
+    }
+    raw.trim_start_matches("W/")
+        .trim_matches('"')
+        .parse()
+        .map(Some)
+        .map_err(|_| (StatusCode::BAD_REQUEST, format!("Invalid If-Match header: {}", raw)))
+}
+
+fn store_error(context: &str, e: StoreError) -> (StatusCode, String) {
+    match e {
+        StoreError::NotFound(msg) => (StatusCode::NOT_FOUND, msg),
+        StoreError::Gone(msg) => (StatusCode::GONE, msg),
+        StoreError::Invalid(msg) => (StatusCode::UNPROCESSABLE_ENTITY, msg),
+        StoreError::Conflict(msg) => (StatusCode::CONFLICT, msg),
+        StoreError::Database(e) => {
+            eprintln!("❌ DB Error ({}): {:?}", context, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, format!("DB Error: {}", e))
+        }
+    }
+}
+
 // --- HANDLERS ---
 
-async fn get_tabs(State(pool): State<Pool<Postgres>>) -> Json<Vec<Tab>> {
-    let rows = sqlx::query("SELECT id, title, content, child_window_id, parent_id, created_at FROM tabs")
-        .fetch_all(&pool)
-        .await
-        .unwrap_or_default();
+async fn get_tabs(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser
+) -> Result<Json<Vec<ListedTab>>, (StatusCode, String)> {
+    let index = acl::load(&state).await?;
+    let tabs = state.store.list_tabs().await.map_err(|e| store_error("List", e))?;
+
+    let visible = tabs
+        .into_iter()
+        .filter_map(|tab| Some(ListedTab { role: index.role(&tab.id, &user.id)?, tab }))
+        .collect();
+    Ok(Json(visible))
+}
 
-    let tabs = rows.iter().map(|row| Tab {
-        id: row.get("id"),
-        title: row.get("title"),
-        content: row.get("content"),
-        child_window_id: row.get("child_window_id"),
-        parent_id: row.get("parent_id"),
-        created_at: row.get("created_at"),
-    }).collect();
+async fn get_tree(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser
+) -> Result<Json<Vec<TreeNode>>, (StatusCode, String)> {
+    let index = acl::load(&state).await?;
+    let mut nodes = state.store.list_tree().await.map_err(|e| store_error("Tree", e))?;
+    nodes.retain(|node| index.can(&node.id, &user.id, Role::Viewer));
 
-    Json(tabs)
+    Ok(Json(nodes))
+}
+
+async fn get_tab(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<String>
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    acl::require(&acl::load(&state).await?, &id, &user, Role::Viewer)?;
+    let tab = state.store
+        .get_tab(&id)
+        .await
+        .map_err(|e| store_error("Get Tab", e))?
+        .ok_or((StatusCode::NOT_FOUND, format!("Tab {} not found", id)))?;
+
+    Ok(([(header::ETAG, etag(tab.version.unwrap_or_default()))], Json(tab)))
 }
 
 async fn save_tab(
-    State(pool): State<Pool<Postgres>>, 
-    Json(tab): Json<Tab>
-) -> Result<&'static str, (StatusCode, String)> {
+    State(state): State<AppState>, 
+    CurrentUser(user): CurrentUser,
+    headers: HeaderMap,
+    Json(mut tab): Json<Tab>
+) -> Result<Response, (StatusCode, String)> {
     // LINE DEBUG
     println!("📥 Received Tab: {} - Content Length: {}", tab.id, tab.content.len());
 
-    let result = sqlx::query(
-        "INSERT INTO tabs (id, title, content, child_window_id, parent_id, created_at) 
-         VALUES ($1, $2, $3, $4, $5, $6) 
-         ON CONFLICT (id) DO UPDATE SET 
-            title = $2, 
-            content = $3, 
-            child_window_id = $4, 
-            parent_id = $5"
-    )
-    .bind(&tab.id)
-    .bind(&tab.title)
-    .bind(&tab.content)
-    .bind(&tab.child_window_id)
-    .bind(&tab.parent_id)
-    .bind(tab.created_at)
-    .execute(&pool)
-    .await;
-
-    match result {
-        Ok(_) => Ok("OK"),
-        Err(e) => {
-            eprintln!("❌ DB Error (Save): {:?}", e);
-            Err((StatusCode::INTERNAL_SERVER_ERROR, format!("DB Error: {}", e)))
+    // Changing a tab takes edit access to it; creating one, or landing it
+    // under a new parent, takes edit access to where it ends up. Checked
+    // before the content is cleaned, as that stores its pasted images.
+    let index = acl::load(&state).await?;
+    let stored_parent = index.nodes.get(&tab.id).map(|node| node.parent_id.as_deref());
+    if stored_parent.is_some() {
+        acl::require(&index, &tab.id, &user, Role::Editor)?;
+    }
+    if stored_parent != Some(tab.parent_id.as_deref()) {
+        acl::require_parent(&index, tab.parent_id.as_deref(), &user)?;
+    }
+
+    let sanitize::Sanitized { html, stripped } = clean_content(&state, &tab.id, &tab.content).await?;
+    tab.content = html;
+
+    // If-Match takes precedence so plain HTTP clients don't have to touch the body.
+    let expected_version = if_match_version(&headers)?.or(tab.version);
+
+    let outcome = state.store.save_tab(&tab, expected_version).await.map_err(|e| store_error("Save", e))?;
+
+    match outcome {
+        SaveOutcome::Saved { version, change } => {
+            let (id, parent_id) = (tab.id.clone(), tab.parent_id);
+            match change {
+                Some(TabChange::Created) => state.events.publish(ChangeEvent::Created { id, parent_id, version }).await,
+                Some(TabChange::Updated) => state.events.publish(ChangeEvent::Updated { id, version }).await,
+                Some(TabChange::Moved) => state.events.publish(ChangeEvent::Moved { id, parent_id, version }).await,
+                None => {}
+            }
+            Ok(([(header::ETAG, etag(version))], Json(SaveResponse { id: tab.id, version, stripped })).into_response())
         }
+        SaveOutcome::Conflict(current) => {
+            let current_version = current.version.unwrap_or_default();
+            println!("⚠️ Version conflict on {}: expected {:?}, server has {}", tab.id, expected_version, current_version);
+            Ok((StatusCode::CONFLICT, [(header::ETAG, etag(current_version))], Json(current)).into_response())
+        }
+    }
+}
+
+// What any write of tab content goes through: the sanitizer, then pasted
+// images (which arrive as base64) moved out into attachments so they stay out
+// of every later list.
+async fn clean_content(state: &AppState, id: &str, html: &str) -> Result<sanitize::Sanitized, (StatusCode, String)> {
+    let mut sanitized = sanitize::sanitize(html);
+    if !sanitized.stripped.is_empty() {
+        let count: usize = sanitized.stripped.iter().map(|s| s.count).sum();
+        println!("🧹 Stripped {} disallowed elements/attributes from {}", count, id);
+    }
+
+    let (content, moved) = state.attachments
+        .extract_inline(&sanitized.html)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Attachment Error: {}", e)))?;
+    if moved > 0 {
+        println!("📎 Moved {} inline images from {} into attachments", moved, id);
+        sanitized.html = content;
     }
+    Ok(sanitized)
 }
 
-// NEW: Delete Handler
+// Moves the tab and its whole subtree to the trash; see the trash module for
+// restoring and purging. Refused outright if any tab in the subtree is one the
+// caller can't edit, rather than leaving part of it behind.
 async fn delete_tab(
-    State(pool): State<Pool<Postgres>>,
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
     Path(id): Path<String>
 ) -> Result<StatusCode, (StatusCode, String)> {
-    // This query finds the parent and every nested child regardless of depth
-    let sql = r#"
-        WITH RECURSIVE tab_tree AS (
-            SELECT id FROM tabs WHERE id = $1
-            UNION ALL
-            SELECT t.id FROM tabs t
-            INNER JOIN tab_tree tt ON t.parent_id = tt.id
-        )
-        DELETE FROM tabs WHERE id IN (SELECT id FROM tab_tree)
-    "#;
-
-    match sqlx::query(sql).bind(&id).execute(&pool).await {
-        Ok(res) => {
-            println!("🗑️ Database Cleaned: {} records removed", res.rows_affected());
+    let index = acl::load(&state).await?;
+    acl::require(&index, &id, &user, Role::Editor)?;
+    let protected = index
+        .live_descendants(&id)
+        .iter()
+        .filter(|child| !index.can(child, &user.id, Role::Editor))
+        .count();
+    if protected > 0 {
+        return Err((StatusCode::FORBIDDEN, format!("Cannot delete {}: {} tabs under it are not yours to edit", id, protected)));
+    }
+
+    match state.store.delete_tab(&id).await {
+        Ok(count) => {
+            println!("🗑️ Moved to trash: {} records", count);
+            if count > 0 {
+                state.events.publish(ChangeEvent::Deleted { id, count }).await;
+            }
             Ok(StatusCode::NO_CONTENT)
         },
-        Err(e) => {
-            eprintln!("❌ DB Delete Error: {:?}", e);
-            Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+        Err(e) => Err(store_error("Delete", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn if_match(value: &str) -> Result<Option<i64>, (StatusCode, String)> {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_MATCH, HeaderValue::from_str(value).unwrap());
+        if_match_version(&headers)
+    }
+
+    #[test]
+    fn if_match_accepts_the_etags_it_hands_out() {
+        assert_eq!(if_match(etag(7).to_str().unwrap()).unwrap(), Some(7));
+        assert_eq!(if_match("W/\"7\"").unwrap(), Some(7));
+        assert_eq!(if_match("*").unwrap(), None);
+        assert_eq!(if_match_version(&HeaderMap::new()).unwrap(), None);
+        assert_eq!(if_match("\"seven\"").unwrap_err().0, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn saving_over_a_newer_version_answers_409_with_its_etag() {
+        for (name, store) in testing::stores().await {
+            let ann = testing::user(&store, "ann").await;
+            let state = testing::state(store);
+            let save = |content: &str, if_match: Option<&str>| {
+                let mut headers = HeaderMap::new();
+                if let Some(value) = if_match {
+                    headers.insert(header::IF_MATCH, HeaderValue::from_str(value).unwrap());
+                }
+                save_tab(State(state.clone()), CurrentUser(ann.clone()), headers, Json(testing::tab("a", None, content)))
+            };
+
+            assert_eq!(save("<p>1</p>", None).await.unwrap().status(), StatusCode::OK, "{}", name);
+            assert_eq!(save("<p>2</p>", Some("\"1\"")).await.unwrap().status(), StatusCode::OK, "{}", name);
+
+            let stale = save("<p>3</p>", Some("\"1\"")).await.unwrap();
+            assert_eq!(stale.status(), StatusCode::CONFLICT, "{}", name);
+            assert_eq!(stale.headers()[header::ETAG], "\"2\"", "{}", name);
         }
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn single_tabs_come_with_their_version_as_etag() {
+        for (name, store) in testing::stores().await {
+            let ann = testing::user(&store, "ann").await;
+            store.save_tab(&testing::tab("a", None, "<p>1</p>"), None).await.unwrap();
+            store.save_tab(&testing::tab("a", None, "<p>2</p>"), None).await.unwrap();
+            let state = testing::state(store);
+
+            let found = get_tab(State(state.clone()), CurrentUser(ann.clone()), Path("a".to_string())).await.unwrap().into_response();
+            assert_eq!(found.headers()[header::ETAG], "\"2\"", "{}", name);
+
+            let missing = get_tab(State(state), CurrentUser(ann), Path("nope".to_string())).await;
+            assert_eq!(missing.err().map(|(status, _)| status), Some(StatusCode::NOT_FOUND), "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn refused_saves_store_none_of_their_images() {
+        use sha2::{Digest, Sha256};
+
+        for (name, store) in testing::stores().await {
+            let ann = testing::user(&store, "ann").await;
+            let bob = testing::user(&store, "bob").await;
+            store.save_tab(&testing::tab("a", None, ""), None).await.unwrap();
+            store.grant_access("a", &ann.id, Role::Owner, 1).await.unwrap();
+            store.grant_access("a", &bob.id, Role::Viewer, 1).await.unwrap();
+            let state = testing::state(store);
+
+            let pasted = testing::tab("a", None, "<p><img src=\"data:image/png;base64,cmVmdXNlZCB1cGxvYWQ=\"></p>");
+            let refused = save_tab(State(state.clone()), CurrentUser(bob), HeaderMap::new(), Json(pasted)).await;
+            assert_eq!(refused.err().map(|(status, _)| status), Some(StatusCode::FORBIDDEN), "{}", name);
+
+            let hash = hex::encode(Sha256::digest(b"refused upload"));
+            assert!(state.attachments.get(&hash).await.unwrap().is_none(), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use serde::Serialize;
+
+use crate::acl::{self, Role};
+use crate::auth::CurrentUser;
+use crate::events::ChangeEvent;
+use crate::store::SaveOutcome;
+use crate::{clean_content, etag, store_error, AppState, Tab};
+
+#[derive(Serialize, Clone)]
+pub struct RevisionSummary {
+    pub rev: i32,
+    pub title: String,
+    pub saved_at: i64,
+}
+
+/// A stored copy of a tab's title and content. Backends append one on every
+/// save unless the latest one already holds the same title and content: the UI
+/// autosaves every tab at once, so without that check an edit to one article
+/// would add a no-op revision to all the others.
+#[derive(Serialize, Clone)]
+pub struct Revision {
+    pub tab_id: String,
+    pub rev: i32,
+    pub title: String,
+    pub content: String,
+    pub saved_at: i64,
+}
+
+pub async fn list_revisions(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<String>
+) -> Result<Json<Vec<RevisionSummary>>, (StatusCode, String)> {
+    acl::require(&acl::load(&state).await?, &id, &user, Role::Viewer)?;
+    let revisions = state.store.list_revisions(&id).await.map_err(|e| store_error("List Revisions", e))?;
+    Ok(Json(revisions))
+}
+
+pub async fn get_revision(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Path((id, rev)): Path<(String, i32)>
+) -> Result<Json<Revision>, (StatusCode, String)> {
+    acl::require(&acl::load(&state).await?, &id, &user, Role::Viewer)?;
+    let revision = state.store
+        .get_revision(&id, rev)
+        .await
+        .map_err(|e| store_error("Get Revision", e))?
+        .ok_or((StatusCode::NOT_FOUND, format!("Revision {} of tab {} not found", rev, id)))?;
+
+    Ok(Json(revision))
+}
+
+/// Copies an old revision back into the tab. It is cleaned like any save, as
+/// revisions recorded before the sanitizer existed may hold markup it would
+/// strip. The restore is itself recorded as a new revision, so rolling back
+/// can be undone the same way.
+pub async fn restore_revision(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Path((id, rev)): Path<(String, i32)>
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    acl::require(&acl::load(&state).await?, &id, &user, Role::Editor)?;
+    let not_found = || (StatusCode::NOT_FOUND, format!("Revision {} of tab {} not found", rev, id));
+    let revision = state.store
+        .get_revision(&id, rev)
+        .await
+        .map_err(|e| store_error("Restore Revision", e))?
+        .ok_or_else(not_found)?;
+    let current = state.store
+        .get_tab(&id)
+        .await
+        .map_err(|e| store_error("Restore Revision", e))?
+        .ok_or_else(not_found)?;
+
+    let content = clean_content(&state, &id, &revision.content).await?.html;
+    let tab = Tab { title: revision.title, content, version: None, ..current };
+    let version = match state.store.save_tab(&tab, current.version).await.map_err(|e| store_error("Restore Revision", e))? {
+        SaveOutcome::Saved { version, change } => {
+            if change.is_some() {
+                state.events.publish(ChangeEvent::Updated { id: id.clone(), version }).await;
+            }
+            version
+        }
+        SaveOutcome::Conflict(_) => {
+            return Err((StatusCode::CONFLICT, format!("Tab {} changed while restoring; try again", id)));
+        }
+    };
+
+    println!("⏪ Restored tab {} to revision {}", id, rev);
+    Ok(([(header::ETAG, etag(version))], Json(Tab { version: Some(version), ..tab })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{state, stores, tab, user};
+
+    #[tokio::test]
+    async fn restoring_sanitizes_content_recorded_before_the_sanitizer() {
+        for (name, store) in stores().await {
+            let ann = user(&store, "ann").await;
+            // Written straight to the store, as saves were before sanitizing.
+            store.save_tab(&tab("a", None, "<p>old</p><script>alert(1)</script>"), None).await.unwrap();
+            store.save_tab(&tab("a", None, "<p>new</p>"), None).await.unwrap();
+
+            let state = state(store.clone());
+            restore_revision(State(state), CurrentUser(ann), Path(("a".to_string(), 1))).await.unwrap();
+
+            let restored = store.get_tab("a").await.unwrap().unwrap();
+            assert_eq!(restored.content, "<p>old</p>", "{}", name);
+            assert_eq!(restored.version, Some(3), "{}", name);
+            let latest = store.list_revisions("a").await.unwrap();
+            assert_eq!(latest.len(), 3, "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn restoring_an_unknown_revision_is_not_found() {
+        for (name, store) in stores().await {
+            let ann = user(&store, "ann").await;
+            store.save_tab(&tab("a", None, "<p>x</p>"), None).await.unwrap();
+
+            let result = restore_revision(State(state(store)), CurrentUser(ann), Path(("a".to_string(), 9))).await;
+            assert_eq!(result.err().map(|(status, _)| status), Some(StatusCode::NOT_FOUND), "{}", name);
+        }
+    }
+}
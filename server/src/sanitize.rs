@@ -0,0 +1,269 @@
+//! Server-side cleaning of tab HTML. Content is rendered straight back into
+//! the editor for everyone on the instance, so only what the tiptap schema in
+//! the UI can produce is kept: anything else is stripped on save and listed in
+//! the save response.
+
+use ammonia::Builder;
+use scraper::{Html, Node};
+use serde::Serialize;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::sync::OnceLock;
+
+/// Tags the editor produces, with the attributes each may carry.
+const ALLOWED: &[(&str, &[&str])] = &[
+    ("p", &[]),
+    ("h1", &[]),
+    ("h2", &[]),
+    ("h3", &[]),
+    ("strong", &[]),
+    ("em", &[]),
+    ("u", &[]),
+    ("s", &[]),
+    ("code", &["class"]),
+    ("pre", &[]),
+    ("blockquote", &[]),
+    ("br", &[]),
+    ("hr", &[]),
+    ("ul", &[]),
+    ("ol", &["start", "type"]),
+    ("li", &[]),
+    ("a", &["href", "class"]),
+    ("span", &["class", "data-tab-id"]),
+    ("img", &["src", "alt", "title", "width", "height"]),
+    ("table", &["style"]),
+    ("colgroup", &[]),
+    ("col", &["style"]),
+    ("tbody", &[]),
+    ("thead", &[]),
+    ("tr", &[]),
+    ("th", &["colspan", "rowspan", "colwidth", "style"]),
+    ("td", &["colspan", "rowspan", "colwidth", "style"]),
+];
+
+/// Dropped together with everything inside them rather than unwrapped.
+const DROPPED_WITH_CONTENT: &[&str] = &["script", "style"];
+
+const URL_SCHEMES: &[&str] = &["http", "https", "mailto", "data"];
+
+// Column resizing writes widths inline; nothing else may come through `style`.
+const STYLE_PROPERTIES: &[&str] = &["width", "min-width"];
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Stripped {
+    pub element: String,
+    /// Set when only an attribute was removed and the element itself kept.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attribute: Option<String>,
+    pub count: usize,
+}
+
+pub struct Sanitized {
+    pub html: String,
+    pub stripped: Vec<Stripped>,
+}
+
+/// Cleans `html` and reports what had to go, grouped by element and attribute
+/// in order of first appearance.
+pub fn sanitize(html: &str) -> Sanitized {
+    Sanitized {
+        html: builder().clean(html).to_string(),
+        stripped: report(html),
+    }
+}
+
+fn builder() -> &'static Builder<'static> {
+    static BUILDER: OnceLock<Builder<'static>> = OnceLock::new();
+    BUILDER.get_or_init(|| {
+        let mut builder = Builder::empty();
+        builder
+            .tags(ALLOWED.iter().map(|(tag, _)| *tag).collect())
+            .tag_attributes(ALLOWED.iter().map(|(tag, attrs)| (*tag, attrs.iter().copied().collect())).collect::<HashMap<_, HashSet<_>>>())
+            .clean_content_tags(DROPPED_WITH_CONTENT.iter().copied().collect())
+            .url_schemes(URL_SCHEMES.iter().copied().collect())
+            .filter_style_properties(STYLE_PROPERTIES.iter().copied().collect())
+            .link_rel(None)
+            .attribute_filter(filter_attribute);
+        builder
+    })
+}
+
+/// Value-level checks the allowlist alone can't express.
+fn filter_attribute<'v>(element: &str, attribute: &str, value: &'v str) -> Option<Cow<'v, str>> {
+    match (element, attribute) {
+        // Pasted images arrive as data URIs; they must not be usable as links.
+        ("img", "src") => {
+            let is_data = value.trim_start().get(..5).is_some_and(|s| s.eq_ignore_ascii_case("data:"));
+            (!is_data || is_image_data_uri(value)).then_some(Cow::Borrowed(value))
+        }
+        (_, "href") if value.trim_start().get(..5).is_some_and(|s| s.eq_ignore_ascii_case("data:")) => None,
+        (_, "class") => {
+            let kept: Vec<&str> = value.split_ascii_whitespace().filter(|c| is_allowed_class(element, c)).collect();
+            if kept.is_empty() {
+                None
+            } else if kept.len() == value.split_ascii_whitespace().count() {
+                Some(Cow::Borrowed(value))
+            } else {
+                Some(Cow::Owned(kept.join(" ")))
+            }
+        }
+        _ => Some(Cow::Borrowed(value)),
+    }
+}
+
+fn is_image_data_uri(value: &str) -> bool {
+    value.trim_start().get(..11).is_some_and(|s| s.eq_ignore_ascii_case("data:image/"))
+}
+
+fn is_allowed_class(element: &str, class: &str) -> bool {
+    match element {
+        "a" | "span" => class == "wiki-link",
+        "code" => class.starts_with("language-"),
+        _ => false,
+    }
+}
+
+fn has_allowed_scheme(value: &str) -> bool {
+    match value.split_once(':') {
+        // A colon after a slash, `?` or `#` is part of a relative URL.
+        Some((scheme, _)) if !scheme.contains(['/', '?', '#']) => {
+            URL_SCHEMES.iter().any(|s| s.eq_ignore_ascii_case(scheme.trim()))
+        }
+        _ => true,
+    }
+}
+
+fn has_only_allowed_properties(style: &str) -> bool {
+    style
+        .split(';')
+        .filter(|declaration| !declaration.trim().is_empty())
+        .all(|declaration| {
+            let property = declaration.split(':').next().unwrap_or_default().trim();
+            STYLE_PROPERTIES.iter().any(|p| p.eq_ignore_ascii_case(property))
+        })
+}
+
+/// Walks the input with the same rules the cleaner applies and tallies every
+/// element or attribute that won't survive.
+fn report(html: &str) -> Vec<Stripped> {
+    let fragment = Html::parse_fragment(html);
+    let allowed: HashMap<&str, &[&str]> = ALLOWED.iter().copied().collect();
+    let mut stripped: Vec<Stripped> = Vec::new();
+    let mut note = |element: &str, attribute: Option<&str>| {
+        match stripped.iter_mut().find(|s| s.element == element && s.attribute.as_deref() == attribute) {
+            Some(entry) => entry.count += 1,
+            None => stripped.push(Stripped {
+                element: element.to_string(),
+                attribute: attribute.map(str::to_string),
+                count: 1,
+            }),
+        }
+    };
+
+    for node in fragment.root_element().descendants() {
+        // Everything inside a dropped element goes with it; report only the element.
+        if node.ancestors().any(|a| {
+            a.value().as_element().is_some_and(|e| DROPPED_WITH_CONTENT.contains(&e.name()))
+        }) {
+            continue;
+        }
+        let element = match node.value() {
+            Node::Element(element) => element,
+            Node::Comment(_) => {
+                note("#comment", None);
+                continue;
+            }
+            _ => continue,
+        };
+        // The fragment parser wraps the input in an <html> element of its own.
+        if node.id() == fragment.root_element().id() {
+            continue;
+        }
+
+        let name = element.name();
+        let Some(attributes) = allowed.get(name) else {
+            note(name, None);
+            continue;
+        };
+        for (attribute, value) in element.attrs() {
+            let kept = attributes.contains(&attribute)
+                && (!matches!(attribute, "href" | "src") || has_allowed_scheme(value))
+                && (attribute != "style" || has_only_allowed_properties(value))
+                && filter_attribute(name, attribute, value).is_some_and(|v| v == value);
+            if !kept {
+                note(name, Some(attribute));
+            }
+        }
+    }
+
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stripped(element: &str, attribute: Option<&str>, count: usize) -> Stripped {
+        Stripped { element: element.to_string(), attribute: attribute.map(str::to_string), count }
+    }
+
+    #[test]
+    fn editor_output_passes_untouched() {
+        let html = concat!(
+            "<h1>Title</h1><p><strong>a</strong> <em>b</em> <u>c</u> <s>d</s><br></p>",
+            "<pre><code class=\"language-rust\">fn main() {}</code></pre>",
+            "<ol start=\"3\"><li>item</li></ol><blockquote><p>quote</p></blockquote><hr>",
+            "<p><a href=\"https://example.com\">out</a> <span data-tab-id=\"t1\" class=\"wiki-link\">in</span></p>",
+            "<p><img src=\"data:image/png;base64,AAAA\" alt=\"x\"> <img src=\"/attachments/abc\"></p>",
+            "<table style=\"min-width:50px\"><colgroup><col style=\"width:50px\"></colgroup>",
+            "<tbody><tr><th colspan=\"2\">h</th></tr><tr><td colwidth=\"50\">c</td></tr></tbody></table>",
+        );
+        let sanitized = sanitize(html);
+        assert_eq!(sanitized.html, html);
+        assert!(sanitized.stripped.is_empty(), "{:?}", sanitized.stripped);
+    }
+
+    #[test]
+    fn scripts_go_with_their_content_and_unknown_tags_are_unwrapped() {
+        let sanitized = sanitize("<p>a<script>alert(1)<b>x</b></script><iframe src=\"x\"></iframe><font>b</font><!-- c --></p>");
+        assert_eq!(sanitized.html, "<p>ab</p>");
+        assert_eq!(sanitized.stripped, [
+            stripped("script", None, 1),
+            stripped("iframe", None, 1),
+            stripped("font", None, 1),
+            stripped("#comment", None, 1),
+        ]);
+    }
+
+    #[test]
+    fn attributes_outside_the_allowlist_are_dropped_and_counted() {
+        let sanitized = sanitize(concat!(
+            "<p onclick=\"x()\">a</p><p onclick=\"y()\" id=\"p\">b</p>",
+            "<a href=\"javascript:alert(1)\">c</a><a href=\"data:text/html,hi\">d</a>",
+            "<img src=\"data:text/html;base64,AAAA\"><span class=\"wiki-link evil\">e</span>",
+            "<code class=\"big\">f</code><table><tr><td style=\"width: 5px; position: fixed\">g</td></tr></table>",
+        ));
+        assert!(!sanitized.html.contains("onclick") && !sanitized.html.contains("javascript") && !sanitized.html.contains("data:text"));
+        assert!(sanitized.html.contains("<span class=\"wiki-link\">e</span>"), "{}", sanitized.html);
+        assert!(sanitized.html.contains("<code>f</code>"), "{}", sanitized.html);
+        assert!(!sanitized.html.contains("position"), "{}", sanitized.html);
+        assert_eq!(sanitized.stripped, [
+            stripped("p", Some("onclick"), 2),
+            stripped("p", Some("id"), 1),
+            stripped("a", Some("href"), 2),
+            stripped("img", Some("src"), 1),
+            stripped("span", Some("class"), 1),
+            stripped("code", Some("class"), 1),
+            stripped("td", Some("style"), 1),
+        ]);
+    }
+
+    #[test]
+    fn relative_urls_count_as_allowed() {
+        assert!(has_allowed_scheme("/attachments/abc"));
+        assert!(has_allowed_scheme("page?at=10:30"));
+        assert!(has_allowed_scheme("HTTPS://example.com"));
+        assert!(!has_allowed_scheme(" javascript:alert(1)"));
+        assert!(!has_allowed_scheme("vbscript:x"));
+    }
+}
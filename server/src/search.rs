@@ -0,0 +1,182 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use scraper::Html;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+use crate::acl::{self, Role};
+use crate::auth::CurrentUser;
+use crate::{store_error, AppState};
+
+const DEFAULT_LIMIT: i64 = 20;
+const MAX_LIMIT: i64 = 100;
+
+/// Put around hits by the backends, then turned into `<mark>` by
+/// [`mark_hits`]. Private-use characters, which [`strip_html`] keeps out of
+/// the indexed text.
+pub const MARK_START: char = '\u{E000}';
+pub const MARK_END: char = '\u{E001}';
+
+#[derive(Deserialize)]
+pub struct SearchParams {
+    q: String,
+    limit: Option<i64>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct PathEntry {
+    pub id: String,
+    pub title: String,
+}
+
+#[derive(Serialize)]
+pub struct SearchHit {
+    pub id: String,
+    pub title: String,
+    /// Matching fragments of the body with hits wrapped in `<mark>`.
+    pub snippet: String,
+    pub rank: f32,
+    /// Ancestors from the root column down to the hit's direct parent.
+    pub path: Vec<PathEntry>,
+}
+
+pub async fn search(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Query(params): Query<SearchParams>
+) -> Result<Json<Vec<SearchHit>>, (StatusCode, String)> {
+    let q = params.q.trim();
+    if q.is_empty() {
+        return Ok(Json(Vec::new()));
+    }
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+    // Hits in branches the caller can't read are dropped afterwards, so ask
+    // for the most the store will give and cut down to `limit` here.
+    let index = acl::load(&state).await?;
+    let mut hits = state.store.search(q, MAX_LIMIT).await.map_err(|e| store_error("Search", e))?;
+    hits.retain(|hit| index.can(&hit.id, &user.id, Role::Viewer));
+    hits.truncate(limit as usize);
+    for hit in &mut hits {
+        hit.path.retain(|entry| index.can(&entry.id, &user.id, Role::Viewer));
+    }
+    Ok(Json(hits))
+}
+
+/// The readable text of an article body, for backends that index it themselves.
+/// Entities come out decoded, so the result is plain text, not HTML.
+pub fn strip_html(html: &str) -> String {
+    let text = Html::parse_fragment(html).root_element().text().collect::<Vec<_>>().join(" ");
+    collapse_whitespace(&text.replace([MARK_START, MARK_END], ""))
+}
+
+/// Escapes a plain-text snippet and turns the markers around its hits into
+/// `<mark>`, the only markup a snippet carries.
+pub fn mark_hits(snippet: &str) -> String {
+    snippet
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace(MARK_START, "<mark>")
+        .replace(MARK_END, "</mark>")
+}
+
+/// Stripped tags leave runs of whitespace behind.
+pub fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Walks `(title, parent_id)` entries keyed by id from `parent_id` up to the
+/// root and returns the chain root-first.
+pub fn build_path(ancestors: &HashMap<String, (String, Option<String>)>, parent_id: Option<String>) -> Vec<PathEntry> {
+    let mut path = Vec::new();
+    let mut seen = HashSet::new();
+    let mut current = parent_id;
+
+    while let Some(id) = current {
+        // A corrupt parent chain must not hang the request.
+        if !seen.insert(id.clone()) {
+            break;
+        }
+        let Some((title, parent)) = ancestors.get(&id) else { break };
+        path.push(PathEntry { id, title: title.clone() });
+        current = parent.clone();
+    }
+
+    path.reverse();
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{state, stores, tab, user};
+
+    #[test]
+    fn strip_html_decodes_entities_and_drops_markers() {
+        assert_eq!(strip_html("<p>a &lt;b&gt;</p><p>\u{E000}c</p>"), "a <b> c");
+    }
+
+    #[test]
+    fn mark_hits_escapes_all_but_the_markers() {
+        assert_eq!(mark_hits("\u{E000}<x>\u{E001} & \"y\""), "<mark>&lt;x&gt;</mark> &amp; &quot;y&quot;");
+    }
+
+    #[tokio::test]
+    async fn snippets_keep_escaped_markup_escaped() {
+        for (name, store) in stores().await {
+            let content = "<p>find &lt;img src=x onerror=alert(1)&gt; here</p>";
+            store.save_tab(&tab("x", None, content), None).await.unwrap();
+
+            let hits = store.search("onerror", 10).await.unwrap();
+            assert_eq!(hits.len(), 1, "{}", name);
+            assert!(!hits[0].snippet.contains("<img"), "{}: {}", name, hits[0].snippet);
+            assert!(hits[0].snippet.contains("&lt;img src=x <mark>onerror</mark>"), "{}: {}", name, hits[0].snippet);
+        }
+    }
+
+    #[test]
+    fn paths_run_root_first_and_stop_at_a_cycle() {
+        let ancestors: HashMap<String, (String, Option<String>)> = [
+            ("a", ("A", None)),
+            ("b", ("B", Some("a"))),
+            ("x", ("X", Some("y"))),
+            ("y", ("Y", Some("x"))),
+        ]
+        .into_iter()
+        .map(|(id, (title, parent))| (id.to_string(), (title.to_string(), parent.map(str::to_string))))
+        .collect();
+
+        let titles = |parent: &str| build_path(&ancestors, Some(parent.to_string())).into_iter().map(|e| e.title).collect::<Vec<_>>();
+        assert_eq!(titles("b"), ["A", "B"]);
+        assert_eq!(titles("x"), ["Y", "X"]);
+        assert!(build_path(&ancestors, None).is_empty());
+    }
+
+    #[tokio::test]
+    async fn hits_and_path_entries_the_caller_cannot_see_are_dropped() {
+        for (name, store) in stores().await {
+            let ann = user(&store, "ann").await;
+            let bob = user(&store, "bob").await;
+            for (id, parent) in [("team", None), ("inner", Some("team")), ("note", Some("inner")), ("memo", Some("team"))] {
+                store.save_tab(&tab(id, parent, "<p>needle</p>"), None).await.unwrap();
+            }
+            // Bob sees `inner` and below it, but nothing else of `team`.
+            store.grant_access("team", &ann.id, Role::Owner, 1).await.unwrap();
+            store.grant_access("inner", &bob.id, Role::Viewer, 1).await.unwrap();
+
+            let params = || Query(SearchParams { q: "needle".to_string(), limit: None });
+            let Json(hits) = search(State(state(store.clone())), CurrentUser(bob), params()).await.unwrap();
+            let mut seen: Vec<(String, Vec<String>)> = hits.into_iter().map(|h| (h.id, h.path.into_iter().map(|e| e.id).collect())).collect();
+            seen.sort();
+            assert_eq!(seen, [("inner".to_string(), vec![]), ("note".to_string(), vec!["inner".to_string()])], "{}", name);
+
+            let Json(hits) = search(State(state(store)), CurrentUser(ann), params()).await.unwrap();
+            assert_eq!(hits.len(), 4, "{}", name);
+        }
+    }
+}
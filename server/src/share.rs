@@ -0,0 +1,227 @@
+//! Read-only links that show one branch to someone without an account. The
+//! link carries a random token; whoever holds it sees the tab it was made on
+//! and everything below it that the link's creator can still see, and nothing
+//! else of the tree.
+
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::acl::{self, Role};
+use crate::auth::{hash_token, random_hex, CurrentUser};
+use crate::hierarchy::subtree;
+use crate::{now_millis, store_error, AppState, Tab};
+
+const DAY_MILLIS: i64 = 24 * 60 * 60 * 1000;
+
+#[derive(Serialize, Clone)]
+pub struct ShareLink {
+    pub id: String,
+    pub tab_id: String,
+    pub created_by: String,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+}
+
+#[derive(Deserialize, Default)]
+pub struct ShareRequest {
+    expires_in_days: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct CreatedShare {
+    id: String,
+    token: String,
+    /// Path to hand out, relative to the API.
+    url: String,
+    expires_at: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct SharedSubtree {
+    root_id: String,
+    expires_at: Option<i64>,
+    /// The shared tab first, then its descendants parent before child. The
+    /// root's `parent_id` is blanked so nothing above it leaks.
+    tabs: Vec<Tab>,
+}
+
+/// Mints a link to the tab's subtree. Sharing outside the team takes the same
+/// owner access as granting it inside.
+pub async fn create_share(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<String>,
+    req: Option<Json<ShareRequest>>
+) -> Result<(StatusCode, Json<CreatedShare>), (StatusCode, String)> {
+    acl::require(&acl::load(&state).await?, &id, &user, Role::Owner)?;
+    state.store
+        .get_tab(&id)
+        .await
+        .map_err(|e| store_error("Share", e))?
+        .ok_or((StatusCode::NOT_FOUND, format!("Tab {} not found", id)))?;
+
+    let Json(req) = req.unwrap_or_default();
+    let now = now_millis();
+    let expires_at = match req.expires_in_days {
+        Some(days) if days <= 0 => {
+            return Err((StatusCode::UNPROCESSABLE_ENTITY, "expires_in_days must be positive".to_string()));
+        }
+        Some(days) => Some(
+            days.checked_mul(DAY_MILLIS)
+                .and_then(|span| now.checked_add(span))
+                .ok_or((StatusCode::UNPROCESSABLE_ENTITY, "expires_in_days is too large".to_string()))?,
+        ),
+        None => None,
+    };
+
+    let token = random_hex(32);
+    let link = ShareLink { id: random_hex(16), tab_id: id, created_by: user.id, created_at: now, expires_at };
+    state.store.create_share(&link, &hash_token(&token)).await.map_err(|e| store_error("Share", e))?;
+
+    println!("🔗 {} shared {} as link {}", user.username, link.tab_id, link.id);
+    let url = format!("/shared/{}", token);
+    Ok((StatusCode::CREATED, Json(CreatedShare { id: link.id, token, url, expires_at })))
+}
+
+pub async fn list_shares(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<String>
+) -> Result<Json<Vec<ShareLink>>, (StatusCode, String)> {
+    acl::require(&acl::load(&state).await?, &id, &user, Role::Owner)?;
+    let links = state.store.list_shares(&id).await.map_err(|e| store_error("List Shares", e))?;
+    Ok(Json(links))
+}
+
+pub async fn revoke_share(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Path((id, share_id)): Path<(String, String)>
+) -> Result<StatusCode, (StatusCode, String)> {
+    acl::require(&acl::load(&state).await?, &id, &user, Role::Owner)?;
+    if state.store.revoke_share(&id, &share_id).await.map_err(|e| store_error("Revoke Share", e))? {
+        println!("🔗 {} revoked share link {}", user.username, share_id);
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err((StatusCode::NOT_FOUND, format!("No share link {} on {}", share_id, id)))
+    }
+}
+
+/// The public side: no login, just the token. Unknown, revoked and expired
+/// links all look the same.
+pub async fn get_shared(
+    State(state): State<AppState>,
+    Path(token): Path<String>
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let not_found = || (StatusCode::NOT_FOUND, "This link doesn't exist or has expired".to_string());
+
+    let link = state.store
+        .find_share(&hash_token(&token), now_millis())
+        .await
+        .map_err(|e| store_error("Shared", e))?
+        .ok_or_else(not_found)?;
+
+    // The link shows what its creator can see now, so later grants narrow it
+    // too. A branch they can't view drops out along with everything below it.
+    let index = acl::load(&state).await?;
+    let mut tabs = state.store.list_tabs().await.map_err(|e| store_error("Shared", e))?;
+    tabs.retain(|tab| index.can(&tab.id, &link.created_by, Role::Viewer));
+    let mut tabs = subtree(tabs, &link.tab_id);
+    let Some(root) = tabs.first_mut() else {
+        // The shared tab is in the trash, or its creator lost access to it.
+        return Err(not_found());
+    };
+    root.parent_id = None;
+
+    // Revoking has to take effect at once, so nothing may keep a copy.
+    Ok((
+        [(header::CACHE_CONTROL, "no-store")],
+        Json(SharedSubtree { root_id: link.tab_id, expires_at: link.expires_at, tabs }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{state, stores, tab, user};
+
+    #[tokio::test]
+    async fn share_lifetimes_past_the_end_of_time_are_refused() {
+        for (name, store) in stores().await {
+            let ann = user(&store, "ann").await;
+            store.save_tab(&tab("a", None, ""), None).await.unwrap();
+
+            let req = ShareRequest { expires_in_days: Some(i64::MAX / 2) };
+            let result = create_share(State(state(store)), CurrentUser(ann), Path("a".to_string()), Some(Json(req))).await;
+            assert_eq!(result.err().map(|(status, _)| status), Some(StatusCode::UNPROCESSABLE_ENTITY), "{}", name);
+        }
+    }
+
+    async fn shared(state: &AppState, token: &str) -> Result<serde_json::Value, StatusCode> {
+        let response = get_shared(State(state.clone()), Path(token.to_string())).await.map_err(|(status, _)| status)?.into_response();
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-store");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        Ok(serde_json::from_slice(&body).unwrap())
+    }
+
+    fn ids(subtree: &serde_json::Value) -> Vec<&str> {
+        subtree["tabs"].as_array().unwrap().iter().map(|t| t["id"].as_str().unwrap()).collect()
+    }
+
+    #[tokio::test]
+    async fn a_link_shows_its_branch_as_the_creator_sees_it() {
+        for (name, store) in stores().await {
+            let ann = user(&store, "ann").await;
+            let bob = user(&store, "bob").await;
+            for (id, parent) in [("top", None), ("a", Some("top")), ("b", Some("a")), ("c", Some("a")), ("side", Some("top"))] {
+                store.save_tab(&tab(id, parent, ""), None).await.unwrap();
+            }
+            let state = state(store.clone());
+
+            let (status, Json(created)) = create_share(State(state.clone()), CurrentUser(ann.clone()), Path("a".to_string()), None).await.unwrap();
+            assert_eq!((status, created.url.clone()), (StatusCode::CREATED, format!("/shared/{}", created.token)), "{}", name);
+            let subtree = shared(&state, &created.token).await.unwrap();
+            assert_eq!(ids(&subtree), ["a", "b", "c"], "{}", name);
+            assert!(subtree["tabs"][0]["parent_id"].is_null(), "{}", name);
+
+            // Ann loses sight of `c`, then of the shared tab itself.
+            store.grant_access("c", &bob.id, Role::Owner, 1).await.unwrap();
+            assert_eq!(ids(&shared(&state, &created.token).await.unwrap()), ["a", "b"], "{}", name);
+            store.grant_access("a", &bob.id, Role::Owner, 1).await.unwrap();
+            assert_eq!(shared(&state, &created.token).await.err(), Some(StatusCode::NOT_FOUND), "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn revoked_expired_and_trashed_links_all_look_missing() {
+        for (name, store) in stores().await {
+            let ann = user(&store, "ann").await;
+            store.save_tab(&tab("a", None, ""), None).await.unwrap();
+            let state = state(store.clone());
+
+            let expired = ShareLink { id: "old".to_string(), tab_id: "a".to_string(), created_by: ann.id.clone(), created_at: 1, expires_at: Some(2) };
+            store.create_share(&expired, &hash_token("old-token")).await.unwrap();
+            assert_eq!(shared(&state, "old-token").await.err(), Some(StatusCode::NOT_FOUND), "{}", name);
+            assert_eq!(shared(&state, "never-issued").await.err(), Some(StatusCode::NOT_FOUND), "{}", name);
+
+            let req = Some(Json(ShareRequest { expires_in_days: Some(1) }));
+            let (_, Json(created)) = create_share(State(state.clone()), CurrentUser(ann.clone()), Path("a".to_string()), req).await.unwrap();
+            assert!(shared(&state, &created.token).await.is_ok(), "{}", name);
+            let Json(listed) = list_shares(State(state.clone()), CurrentUser(ann.clone()), Path("a".to_string())).await.unwrap();
+            assert_eq!(listed.len(), 2, "{}", name);
+
+            store.delete_tab("a").await.unwrap();
+            assert_eq!(shared(&state, &created.token).await.err(), Some(StatusCode::NOT_FOUND), "{}", name);
+            store.restore_from_trash("a").await.unwrap();
+
+            let revoked = revoke_share(State(state.clone()), CurrentUser(ann), Path(("a".to_string(), created.id.clone()))).await;
+            assert_eq!(revoked, Ok(StatusCode::NO_CONTENT), "{}", name);
+            assert_eq!(shared(&state, &created.token).await.err(), Some(StatusCode::NOT_FOUND), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,714 @@
+use async_trait::async_trait;
+use std::collections::{HashMap, HashSet};
+use std::sync::RwLock;
+
+use super::{SaveOutcome, StoreError, StoreResult, TabChange, TabStore};
+use crate::acl::{AclIndex, AclNode, Grant, Role};
+use crate::auth::{ApiToken, NewToken, TokenKind, User};
+use crate::hierarchy::{check_order, cycle_error, splice};
+use crate::links::{extract_links, group_broken_links, BrokenLink, BrokenLinkReport, LinkEntry, WikiLink};
+use crate::revisions::{Revision, RevisionSummary};
+use crate::search::{build_path, mark_hits, strip_html, SearchHit, MARK_END, MARK_START};
+use crate::share::ShareLink;
+use crate::trash::TrashEntry;
+use crate::{now_millis, Tab, TreeNode};
+
+// Characters of context kept on each side of the first match in a snippet.
+const SNIPPET_CONTEXT: usize = 80;
+
+#[derive(Clone)]
+struct StoredTab {
+    tab: Tab,
+    version: i64,
+    position: i32,
+    deleted_at: Option<i64>,
+    deleted_root: Option<String>,
+}
+
+impl StoredTab {
+    fn is_live(&self) -> bool {
+        self.deleted_at.is_none()
+    }
+
+    fn to_tab(&self) -> Tab {
+        Tab { version: Some(self.version), ..self.tab.clone() }
+    }
+}
+
+#[derive(Default, Clone)]
+struct Data {
+    tabs: HashMap<String, StoredTab>,
+    revisions: HashMap<String, Vec<Revision>>,
+    links: HashMap<String, Vec<WikiLink>>,
+    users: HashMap<String, StoredUser>,
+    tokens: Vec<StoredToken>,
+    /// Grants by tab id, as `(user id, role, granted_at)`.
+    acl: HashMap<String, Vec<(String, Role, i64)>>,
+    /// Share links with the hash of their token.
+    shares: Vec<(ShareLink, String)>,
+}
+
+#[derive(Clone)]
+struct StoredUser {
+    user: User,
+    password_hash: String,
+}
+
+#[derive(Clone)]
+struct StoredToken {
+    id: String,
+    user_id: String,
+    token_hash: String,
+    kind: TokenKind,
+    name: Option<String>,
+    created_at: i64,
+    expires_at: Option<i64>,
+    last_used_at: Option<i64>,
+}
+
+impl StoredToken {
+    fn is_expired(&self, now: i64) -> bool {
+        self.expires_at.is_some_and(|at| at <= now)
+    }
+}
+
+impl Data {
+    fn live(&self, id: &str) -> Option<&StoredTab> {
+        self.tabs.get(id).filter(|t| t.is_live())
+    }
+
+    /// Live tabs in the column under `parent_id`, in display order.
+    fn column(&self, parent_id: Option<&str>) -> Vec<&StoredTab> {
+        let mut column: Vec<&StoredTab> = self
+            .tabs
+            .values()
+            .filter(|t| t.is_live() && t.tab.parent_id.as_deref() == parent_id)
+            .collect();
+        column.sort_by(|a, b| display_order(a, b));
+        column
+    }
+
+    fn would_create_cycle(&self, tab_id: &str, parent_id: &str) -> bool {
+        let mut seen = HashSet::new();
+        let mut current = Some(parent_id.to_string());
+        while let Some(id) = current {
+            if id == tab_id {
+                return true;
+            }
+            if !seen.insert(id.clone()) {
+                return false;
+            }
+            current = self.tabs.get(&id).and_then(|t| t.tab.parent_id.clone());
+        }
+        false
+    }
+
+    fn record_revision(&mut self, tab_id: &str, title: &str, content: &str) {
+        let revisions = self.revisions.entry(tab_id.to_string()).or_default();
+        if let Some(latest) = revisions.last() {
+            if latest.title == title && latest.content == content {
+                return;
+            }
+        }
+        let rev = revisions.last().map_or(1, |r| r.rev + 1);
+        revisions.push(Revision {
+            tab_id: tab_id.to_string(),
+            rev,
+            title: title.to_string(),
+            content: content.to_string(),
+            saved_at: now_millis(),
+        });
+    }
+
+    /// `save_tab` against this copy of the data, so an import can stage its
+    /// writes and keep them only if all succeed.
+    fn save(&mut self, tab: &Tab, expected_version: Option<i64>) -> StoreResult<SaveOutcome> {
+        if let Some(parent_id) = &tab.parent_id {
+            if self.would_create_cycle(&tab.id, parent_id) {
+                return Err(cycle_error(&tab.id, parent_id));
+            }
+        }
+        let end_of_column = self.column(tab.parent_id.as_deref()).len() as i32;
+        let previous = self.live(&tab.id).map(|t| (t.version, t.tab.parent_id.clone()));
+
+        let version = match self.tabs.get_mut(&tab.id) {
+            Some(existing) => {
+                if !existing.is_live() {
+                    return Err(StoreError::Gone(format!("Tab {} is in the trash; restore it before saving", tab.id)));
+                }
+                if expected_version.is_some_and(|v| v != existing.version) {
+                    return Ok(SaveOutcome::Conflict(existing.to_tab()));
+                }
+                let old = &existing.tab;
+                let changed = (&old.title, &old.content, &old.child_window_id, &old.parent_id)
+                    != (&tab.title, &tab.content, &tab.child_window_id, &tab.parent_id);
+                if old.parent_id != tab.parent_id {
+                    existing.position = end_of_column;
+                }
+                if changed {
+                    existing.version += 1;
+                }
+                // created_at is fixed at creation, as in the SQL backends.
+                existing.tab = Tab { created_at: old.created_at, ..tab.clone() };
+                existing.version
+            }
+            None => {
+                self.tabs.insert(tab.id.clone(), StoredTab {
+                    tab: tab.clone(),
+                    version: 1,
+                    position: end_of_column,
+                    deleted_at: None,
+                    deleted_root: None,
+                });
+                1
+            }
+        };
+
+        self.record_revision(&tab.id, &tab.title, &tab.content);
+        self.links.insert(tab.id.clone(), extract_links(&tab.content));
+
+        let change = TabChange::between(previous, version, &tab.parent_id);
+        Ok(SaveOutcome::Saved { version, change })
+    }
+
+    fn set_positions(&mut self, order: &[String]) {
+        for (position, id) in order.iter().enumerate() {
+            if let Some(t) = self.tabs.get_mut(id) {
+                t.position = position as i32;
+            }
+        }
+    }
+
+    fn ancestors(&self) -> HashMap<String, (String, Option<String>)> {
+        self.tabs
+            .iter()
+            .map(|(id, t)| (id.clone(), (t.tab.title.clone(), t.tab.parent_id.clone())))
+            .collect()
+    }
+}
+
+fn display_order(a: &StoredTab, b: &StoredTab) -> std::cmp::Ordering {
+    (a.position, a.tab.created_at, &a.tab.id).cmp(&(b.position, b.tab.created_at, &b.tab.id))
+}
+
+/// Marks every occurrence of `terms` within a window around the first match,
+/// for [`mark_hits`]. `text` is matched case-insensitively, char by char.
+fn highlight(text: &str, terms: &[String]) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let lower: Vec<char> = text.to_lowercase().chars().collect();
+    // Lowercasing can change the length of exotic characters; fall back to a
+    // plain prefix rather than risk misaligned offsets.
+    if lower.len() != chars.len() {
+        return chars.iter().take(SNIPPET_CONTEXT * 2).collect();
+    }
+
+    let matches_at = |i: usize| -> Option<usize> {
+        terms.iter().find_map(|term| {
+            let term: Vec<char> = term.chars().collect();
+            (lower.len() >= i + term.len() && lower[i..i + term.len()] == term[..]).then_some(term.len())
+        })
+    };
+
+    let first = (0..lower.len()).find(|&i| matches_at(i).is_some()).unwrap_or(0);
+    let start = first.saturating_sub(SNIPPET_CONTEXT);
+    let end = (first + SNIPPET_CONTEXT).min(chars.len());
+
+    let mut out = String::new();
+    if start > 0 {
+        out.push_str("… ");
+    }
+    let mut i = start;
+    while i < end {
+        match matches_at(i) {
+            Some(len) => {
+                out.push(MARK_START);
+                out.extend(&chars[i..i + len]);
+                out.push(MARK_END);
+                i += len;
+            }
+            None => {
+                out.push(chars[i]);
+                i += 1;
+            }
+        }
+    }
+    if end < chars.len() {
+        out.push_str(" …");
+    }
+    out
+}
+
+/// Keeps everything in process memory; nothing survives a restart.
+#[derive(Default)]
+pub struct MemoryStore {
+    data: RwLock<Data>,
+}
+
+impl MemoryStore {
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, Data> {
+        self.data.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, Data> {
+        self.data.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[async_trait]
+impl TabStore for MemoryStore {
+    async fn list_tabs(&self) -> StoreResult<Vec<Tab>> {
+        let data = self.read();
+        let mut tabs: Vec<&StoredTab> = data.tabs.values().filter(|t| t.is_live()).collect();
+        tabs.sort_by(|a, b| display_order(a, b));
+        Ok(tabs.into_iter().map(StoredTab::to_tab).collect())
+    }
+
+    async fn list_tree(&self) -> StoreResult<Vec<TreeNode>> {
+        let tabs = self.list_tabs().await?;
+        Ok(tabs.into_iter().map(|t| TreeNode {
+            id: t.id,
+            title: t.title,
+            parent_id: t.parent_id,
+            child_window_id: t.child_window_id,
+            created_at: t.created_at,
+        }).collect())
+    }
+
+    async fn get_tab(&self, id: &str) -> StoreResult<Option<Tab>> {
+        Ok(self.read().live(id).map(StoredTab::to_tab))
+    }
+
+    async fn save_tab(&self, tab: &Tab, expected_version: Option<i64>) -> StoreResult<SaveOutcome> {
+        self.write().save(tab, expected_version)
+    }
+
+    async fn import_tabs(&self, tabs: &[Tab]) -> StoreResult<u64> {
+        // Work on a copy so a failure halfway leaves nothing behind.
+        let mut data = self.write();
+        let mut staged = data.clone();
+        for tab in tabs {
+            if let SaveOutcome::Conflict(_) = staged.save(tab, None)? {
+                return Err(StoreError::Conflict(format!("Tab {} changed during the import", tab.id)));
+            }
+        }
+        *data = staged;
+        Ok(tabs.len() as u64)
+    }
+
+    async fn delete_tab(&self, id: &str) -> StoreResult<u64> {
+        let mut data = self.write();
+        if data.live(id).is_none() {
+            return Ok(0);
+        }
+
+        let mut subtree = vec![id.to_string()];
+        let mut i = 0;
+        while i < subtree.len() {
+            let parent = subtree[i].clone();
+            subtree.extend(
+                data.tabs
+                    .values()
+                    .filter(|t| t.is_live() && t.tab.parent_id.as_deref() == Some(parent.as_str()))
+                    .map(|t| t.tab.id.clone())
+                    .filter(|child| !subtree.contains(child))
+                    .collect::<Vec<_>>(),
+            );
+            i += 1;
+        }
+
+        let now = now_millis();
+        for tab_id in &subtree {
+            if let Some(t) = data.tabs.get_mut(tab_id) {
+                t.deleted_at = Some(now);
+                t.deleted_root = Some(id.to_string());
+            }
+        }
+        Ok(subtree.len() as u64)
+    }
+
+    async fn move_tab(&self, id: &str, parent_id: Option<&str>, position: Option<usize>) -> StoreResult<Tab> {
+        let mut data = self.write();
+
+        if data.live(id).is_none() {
+            return Err(StoreError::NotFound(format!("Tab {} not found", id)));
+        }
+        if let Some(parent_id) = parent_id {
+            if data.live(parent_id).is_none() {
+                return Err(StoreError::NotFound(format!("Target parent {} not found", parent_id)));
+            }
+            if data.would_create_cycle(id, parent_id) {
+                return Err(cycle_error(id, parent_id));
+            }
+        }
+
+        let siblings: Vec<String> = data
+            .column(parent_id)
+            .into_iter()
+            .filter(|t| t.tab.id != id)
+            .map(|t| t.tab.id.clone())
+            .collect();
+        let order = splice(siblings, id, position);
+        data.set_positions(&order);
+
+        let stored = data.tabs.get_mut(id).expect("checked above");
+        if stored.tab.parent_id.as_deref() != parent_id {
+            stored.tab.parent_id = parent_id.map(str::to_string);
+            stored.version += 1;
+        }
+        Ok(stored.to_tab())
+    }
+
+    async fn reorder_window(&self, parent_id: Option<&str>, order: &[String]) -> StoreResult<()> {
+        let mut data = self.write();
+        let current: HashSet<String> = data.column(parent_id).iter().map(|t| t.tab.id.clone()).collect();
+        check_order(&current, order)?;
+        data.set_positions(order);
+        Ok(())
+    }
+
+    async fn list_revisions(&self, id: &str) -> StoreResult<Vec<RevisionSummary>> {
+        let data = self.read();
+        Ok(data.revisions.get(id).map_or_else(Vec::new, |revs| {
+            revs.iter().rev().map(|r| RevisionSummary {
+                rev: r.rev,
+                title: r.title.clone(),
+                saved_at: r.saved_at,
+            }).collect()
+        }))
+    }
+
+    async fn get_revision(&self, id: &str, rev: i32) -> StoreResult<Option<Revision>> {
+        let data = self.read();
+        Ok(data.revisions.get(id).and_then(|revs| revs.iter().find(|r| r.rev == rev).cloned()))
+    }
+
+    async fn search(&self, query: &str, limit: i64) -> StoreResult<Vec<SearchHit>> {
+        let terms: Vec<String> = query.split_whitespace().map(str::to_lowercase).collect();
+        let data = self.read();
+        let ancestors = data.ancestors();
+
+        // Every term must appear in the title or body; title hits weigh more.
+        let mut hits: Vec<SearchHit> = data
+            .tabs
+            .values()
+            .filter(|t| t.is_live())
+            .filter_map(|t| {
+                let title = t.tab.title.to_lowercase();
+                let body = strip_html(&t.tab.content);
+                let body_lower = body.to_lowercase();
+                let mut rank = 0.0;
+                for term in &terms {
+                    let in_title = title.matches(term.as_str()).count();
+                    let in_body = body_lower.matches(term.as_str()).count();
+                    if in_title + in_body == 0 {
+                        return None;
+                    }
+                    rank += in_title as f32 * 10.0 + in_body as f32;
+                }
+                Some(SearchHit {
+                    id: t.tab.id.clone(),
+                    title: t.tab.title.clone(),
+                    snippet: mark_hits(&highlight(&body, &terms)),
+                    rank,
+                    path: build_path(&ancestors, t.tab.parent_id.clone()),
+                })
+            })
+            .collect();
+
+        hits.sort_by(|a, b| b.rank.total_cmp(&a.rank).then_with(|| a.title.cmp(&b.title)));
+        hits.truncate(limit.max(0) as usize);
+        Ok(hits)
+    }
+
+    async fn links(&self, id: &str) -> StoreResult<Vec<LinkEntry>> {
+        let data = self.read();
+        Ok(data.links.get(id).map_or_else(Vec::new, |links| {
+            links.iter().map(|l| LinkEntry {
+                id: l.target_id.clone(),
+                title: data.live(&l.target_id).map(|t| t.tab.title.clone()),
+                text: l.text.clone(),
+            }).collect()
+        }))
+    }
+
+    async fn backlinks(&self, id: &str) -> StoreResult<Vec<LinkEntry>> {
+        let data = self.read();
+        let mut entries: Vec<LinkEntry> = data
+            .links
+            .iter()
+            .filter_map(|(source_id, links)| data.live(source_id).map(|source| (source, links)))
+            .flat_map(|(source, links)| {
+                links.iter().filter(|l| l.target_id == id).map(|l| LinkEntry {
+                    id: source.tab.id.clone(),
+                    title: Some(source.tab.title.clone()),
+                    text: l.text.clone(),
+                })
+            })
+            .collect();
+        entries.sort_by(|a, b| a.title.cmp(&b.title));
+        Ok(entries)
+    }
+
+    async fn broken_links(&self) -> StoreResult<Vec<BrokenLinkReport>> {
+        let data = self.read();
+        let mut sources: Vec<&StoredTab> = data
+            .links
+            .keys()
+            .filter_map(|id| data.live(id))
+            .collect();
+        sources.sort_by(|a, b| (&a.tab.title, &a.tab.id).cmp(&(&b.tab.title, &b.tab.id)));
+
+        let rows = sources.into_iter().flat_map(|source| {
+            data.links[&source.tab.id]
+                .iter()
+                .filter(|l| data.live(&l.target_id).is_none())
+                .map(|l| (
+                    source.tab.id.clone(),
+                    source.tab.title.clone(),
+                    source.tab.parent_id.clone(),
+                    BrokenLink { target_id: l.target_id.clone(), text: l.text.clone() },
+                ))
+                .collect::<Vec<_>>()
+        });
+        Ok(group_broken_links(rows))
+    }
+
+    async fn backfill_links(&self) -> StoreResult<u64> {
+        // Links are indexed on every save and nothing predates this process.
+        Ok(0)
+    }
+
+    async fn list_trash(&self) -> StoreResult<Vec<TrashEntry>> {
+        let data = self.read();
+        let mut entries: Vec<TrashEntry> = data
+            .tabs
+            .values()
+            .filter(|t| t.deleted_root.as_deref() == Some(t.tab.id.as_str()))
+            .map(|t| TrashEntry {
+                id: t.tab.id.clone(),
+                title: t.tab.title.clone(),
+                parent_id: t.tab.parent_id.clone(),
+                deleted_at: t.deleted_at.unwrap_or_default(),
+                tab_count: data
+                    .tabs
+                    .values()
+                    .filter(|d| d.deleted_root.as_deref() == Some(t.tab.id.as_str()))
+                    .count() as i64,
+            })
+            .collect();
+        entries.sort_by_key(|e| std::cmp::Reverse(e.deleted_at));
+        Ok(entries)
+    }
+
+    async fn restore_from_trash(&self, id: &str) -> StoreResult<u64> {
+        let mut data = self.write();
+
+        let root = data
+            .tabs
+            .get(id)
+            .filter(|t| t.deleted_root.as_deref() == Some(id))
+            .ok_or_else(|| StoreError::NotFound(format!("No trash entry for tab {}", id)))?;
+        if let Some(parent_id) = &root.tab.parent_id {
+            if data.tabs.get(parent_id).is_some_and(|p| !p.is_live()) {
+                return Err(StoreError::Conflict(format!("Parent {} is in the trash; restore it first", parent_id)));
+            }
+        }
+
+        let mut count = 0;
+        for t in data.tabs.values_mut().filter(|t| t.deleted_root.as_deref() == Some(id)) {
+            t.deleted_at = None;
+            t.deleted_root = None;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    async fn purge_trash(&self, cutoff: i64, roots: &[String]) -> StoreResult<u64> {
+        let mut data = self.write();
+        let purged: Vec<String> = data
+            .tabs
+            .values()
+            .filter(|t| t.deleted_at.is_some_and(|at| at <= cutoff))
+            .filter(|t| t.deleted_root.as_ref().is_some_and(|root| roots.contains(root)))
+            .map(|t| t.tab.id.clone())
+            .collect();
+
+        for id in &purged {
+            data.tabs.remove(id);
+            data.revisions.remove(id);
+            data.links.remove(id);
+            data.acl.remove(id);
+            data.shares.retain(|(link, _)| link.tab_id != *id);
+        }
+        Ok(purged.len() as u64)
+    }
+
+    async fn count_users(&self) -> StoreResult<i64> {
+        Ok(self.read().users.len() as i64)
+    }
+
+    async fn create_user(&self, user: &User, password_hash: &str) -> StoreResult<()> {
+        let mut data = self.write();
+        if data.users.values().any(|u| u.user.username == user.username) {
+            return Err(StoreError::Conflict(format!("Username {} is taken", user.username)));
+        }
+        data.users.insert(user.id.clone(), StoredUser { user: user.clone(), password_hash: password_hash.to_string() });
+        Ok(())
+    }
+
+    async fn create_first_user(&self, user: &User, password_hash: &str) -> StoreResult<bool> {
+        let mut data = self.write();
+        if !data.users.is_empty() {
+            return Ok(false);
+        }
+        data.users.insert(user.id.clone(), StoredUser { user: user.clone(), password_hash: password_hash.to_string() });
+        Ok(true)
+    }
+
+    async fn find_login(&self, username: &str) -> StoreResult<Option<(User, String)>> {
+        Ok(self
+            .read()
+            .users
+            .values()
+            .find(|u| u.user.username == username)
+            .map(|u| (u.user.clone(), u.password_hash.clone())))
+    }
+
+    async fn create_token(&self, token: &NewToken) -> StoreResult<()> {
+        let mut data = self.write();
+        data.tokens.retain(|t| !t.is_expired(token.created_at));
+        data.tokens.push(StoredToken {
+            id: token.id.clone(),
+            user_id: token.user_id.clone(),
+            token_hash: token.token_hash.clone(),
+            kind: token.kind,
+            name: token.name.clone(),
+            created_at: token.created_at,
+            expires_at: token.expires_at,
+            last_used_at: None,
+        });
+        Ok(())
+    }
+
+    async fn token_user(&self, token_hash: &str, now: i64) -> StoreResult<Option<User>> {
+        let mut data = self.write();
+        let Some(token) = data.tokens.iter_mut().find(|t| t.token_hash == token_hash && !t.is_expired(now)) else {
+            return Ok(None);
+        };
+        token.last_used_at = Some(now);
+        let user_id = token.user_id.clone();
+        Ok(data.users.get(&user_id).map(|u| u.user.clone()))
+    }
+
+    async fn list_api_tokens(&self, user_id: &str) -> StoreResult<Vec<ApiToken>> {
+        let data = self.read();
+        let mut tokens: Vec<&StoredToken> = data
+            .tokens
+            .iter()
+            .filter(|t| t.user_id == user_id && t.kind == TokenKind::Api)
+            .collect();
+        tokens.sort_by_key(|t| t.created_at);
+
+        Ok(tokens
+            .into_iter()
+            .map(|t| ApiToken {
+                id: t.id.clone(),
+                name: t.name.clone(),
+                created_at: t.created_at,
+                expires_at: t.expires_at,
+                last_used_at: t.last_used_at,
+            })
+            .collect())
+    }
+
+    async fn revoke_api_token(&self, user_id: &str, id: &str) -> StoreResult<bool> {
+        let mut data = self.write();
+        let before = data.tokens.len();
+        data.tokens.retain(|t| !(t.id == id && t.user_id == user_id && t.kind == TokenKind::Api));
+        Ok(data.tokens.len() < before)
+    }
+
+    async fn delete_session(&self, token_hash: &str) -> StoreResult<()> {
+        self.write().tokens.retain(|t| !(t.token_hash == token_hash && t.kind == TokenKind::Session));
+        Ok(())
+    }
+
+    async fn acl_index(&self) -> StoreResult<AclIndex> {
+        let data = self.read();
+        let mut index = AclIndex::default();
+        for stored in data.tabs.values() {
+            index.nodes.insert(stored.tab.id.clone(), AclNode { parent_id: stored.tab.parent_id.clone(), live: stored.is_live() });
+        }
+        for (tab_id, grants) in &data.acl {
+            let mut grants: Vec<Grant> = grants
+                .iter()
+                .filter_map(|(user_id, role, granted_at)| {
+                    Some(Grant {
+                        tab_id: tab_id.clone(),
+                        user_id: user_id.clone(),
+                        username: data.users.get(user_id)?.user.username.clone(),
+                        role: *role,
+                        granted_at: *granted_at,
+                    })
+                })
+                .collect();
+            grants.sort_by_key(|g| g.granted_at);
+            index.grants.insert(tab_id.clone(), grants);
+        }
+        Ok(index)
+    }
+
+    async fn grant_access(&self, tab_id: &str, user_id: &str, role: Role, granted_at: i64) -> StoreResult<()> {
+        let mut data = self.write();
+        if !data.tabs.contains_key(tab_id) {
+            return Err(StoreError::NotFound(format!("Tab {} not found", tab_id)));
+        }
+        let grants = data.acl.entry(tab_id.to_string()).or_default();
+        grants.retain(|(holder, _, _)| holder != user_id);
+        grants.push((user_id.to_string(), role, granted_at));
+        Ok(())
+    }
+
+    async fn revoke_access(&self, tab_id: &str, user_id: &str) -> StoreResult<bool> {
+        let mut data = self.write();
+        let Some(grants) = data.acl.get_mut(tab_id) else { return Ok(false) };
+        let before = grants.len();
+        grants.retain(|(holder, _, _)| holder != user_id);
+        let removed = grants.len() < before;
+        if grants.is_empty() {
+            data.acl.remove(tab_id);
+        }
+        Ok(removed)
+    }
+
+    async fn create_share(&self, link: &ShareLink, token_hash: &str) -> StoreResult<()> {
+        self.write().shares.push((link.clone(), token_hash.to_string()));
+        Ok(())
+    }
+
+    async fn find_share(&self, token_hash: &str, now: i64) -> StoreResult<Option<ShareLink>> {
+        Ok(self
+            .read()
+            .shares
+            .iter()
+            .find(|(link, hash)| hash == token_hash && link.expires_at.is_none_or(|at| at > now))
+            .map(|(link, _)| link.clone()))
+    }
+
+    async fn list_shares(&self, tab_id: &str) -> StoreResult<Vec<ShareLink>> {
+        Ok(self
+            .read()
+            .shares
+            .iter()
+            .filter(|(link, _)| link.tab_id == tab_id)
+            .map(|(link, _)| link.clone())
+            .collect())
+    }
+
+    async fn revoke_share(&self, tab_id: &str, id: &str) -> StoreResult<bool> {
+        let mut data = self.write();
+        let before = data.shares.len();
+        data.shares.retain(|(link, _)| !(link.id == id && link.tab_id == tab_id));
+        Ok(data.shares.len() < before)
+    }
+}
@@ -0,0 +1,473 @@
+//! Tab persistence behind one trait so the handlers don't care where the
+//! encyclopedia lives. The backend is picked at startup from the scheme of
+//! `DATABASE_URL`:
+//!
+//! - `postgres://…` / `postgresql://…` — the shared server setup
+//! - `sqlite:…` — a single file, e.g. `sqlite://encyclopedia.db`
+//! - `memory://` — nothing persisted, for tests and quick experiments
+
+use async_trait::async_trait;
+use std::sync::Arc;
+
+use crate::acl::{AclIndex, Role};
+use crate::auth::{ApiToken, NewToken, User};
+use crate::links::{BrokenLinkReport, LinkEntry};
+use crate::revisions::{Revision, RevisionSummary};
+use crate::search::SearchHit;
+use crate::share::ShareLink;
+use crate::trash::TrashEntry;
+use crate::{Tab, TreeNode};
+
+mod memory;
+mod postgres;
+mod sqlite;
+
+pub use memory::MemoryStore;
+pub use postgres::PgStore;
+pub use sqlite::SqliteStore;
+
+#[derive(Debug)]
+pub enum StoreError {
+    NotFound(String),
+    /// The tab exists but is in the trash.
+    Gone(String),
+    /// The request is well-formed but would break the tree (cycles, bad orders).
+    Invalid(String),
+    /// The request clashes with the current state of another row.
+    Conflict(String),
+    Database(sqlx::Error),
+}
+
+impl From<sqlx::Error> for StoreError {
+    fn from(e: sqlx::Error) -> Self {
+        StoreError::Database(e)
+    }
+}
+
+pub type StoreResult<T> = Result<T, StoreError>;
+
+pub enum SaveOutcome {
+    /// `change` is `None` when the save left the tab exactly as it was.
+    Saved { version: i64, change: Option<TabChange> },
+    /// The expected version was stale; carries the copy currently stored.
+    Conflict(Tab),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TabChange {
+    Created,
+    Updated,
+    /// The tab landed under a different parent (its content may also have changed).
+    Moved,
+}
+
+impl TabChange {
+    /// Classifies a save from the live row it replaced, as `(version, parent_id)`.
+    pub fn between(previous: Option<(i64, Option<String>)>, version: i64, parent_id: &Option<String>) -> Option<Self> {
+        match previous {
+            None => Some(TabChange::Created),
+            Some((_, previous_parent)) if previous_parent != *parent_id => Some(TabChange::Moved),
+            Some((previous_version, _)) if previous_version != version => Some(TabChange::Updated),
+            Some(_) => None,
+        }
+    }
+}
+
+#[async_trait]
+pub trait TabStore: Send + Sync {
+    /// Live tabs, each column in its curated order.
+    async fn list_tabs(&self) -> StoreResult<Vec<Tab>>;
+    async fn list_tree(&self) -> StoreResult<Vec<TreeNode>>;
+    async fn get_tab(&self, id: &str) -> StoreResult<Option<Tab>>;
+
+    /// Upserts `tab`, refusing when `expected_version` is set and stale. Also
+    /// records a revision and re-indexes the tab's wiki links. Fails with
+    /// `Gone` for trashed tabs and `Invalid` when the parent would form a cycle.
+    async fn save_tab(&self, tab: &Tab, expected_version: Option<i64>) -> StoreResult<SaveOutcome>;
+    /// Saves `tabs` in order (parents first) as `save_tab` would with no
+    /// expected version, all in one transaction: either every tab lands or
+    /// none does. Returns how many were written.
+    async fn import_tabs(&self, tabs: &[Tab]) -> StoreResult<u64>;
+
+    /// Moves the tab and its live subtree to the trash; returns the row count.
+    async fn delete_tab(&self, id: &str) -> StoreResult<u64>;
+    async fn move_tab(&self, id: &str, parent_id: Option<&str>, position: Option<usize>) -> StoreResult<Tab>;
+    /// `order` must name every live tab under `parent_id` exactly once.
+    async fn reorder_window(&self, parent_id: Option<&str>, order: &[String]) -> StoreResult<()>;
+
+    async fn list_revisions(&self, id: &str) -> StoreResult<Vec<RevisionSummary>>;
+    async fn get_revision(&self, id: &str, rev: i32) -> StoreResult<Option<Revision>>;
+
+    async fn search(&self, query: &str, limit: i64) -> StoreResult<Vec<SearchHit>>;
+
+    async fn links(&self, id: &str) -> StoreResult<Vec<LinkEntry>>;
+    async fn backlinks(&self, id: &str) -> StoreResult<Vec<LinkEntry>>;
+    async fn broken_links(&self) -> StoreResult<Vec<BrokenLinkReport>>;
+    /// Indexes links for tabs written before the link index existed.
+    async fn backfill_links(&self) -> StoreResult<u64>;
+
+    async fn list_trash(&self) -> StoreResult<Vec<TrashEntry>>;
+    async fn restore_from_trash(&self, id: &str) -> StoreResult<u64>;
+    /// Permanently removes the trash entries rooted at `roots` that were
+    /// trashed at or before `cutoff` (epoch millis).
+    async fn purge_trash(&self, cutoff: i64, roots: &[String]) -> StoreResult<u64>;
+
+    async fn count_users(&self) -> StoreResult<i64>;
+    /// Fails with `Conflict` when the username is taken.
+    async fn create_user(&self, user: &User, password_hash: &str) -> StoreResult<()>;
+    /// Creates the user only if there are none yet, checked and inserted as
+    /// one step so two sign-ups on an empty database can't both get in.
+    /// `false` when another user got there first.
+    async fn create_first_user(&self, user: &User, password_hash: &str) -> StoreResult<bool>;
+    /// The user and their Argon2 hash, for checking a login.
+    async fn find_login(&self, username: &str) -> StoreResult<Option<(User, String)>>;
+    /// Stores a session or API token, clearing out expired ones on the way.
+    async fn create_token(&self, token: &NewToken) -> StoreResult<()>;
+    /// Resolves an unexpired token by its hash and stamps `last_used_at`.
+    async fn token_user(&self, token_hash: &str, now: i64) -> StoreResult<Option<User>>;
+    async fn list_api_tokens(&self, user_id: &str) -> StoreResult<Vec<ApiToken>>;
+    /// Returns false when the user has no API token with that id.
+    async fn revoke_api_token(&self, user_id: &str, id: &str) -> StoreResult<bool>;
+    async fn delete_session(&self, token_hash: &str) -> StoreResult<()>;
+
+    /// Every tab's parent (trashed ones included) and every grant.
+    async fn acl_index(&self) -> StoreResult<AclIndex>;
+    /// Sets the user's role on the tab, replacing any grant they had there.
+    async fn grant_access(&self, tab_id: &str, user_id: &str, role: Role, granted_at: i64) -> StoreResult<()>;
+    /// Returns false when the user had no grant on that tab.
+    async fn revoke_access(&self, tab_id: &str, user_id: &str) -> StoreResult<bool>;
+
+    async fn create_share(&self, link: &ShareLink, token_hash: &str) -> StoreResult<()>;
+    /// Resolves an unexpired share link by the hash of its token.
+    async fn find_share(&self, token_hash: &str, now: i64) -> StoreResult<Option<ShareLink>>;
+    async fn list_shares(&self, tab_id: &str) -> StoreResult<Vec<ShareLink>>;
+    /// Returns false when the tab has no share link with that id.
+    async fn revoke_share(&self, tab_id: &str, id: &str) -> StoreResult<bool>;
+}
+
+pub fn is_postgres_url(database_url: &str) -> bool {
+    database_url.starts_with("postgres://") || database_url.starts_with("postgresql://")
+}
+
+/// Opens the backend named by `database_url` and brings its schema up to date.
+pub async fn connect(database_url: &str) -> Result<Arc<dyn TabStore>, String> {
+    if is_postgres_url(database_url) {
+        let store = PgStore::connect(database_url).await.map_err(|e| e.to_string())?;
+        Ok(Arc::new(store))
+    } else if database_url.starts_with("sqlite:") {
+        let store = SqliteStore::connect(database_url).await.map_err(|e| e.to_string())?;
+        Ok(Arc::new(store))
+    } else if database_url.starts_with("memory:") {
+        Ok(Arc::new(MemoryStore::default()))
+    } else {
+        Err(format!("Unsupported DATABASE_URL scheme: {}", database_url))
+    }
+}
+
+/// The contract every backend has to meet, run against the ones that need no
+/// server.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{stores, tab};
+
+    #[tokio::test]
+    async fn saves_record_revisions_unless_nothing_changed() {
+        for (name, store) in stores().await {
+            store.save_tab(&tab("a", None, "<p>one</p>"), None).await.unwrap();
+            store.save_tab(&tab("a", None, "<p>one</p>"), None).await.unwrap();
+            store.save_tab(&Tab { title: "Renamed".to_string(), ..tab("a", None, "<p>two</p>") }, None).await.unwrap();
+
+            let revisions = store.list_revisions("a").await.unwrap();
+            let listed: Vec<(i32, &str)> = revisions.iter().map(|r| (r.rev, r.title.as_str())).collect();
+            assert_eq!(listed, [(2, "Renamed"), (1, "a")], "{}", name);
+
+            let first = store.get_revision("a", 1).await.unwrap().unwrap();
+            assert_eq!(first.content, "<p>one</p>", "{}", name);
+            assert!(store.get_revision("a", 3).await.unwrap().is_none(), "{}", name);
+            assert!(store.list_revisions("missing").await.unwrap().is_empty(), "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn stale_saves_conflict_with_the_stored_copy() {
+        for (name, store) in stores().await {
+            let saved = |outcome: SaveOutcome| match outcome {
+                SaveOutcome::Saved { version, .. } => version,
+                SaveOutcome::Conflict(_) => panic!("{}: unexpected conflict", name),
+            };
+            assert_eq!(saved(store.save_tab(&tab("a", None, "<p>1</p>"), None).await.unwrap()), 1);
+            assert_eq!(saved(store.save_tab(&tab("a", None, "<p>2</p>"), Some(1)).await.unwrap()), 2);
+            // An identical save doesn't bump the version.
+            assert_eq!(saved(store.save_tab(&tab("a", None, "<p>2</p>"), Some(2)).await.unwrap()), 2);
+
+            match store.save_tab(&tab("a", None, "<p>stale</p>"), Some(1)).await.unwrap() {
+                SaveOutcome::Conflict(current) => {
+                    assert_eq!(current.version, Some(2), "{}", name);
+                    assert_eq!(current.content, "<p>2</p>", "{}", name);
+                }
+                SaveOutcome::Saved { .. } => panic!("{}: stale save went through", name),
+            }
+            // No expected version means last write wins.
+            assert_eq!(saved(store.save_tab(&tab("a", None, "<p>3</p>"), None).await.unwrap()), 3);
+            assert_eq!(store.get_tab("a").await.unwrap().unwrap().content, "<p>3</p>", "{}", name);
+        }
+    }
+
+    fn titled(id: &str, title: &str, parent_id: Option<&str>, content: &str) -> Tab {
+        Tab { title: title.to_string(), ..tab(id, parent_id, content) }
+    }
+
+    #[tokio::test]
+    async fn search_needs_every_term_and_ranks_title_hits_first() {
+        for (name, store) in stores().await {
+            store.save_tab(&titled("a", "Gravity", None, "<p>apples fall</p>"), None).await.unwrap();
+            store.save_tab(&titled("b", "Fruit", None, "<p>gravity pulls apples</p>"), None).await.unwrap();
+            store.save_tab(&titled("c", "Other", None, "<p>gravity only</p>"), None).await.unwrap();
+
+            let hits = store.search("gravity apples", 10).await.unwrap();
+            let ids: Vec<&str> = hits.iter().map(|hit| hit.id.as_str()).collect();
+            assert_eq!(ids, ["a", "b"], "{}", name);
+            assert!(hits[1].snippet.contains("<mark>"), "{}: {}", name, hits[1].snippet);
+            assert_eq!(store.search("gravity", 1).await.unwrap().len(), 1, "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn search_hits_carry_their_path_and_skip_the_trash() {
+        for (name, store) in stores().await {
+            store.save_tab(&titled("r", "Science", None, ""), None).await.unwrap();
+            store.save_tab(&titled("p", "Physics", Some("r"), ""), None).await.unwrap();
+            store.save_tab(&titled("q", "Quanta", Some("p"), "<p>entanglement</p>"), None).await.unwrap();
+
+            let hits = store.search("entanglement", 10).await.unwrap();
+            let path: Vec<&str> = hits[0].path.iter().map(|entry| entry.title.as_str()).collect();
+            assert_eq!(path, ["Science", "Physics"], "{}", name);
+
+            store.delete_tab("q").await.unwrap();
+            assert!(store.search("entanglement", 10).await.unwrap().is_empty(), "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn the_tree_lists_live_tabs_in_column_order() {
+        for (name, store) in stores().await {
+            for (id, parent) in [("r", None), ("x", Some("r")), ("y", Some("r")), ("gone", Some("r"))] {
+                store.save_tab(&tab(id, parent, "<p>body</p>"), None).await.unwrap();
+            }
+            store.delete_tab("gone").await.unwrap();
+
+            let tree = store.list_tree().await.unwrap();
+            let listed: Vec<(&str, Option<&str>)> = tree.iter().map(|n| (n.id.as_str(), n.parent_id.as_deref())).collect();
+            assert_eq!(listed, [("r", None), ("x", Some("r")), ("y", Some("r"))], "{}", name);
+
+            assert_eq!(store.get_tab("x").await.unwrap().unwrap().content, "<p>body</p>", "{}", name);
+            assert!(store.get_tab("gone").await.unwrap().is_none(), "{}", name);
+            assert!(store.get_tab("missing").await.unwrap().is_none(), "{}", name);
+        }
+    }
+
+    fn link(target: &str, text: &str) -> String {
+        format!("<span data-tab-id=\"{}\" class=\"wiki-link\">{}</span>", target, text)
+    }
+
+    #[tokio::test]
+    async fn links_and_backlinks_follow_each_save() {
+        for (name, store) in stores().await {
+            store.save_tab(&titled("b", "Beta", None, ""), None).await.unwrap();
+            let content = format!("<p>{} and {}</p>", link("b", "to beta"), link("m", "nowhere"));
+            store.save_tab(&titled("a", "Alpha", None, &content), None).await.unwrap();
+
+            let links = store.links("a").await.unwrap();
+            let listed: Vec<(&str, Option<&str>, &str)> = links.iter().map(|l| (l.id.as_str(), l.title.as_deref(), l.text.as_str())).collect();
+            assert_eq!(listed, [("b", Some("Beta"), "to beta"), ("m", None, "nowhere")], "{}", name);
+
+            let backlinks = store.backlinks("b").await.unwrap();
+            assert_eq!(backlinks.iter().map(|l| l.id.as_str()).collect::<Vec<_>>(), ["a"], "{}", name);
+
+            store.save_tab(&titled("a", "Alpha", None, "<p>no links</p>"), None).await.unwrap();
+            assert!(store.backlinks("b").await.unwrap().is_empty(), "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn broken_links_cover_missing_and_trashed_targets() {
+        for (name, store) in stores().await {
+            store.save_tab(&titled("t", "Target", None, ""), None).await.unwrap();
+            store.save_tab(&titled("gone", "Gone", None, ""), None).await.unwrap();
+            let content = format!("<p>{} {} {}</p>", link("t", "ok"), link("gone", "trashed"), link("never", "missing"));
+            store.save_tab(&titled("s", "Source", None, &content), None).await.unwrap();
+            store.save_tab(&titled("fine", "Fine", None, &link("t", "ok")), None).await.unwrap();
+            store.delete_tab("gone").await.unwrap();
+
+            let report = store.broken_links().await.unwrap();
+            assert_eq!(report.len(), 1, "{}", name);
+            assert_eq!(report[0].id, "s", "{}", name);
+            let dead: Vec<&str> = report[0].links.iter().map(|l| l.target_id.as_str()).collect();
+            assert_eq!(dead, ["gone", "never"], "{}", name);
+
+            store.restore_from_trash("gone").await.unwrap();
+            let dead = store.broken_links().await.unwrap().remove(0).links;
+            assert_eq!(dead.iter().map(|l| l.text.as_str()).collect::<Vec<_>>(), ["missing"], "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn deleting_trashes_the_subtree_as_one_entry() {
+        for (name, store) in stores().await {
+            for (id, parent) in [("r", None), ("c", Some("r")), ("g", Some("c"))] {
+                store.save_tab(&tab(id, parent, ""), None).await.unwrap();
+            }
+            assert_eq!(store.delete_tab("r").await.unwrap(), 3, "{}", name);
+            assert!(store.list_tabs().await.unwrap().is_empty(), "{}", name);
+
+            let trash = store.list_trash().await.unwrap();
+            assert_eq!(trash.iter().map(|e| (e.id.as_str(), e.tab_count)).collect::<Vec<_>>(), [("r", 3)], "{}", name);
+            assert!(matches!(store.save_tab(&tab("c", Some("r"), "<p>edit</p>"), None).await, Err(StoreError::Gone(_))), "{}", name);
+            // Only the tab that was deleted can be restored, not one it took along.
+            assert!(matches!(store.restore_from_trash("c").await, Err(StoreError::NotFound(_))), "{}", name);
+
+            assert_eq!(store.restore_from_trash("r").await.unwrap(), 3, "{}", name);
+            assert_eq!(store.list_tabs().await.unwrap().len(), 3, "{}", name);
+            assert!(store.list_trash().await.unwrap().is_empty(), "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_subtree_deleted_first_comes_back_on_its_own() {
+        for (name, store) in stores().await {
+            for (id, parent) in [("r", None), ("c", Some("r")), ("g", Some("c"))] {
+                store.save_tab(&tab(id, parent, ""), None).await.unwrap();
+            }
+            store.delete_tab("c").await.unwrap();
+            store.delete_tab("r").await.unwrap();
+
+            assert!(matches!(store.restore_from_trash("c").await, Err(StoreError::Conflict(_))), "{}", name);
+            assert_eq!(store.restore_from_trash("r").await.unwrap(), 1, "{}", name);
+            assert_eq!(store.restore_from_trash("c").await.unwrap(), 2, "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn purging_takes_only_the_named_entries_old_enough() {
+        for (name, store) in stores().await {
+            for id in ["a", "b"] {
+                store.save_tab(&tab(id, None, ""), None).await.unwrap();
+                store.delete_tab(id).await.unwrap();
+            }
+            let roots = ["a".to_string(), "b".to_string()];
+            assert_eq!(store.purge_trash(0, &roots).await.unwrap(), 0, "{}", name);
+            assert_eq!(store.purge_trash(i64::MAX, &roots[..1]).await.unwrap(), 1, "{}", name);
+
+            let left: Vec<String> = store.list_trash().await.unwrap().into_iter().map(|e| e.id).collect();
+            assert_eq!(left, ["b"], "{}", name);
+            assert!(store.get_revision("a", 1).await.unwrap().is_none(), "{}", name);
+        }
+    }
+
+    async fn column(store: &Arc<dyn TabStore>, parent_id: Option<&str>) -> Vec<String> {
+        let tabs = store.list_tabs().await.unwrap();
+        tabs.into_iter().filter(|t| t.parent_id.as_deref() == parent_id).map(|t| t.id).collect()
+    }
+
+    #[tokio::test]
+    async fn moves_land_at_the_asked_position_and_bump_the_version() {
+        for (name, store) in stores().await {
+            for (id, parent) in [("r", None), ("s", None), ("a", Some("r")), ("b", Some("r")), ("x", Some("s"))] {
+                store.save_tab(&tab(id, parent, ""), None).await.unwrap();
+            }
+
+            let moved = store.move_tab("x", Some("r"), Some(1)).await.unwrap();
+            assert_eq!((moved.parent_id.as_deref(), moved.version), (Some("r"), Some(2)), "{}", name);
+            assert_eq!(column(&store, Some("r")).await, ["a", "x", "b"], "{}", name);
+            assert!(column(&store, Some("s")).await.is_empty(), "{}", name);
+
+            // A position past the end appends, and staying put keeps the version.
+            let moved = store.move_tab("a", Some("r"), Some(99)).await.unwrap();
+            assert_eq!(moved.version, Some(1), "{}", name);
+            assert_eq!(column(&store, Some("r")).await, ["x", "b", "a"], "{}", name);
+
+            store.move_tab("s", Some("b"), None).await.unwrap();
+            assert_eq!(column(&store, None).await, ["r"], "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn moves_refuse_cycles_and_missing_parents() {
+        for (name, store) in stores().await {
+            for (id, parent) in [("r", None), ("c", Some("r")), ("g", Some("c")), ("t", None)] {
+                store.save_tab(&tab(id, parent, ""), None).await.unwrap();
+            }
+            store.delete_tab("t").await.unwrap();
+
+            assert!(matches!(store.move_tab("r", Some("g"), None).await, Err(StoreError::Invalid(_))), "{}", name);
+            assert!(matches!(store.move_tab("c", Some("c"), None).await, Err(StoreError::Invalid(_))), "{}", name);
+            assert!(matches!(store.move_tab("c", Some("t"), None).await, Err(StoreError::NotFound(_))), "{}", name);
+            assert!(matches!(store.move_tab("t", None, None).await, Err(StoreError::NotFound(_))), "{}", name);
+            assert_eq!(store.get_tab("c").await.unwrap().unwrap().parent_id.as_deref(), Some("r"), "{}", name);
+
+            // Saving with a parent below the tab is the same cycle by another route.
+            assert!(matches!(store.save_tab(&tab("r", Some("g"), ""), None).await, Err(StoreError::Invalid(_))), "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_reordered_column_keeps_its_order_through_later_saves() {
+        for (name, store) in stores().await {
+            for (id, parent) in [("r", None), ("a", Some("r")), ("b", Some("r")), ("c", Some("r"))] {
+                store.save_tab(&tab(id, parent, ""), None).await.unwrap();
+            }
+            let order = ["c".to_string(), "a".to_string(), "b".to_string()];
+            store.reorder_window(Some("r"), &order).await.unwrap();
+            assert_eq!(column(&store, Some("r")).await, order, "{}", name);
+
+            // New tabs join the end, and editing one doesn't move it.
+            store.save_tab(&tab("d", Some("r"), ""), None).await.unwrap();
+            store.save_tab(&tab("a", Some("r"), "<p>edit</p>"), None).await.unwrap();
+            assert_eq!(column(&store, Some("r")).await, ["c", "a", "b", "d"], "{}", name);
+
+            assert!(matches!(store.reorder_window(Some("r"), &order).await, Err(StoreError::Invalid(_))), "{}", name);
+            assert!(matches!(store.reorder_window(None, &[]).await, Err(StoreError::Invalid(_))), "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn unknown_database_schemes_are_refused() {
+        assert!(is_postgres_url("postgres://db/miller"));
+        assert!(is_postgres_url("postgresql://db/miller"));
+        assert!(!is_postgres_url("sqlite://miller.db"));
+        assert!(connect("mysql://db/miller").await.is_err());
+        assert!(connect("miller.db").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn saves_report_what_kind_of_change_they_made() {
+        for (name, store) in stores().await {
+            let change = |outcome: SaveOutcome| match outcome {
+                SaveOutcome::Saved { change, .. } => change,
+                SaveOutcome::Conflict(_) => panic!("unexpected conflict"),
+            };
+            store.save_tab(&tab("p", None, ""), None).await.unwrap();
+            assert_eq!(change(store.save_tab(&tab("a", None, ""), None).await.unwrap()), Some(TabChange::Created), "{}", name);
+            assert_eq!(change(store.save_tab(&tab("a", None, "<p>x</p>"), None).await.unwrap()), Some(TabChange::Updated), "{}", name);
+            assert_eq!(change(store.save_tab(&tab("a", None, "<p>x</p>"), None).await.unwrap()), None, "{}", name);
+            assert_eq!(change(store.save_tab(&tab("a", Some("p"), "<p>y</p>"), None).await.unwrap()), Some(TabChange::Moved), "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn an_import_that_fails_partway_leaves_nothing_behind() {
+        for (name, store) in stores().await {
+            store.save_tab(&tab("binned", None, ""), None).await.unwrap();
+            store.delete_tab("binned").await.unwrap();
+
+            let batch = [tab("new", None, "<p>x</p>"), tab("child", Some("new"), ""), tab("binned", None, "")];
+            assert!(matches!(store.import_tabs(&batch).await, Err(StoreError::Gone(_))), "{}", name);
+            assert!(store.list_tabs().await.unwrap().is_empty(), "{}", name);
+            assert!(store.list_revisions("new").await.unwrap().is_empty(), "{}", name);
+
+            assert_eq!(store.import_tabs(&batch[..2]).await.unwrap(), 2, "{}", name);
+            assert_eq!(column(&store, Some("new")).await, ["child"], "{}", name);
+        }
+    }
+}
@@ -0,0 +1,900 @@
+use async_trait::async_trait;
+use sqlx::{
+    postgres::{PgPoolOptions, PgRow},
+    Pool, Postgres, Row, Transaction,
+};
+use std::collections::{HashMap, HashSet};
+
+use super::{SaveOutcome, StoreError, StoreResult, TabChange, TabStore};
+use crate::acl::{AclIndex, AclNode, Grant, Role};
+use crate::auth::{ApiToken, NewToken, TokenKind, User};
+use crate::hierarchy::{check_order, cycle_error, splice};
+use crate::links::{extract_links, group_broken_links, BrokenLink, BrokenLinkReport, LinkEntry};
+use crate::revisions::{Revision, RevisionSummary};
+use crate::search::{build_path, collapse_whitespace, SearchHit};
+use crate::share::ShareLink;
+use crate::trash::TrashEntry;
+use crate::{now_millis, Tab, TreeNode};
+
+// Arbitrary key for pg_advisory_xact_lock; serializes moves so two concurrent
+// ones can't each pass the cycle check and still form a loop together.
+const MOVE_LOCK_KEY: i64 = 0x6d6f7665;
+
+// Serializes first-user sign-ups; under READ COMMITTED two of them would
+// otherwise both find the table empty.
+const SIGNUP_LOCK_KEY: i64 = 0x7369676e;
+
+const TAB_COLUMNS: &str = "id, title, content, child_window_id, parent_id, created_at, version";
+
+pub struct PgStore {
+    pool: Pool<Postgres>,
+}
+
+impl PgStore {
+    pub async fn connect(database_url: &str) -> Result<Self, sqlx::Error> {
+        let pool = PgPoolOptions::new()
+            .max_connections(5)
+            .connect(database_url)
+            .await?;
+
+        sqlx::migrate!("./migrations/postgres").run(&pool).await?;
+
+        Ok(PgStore { pool })
+    }
+}
+
+fn tab_from_row(row: &PgRow) -> Tab {
+    Tab {
+        id: row.get("id"),
+        title: row.get("title"),
+        content: row.get::<Option<String>, _>("content").unwrap_or_default(),
+        child_window_id: row.get("child_window_id"),
+        parent_id: row.get("parent_id"),
+        created_at: row.get("created_at"),
+        version: Some(row.get("version")),
+    }
+}
+
+fn user_from_row(row: &PgRow) -> User {
+    User {
+        id: row.get("id"),
+        username: row.get("username"),
+        created_at: row.get("created_at"),
+    }
+}
+
+fn share_from_row(row: &PgRow) -> ShareLink {
+    ShareLink {
+        id: row.get("id"),
+        tab_id: row.get("tab_id"),
+        created_by: row.get("created_by"),
+        created_at: row.get("created_at"),
+        expires_at: row.get("expires_at"),
+    }
+}
+
+fn link_entry(row: &PgRow) -> LinkEntry {
+    LinkEntry {
+        id: row.get("id"),
+        title: row.get("title"),
+        text: row.get("link_text"),
+    }
+}
+
+/// Appends a revision unless the latest one already matches. Must run inside
+/// the transaction that wrote the `tabs` row: the row lock taken by that write
+/// is what keeps two concurrent saves from picking the same `rev`.
+async fn record_revision(
+    tx: &mut Transaction<'_, Postgres>,
+    tab_id: &str,
+    title: &str,
+    content: &str,
+) -> Result<(), sqlx::Error> {
+    let latest = sqlx::query(
+        "SELECT rev, (title = $2 AND content IS NOT DISTINCT FROM $3) AS unchanged
+         FROM tab_revisions WHERE tab_id = $1
+         ORDER BY rev DESC LIMIT 1"
+    )
+    .bind(tab_id)
+    .bind(title)
+    .bind(content)
+    .fetch_optional(&mut **tx)
+    .await?;
+
+    let next_rev = match latest {
+        Some(row) if row.get::<bool, _>("unchanged") => return Ok(()),
+        Some(row) => row.get::<i32, _>("rev") + 1,
+        None => 1,
+    };
+
+    sqlx::query(
+        "INSERT INTO tab_revisions (tab_id, rev, title, content, saved_at)
+         VALUES ($1, $2, $3, $4, $5)"
+    )
+    .bind(tab_id)
+    .bind(next_rev)
+    .bind(title)
+    .bind(content)
+    .bind(now_millis())
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+/// Replaces the outgoing links recorded for `source_id` with the ones found in `content`.
+async fn sync_links(
+    tx: &mut Transaction<'_, Postgres>,
+    source_id: &str,
+    content: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM tab_links WHERE source_id = $1")
+        .bind(source_id)
+        .execute(&mut **tx)
+        .await?;
+
+    let links = extract_links(content);
+    if links.is_empty() {
+        return Ok(());
+    }
+
+    let targets: Vec<&str> = links.iter().map(|l| l.target_id.as_str()).collect();
+    let texts: Vec<&str> = links.iter().map(|l| l.text.as_str()).collect();
+    let positions: Vec<i32> = (0..links.len() as i32).collect();
+
+    sqlx::query(
+        "INSERT INTO tab_links (source_id, target_id, link_text, position)
+         SELECT $1, * FROM UNNEST($2::TEXT[], $3::TEXT[], $4::INTEGER[])"
+    )
+    .bind(source_id)
+    .bind(&targets)
+    .bind(&texts)
+    .bind(&positions)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+/// True when `parent_id` is `tab_id` itself or one of its descendants, i.e.
+/// when hanging `tab_id` under it would make the tab its own ancestor.
+async fn would_create_cycle(
+    tx: &mut Transaction<'_, Postgres>,
+    tab_id: &str,
+    parent_id: &str,
+) -> Result<bool, sqlx::Error> {
+    // UNION (not UNION ALL) so an already corrupt chain terminates.
+    let row = sqlx::query(
+        "WITH RECURSIVE ancestors AS (
+            SELECT id, parent_id FROM tabs WHERE id = $2
+            UNION
+            SELECT t.id, t.parent_id FROM tabs t
+            INNER JOIN ancestors a ON t.id = a.parent_id
+        )
+        SELECT EXISTS (SELECT 1 FROM ancestors WHERE id = $1) AS cycle"
+    )
+    .bind(tab_id)
+    .bind(parent_id)
+    .fetch_one(&mut **tx)
+    .await?;
+
+    Ok(row.get("cycle"))
+}
+
+async fn set_positions(tx: &mut Transaction<'_, Postgres>, order: &[String]) -> Result<(), sqlx::Error> {
+    let positions: Vec<i32> = (0..order.len() as i32).collect();
+    sqlx::query(
+        "UPDATE tabs SET position = o.position
+         FROM UNNEST($1::TEXT[], $2::INTEGER[]) AS o(id, position)
+         WHERE tabs.id = o.id"
+    )
+    .bind(order)
+    .bind(&positions)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+/// The body of `save_tab`, inside a transaction the caller commits.
+async fn write_tab(
+    tx: &mut Transaction<'_, Postgres>,
+    tab: &Tab,
+    expected_version: Option<i64>,
+) -> StoreResult<SaveOutcome> {
+    let previous = sqlx::query("SELECT version, parent_id FROM tabs WHERE id = $1 AND deleted_at IS NULL FOR UPDATE")
+        .bind(&tab.id)
+        .fetch_optional(&mut **tx)
+        .await?
+        .map(|row| (row.get::<i64, _>("version"), row.get::<Option<String>, _>("parent_id")));
+
+    // The version only moves when something actually changed, because the UI
+    // re-posts every tab on each autosave. New tabs, and tabs that land in a
+    // different column, go to the end of that column.
+    let saved = sqlx::query(
+        "INSERT INTO tabs (id, title, content, child_window_id, parent_id, created_at, version, position)
+         VALUES ($1, $2, $3, $4, $5, $6, 1, (
+            SELECT COALESCE(MAX(position) + 1, 0) FROM tabs
+            WHERE parent_id IS NOT DISTINCT FROM $5 AND deleted_at IS NULL
+         ))
+         ON CONFLICT (id) DO UPDATE SET
+            title = $2,
+            content = $3,
+            child_window_id = $4,
+            parent_id = $5,
+            position = CASE WHEN tabs.parent_id IS DISTINCT FROM $5 THEN EXCLUDED.position ELSE tabs.position END,
+            version = tabs.version + CASE
+                WHEN (tabs.title, tabs.content, tabs.child_window_id, tabs.parent_id)
+                    IS DISTINCT FROM ($2, $3, $4, $5) THEN 1
+                ELSE 0
+            END
+         WHERE tabs.deleted_at IS NULL AND ($7::BIGINT IS NULL OR tabs.version = $7)
+         RETURNING version"
+    )
+    .bind(&tab.id)
+    .bind(&tab.title)
+    .bind(&tab.content)
+    .bind(&tab.child_window_id)
+    .bind(&tab.parent_id)
+    .bind(tab.created_at)
+    .bind(expected_version)
+    .fetch_optional(&mut **tx)
+    .await?;
+
+    // Re-parenting through a plain save must not create a loop either.
+    if let Some(parent_id) = &tab.parent_id {
+        if would_create_cycle(tx, &tab.id, parent_id).await? {
+            return Err(cycle_error(&tab.id, parent_id));
+        }
+    }
+
+    let Some(row) = saved else {
+        // The ON CONFLICT guard skipped the update: either the tab is in the trash
+        // or somebody saved a newer version first.
+        let current = sqlx::query(&format!("SELECT {TAB_COLUMNS}, deleted_at FROM tabs WHERE id = $1"))
+            .bind(&tab.id)
+            .fetch_one(&mut **tx)
+            .await?;
+        if current.get::<Option<i64>, _>("deleted_at").is_some() {
+            return Err(StoreError::Gone(format!("Tab {} is in the trash; restore it before saving", tab.id)));
+        }
+        return Ok(SaveOutcome::Conflict(tab_from_row(&current)));
+    };
+    let version: i64 = row.get("version");
+
+    record_revision(tx, &tab.id, &tab.title, &tab.content).await?;
+    sync_links(tx, &tab.id, &tab.content).await?;
+
+    let change = TabChange::between(previous, version, &tab.parent_id);
+    Ok(SaveOutcome::Saved { version, change })
+}
+
+#[async_trait]
+impl TabStore for PgStore {
+    async fn list_tabs(&self) -> StoreResult<Vec<Tab>> {
+        // Flat list, but each column's tabs come out in their curated order.
+        let rows = sqlx::query(&format!(
+            "SELECT {TAB_COLUMNS} FROM tabs
+             WHERE deleted_at IS NULL
+             ORDER BY position, created_at, id"
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(tab_from_row).collect())
+    }
+
+    async fn list_tree(&self) -> StoreResult<Vec<TreeNode>> {
+        let rows = sqlx::query(
+            "SELECT id, title, parent_id, child_window_id, created_at FROM tabs
+             WHERE deleted_at IS NULL
+             ORDER BY position, created_at, id"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(|row| TreeNode {
+            id: row.get("id"),
+            title: row.get("title"),
+            parent_id: row.get("parent_id"),
+            child_window_id: row.get("child_window_id"),
+            created_at: row.get("created_at"),
+        }).collect())
+    }
+
+    async fn get_tab(&self, id: &str) -> StoreResult<Option<Tab>> {
+        let row = sqlx::query(&format!("SELECT {TAB_COLUMNS} FROM tabs WHERE id = $1 AND deleted_at IS NULL"))
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.as_ref().map(tab_from_row))
+    }
+
+    async fn save_tab(&self, tab: &Tab, expected_version: Option<i64>) -> StoreResult<SaveOutcome> {
+        let mut tx = self.pool.begin().await?;
+        let outcome = write_tab(&mut tx, tab, expected_version).await?;
+        if let SaveOutcome::Saved { .. } = outcome {
+            tx.commit().await?;
+        }
+        Ok(outcome)
+    }
+
+    async fn import_tabs(&self, tabs: &[Tab]) -> StoreResult<u64> {
+        let mut tx = self.pool.begin().await?;
+        for tab in tabs {
+            if let SaveOutcome::Conflict(_) = write_tab(&mut tx, tab, None).await? {
+                return Err(StoreError::Conflict(format!("Tab {} changed during the import", tab.id)));
+            }
+        }
+        tx.commit().await?;
+        Ok(tabs.len() as u64)
+    }
+
+    async fn delete_tab(&self, id: &str) -> StoreResult<u64> {
+        // This query finds the parent and every nested child regardless of depth.
+        // Rows already in the trash keep their own deleted_root so they come back
+        // with the deletion that originally removed them, not with this one.
+        let sql = r#"
+            WITH RECURSIVE tab_tree AS (
+                SELECT id FROM tabs WHERE id = $1 AND deleted_at IS NULL
+                UNION ALL
+                SELECT t.id FROM tabs t
+                INNER JOIN tab_tree tt ON t.parent_id = tt.id
+                WHERE t.deleted_at IS NULL
+            )
+            UPDATE tabs SET deleted_at = $2, deleted_root = $1
+            WHERE id IN (SELECT id FROM tab_tree)
+        "#;
+
+        let res = sqlx::query(sql).bind(id).bind(now_millis()).execute(&self.pool).await?;
+        Ok(res.rows_affected())
+    }
+
+    async fn move_tab(&self, id: &str, parent_id: Option<&str>, position: Option<usize>) -> StoreResult<Tab> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("SELECT pg_advisory_xact_lock($1)")
+            .bind(MOVE_LOCK_KEY)
+            .execute(&mut *tx)
+            .await?;
+
+        let exists = sqlx::query("SELECT 1 FROM tabs WHERE id = $1 AND deleted_at IS NULL FOR UPDATE")
+            .bind(id)
+            .fetch_optional(&mut *tx)
+            .await?;
+        if exists.is_none() {
+            return Err(StoreError::NotFound(format!("Tab {} not found", id)));
+        }
+
+        if let Some(parent_id) = parent_id {
+            let parent = sqlx::query("SELECT 1 FROM tabs WHERE id = $1 AND deleted_at IS NULL")
+                .bind(parent_id)
+                .fetch_optional(&mut *tx)
+                .await?;
+            if parent.is_none() {
+                return Err(StoreError::NotFound(format!("Target parent {} not found", parent_id)));
+            }
+            if would_create_cycle(&mut tx, id, parent_id).await? {
+                return Err(cycle_error(id, parent_id));
+            }
+        }
+
+        // Renumber the destination column with the moved tab spliced in.
+        let siblings = sqlx::query(
+            "SELECT id FROM tabs
+             WHERE parent_id IS NOT DISTINCT FROM $1 AND deleted_at IS NULL AND id <> $2
+             ORDER BY position, created_at, id"
+        )
+        .bind(parent_id)
+        .bind(id)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let order = splice(siblings.iter().map(|row| row.get("id")).collect(), id, position);
+        set_positions(&mut tx, &order).await?;
+
+        let row = sqlx::query(&format!(
+            "UPDATE tabs SET parent_id = $2,
+                version = version + CASE WHEN parent_id IS DISTINCT FROM $2 THEN 1 ELSE 0 END
+             WHERE id = $1
+             RETURNING {TAB_COLUMNS}"
+        ))
+        .bind(id)
+        .bind(parent_id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(tab_from_row(&row))
+    }
+
+    async fn reorder_window(&self, parent_id: Option<&str>, order: &[String]) -> StoreResult<()> {
+        let mut tx = self.pool.begin().await?;
+
+        // Lock the column so a concurrent save can't add a sibling we didn't account for.
+        let rows = sqlx::query(
+            "SELECT id FROM tabs WHERE parent_id IS NOT DISTINCT FROM $1 AND deleted_at IS NULL FOR UPDATE"
+        )
+        .bind(parent_id)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let current: HashSet<String> = rows.iter().map(|row| row.get("id")).collect();
+        check_order(&current, order)?;
+        set_positions(&mut tx, order).await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn list_revisions(&self, id: &str) -> StoreResult<Vec<RevisionSummary>> {
+        let rows = sqlx::query(
+            "SELECT rev, title, saved_at FROM tab_revisions WHERE tab_id = $1 ORDER BY rev DESC"
+        )
+        .bind(id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(|row| RevisionSummary {
+            rev: row.get("rev"),
+            title: row.get("title"),
+            saved_at: row.get("saved_at"),
+        }).collect())
+    }
+
+    async fn get_revision(&self, id: &str, rev: i32) -> StoreResult<Option<Revision>> {
+        let row = sqlx::query(
+            "SELECT tab_id, rev, title, content, saved_at FROM tab_revisions WHERE tab_id = $1 AND rev = $2"
+        )
+        .bind(id)
+        .bind(rev)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|row| Revision {
+            tab_id: row.get("tab_id"),
+            rev: row.get("rev"),
+            title: row.get("title"),
+            content: row.get::<Option<String>, _>("content").unwrap_or_default(),
+            saved_at: row.get("saved_at"),
+        }))
+    }
+
+    async fn search(&self, query: &str, limit: i64) -> StoreResult<Vec<SearchHit>> {
+        // Rank against the index first and only build headlines for the page we
+        // return; ts_headline re-parses the whole document, which is expensive.
+        let rows = sqlx::query(
+            "WITH query AS (SELECT websearch_to_tsquery('english', $1) AS q),
+             hits AS (
+                SELECT t.id, t.title, t.parent_id, t.content, ts_rank_cd(t.search_vector, query.q) AS rank
+                FROM tabs t, query
+                WHERE t.search_vector @@ query.q AND t.deleted_at IS NULL
+                ORDER BY rank DESC, t.title
+                LIMIT $2
+             )
+             SELECT hits.id, hits.title, hits.parent_id, hits.rank,
+                    ts_headline('english', regexp_replace(COALESCE(hits.content, ''), '<[^>]*>', ' ', 'g'), query.q,
+                                'StartSel=<mark>, StopSel=</mark>, MaxFragments=2, MaxWords=25, MinWords=8') AS snippet
+             FROM hits, query
+             ORDER BY hits.rank DESC, hits.title"
+        )
+        .bind(query)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let parent_ids: Vec<String> = rows
+            .iter()
+            .filter_map(|row| row.get::<Option<String>, _>("parent_id"))
+            .collect();
+
+        // Everything on the way up from the hits to the root, in one query.
+        let ancestor_rows = sqlx::query(
+            "WITH RECURSIVE ancestors AS (
+                SELECT id, title, parent_id FROM tabs WHERE id = ANY($1)
+                UNION
+                SELECT t.id, t.title, t.parent_id FROM tabs t
+                INNER JOIN ancestors a ON t.id = a.parent_id
+            )
+            SELECT id, title, parent_id FROM ancestors"
+        )
+        .bind(&parent_ids)
+        .fetch_all(&self.pool)
+        .await?;
+        let ancestors: HashMap<String, (String, Option<String>)> = ancestor_rows
+            .iter()
+            .map(|row| (row.get("id"), (row.get("title"), row.get("parent_id"))))
+            .collect();
+
+        Ok(rows.iter().map(|row| SearchHit {
+            id: row.get("id"),
+            title: row.get("title"),
+            snippet: collapse_whitespace(&row.get::<String, _>("snippet")),
+            rank: row.get("rank"),
+            path: build_path(&ancestors, row.get("parent_id")),
+        }).collect())
+    }
+
+    async fn links(&self, id: &str) -> StoreResult<Vec<LinkEntry>> {
+        let rows = sqlx::query(
+            "SELECT l.target_id AS id, t.title, l.link_text FROM tab_links l
+             LEFT JOIN tabs t ON t.id = l.target_id AND t.deleted_at IS NULL
+             WHERE l.source_id = $1
+             ORDER BY l.position"
+        )
+        .bind(id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(link_entry).collect())
+    }
+
+    async fn backlinks(&self, id: &str) -> StoreResult<Vec<LinkEntry>> {
+        let rows = sqlx::query(
+            "SELECT l.source_id AS id, t.title, l.link_text FROM tab_links l
+             INNER JOIN tabs t ON t.id = l.source_id AND t.deleted_at IS NULL
+             WHERE l.target_id = $1
+             ORDER BY t.title, l.position"
+        )
+        .bind(id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(link_entry).collect())
+    }
+
+    async fn broken_links(&self) -> StoreResult<Vec<BrokenLinkReport>> {
+        let rows = sqlx::query(
+            "SELECT s.id, s.title, s.parent_id, l.target_id, l.link_text FROM tab_links l
+             INNER JOIN tabs s ON s.id = l.source_id AND s.deleted_at IS NULL
+             WHERE NOT EXISTS (SELECT 1 FROM tabs t WHERE t.id = l.target_id AND t.deleted_at IS NULL)
+             ORDER BY s.title, s.id, l.position"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(group_broken_links(rows.iter().map(|row| (
+            row.get("id"),
+            row.get("title"),
+            row.get("parent_id"),
+            BrokenLink { target_id: row.get("target_id"), text: row.get("link_text") },
+        ))))
+    }
+
+    async fn backfill_links(&self) -> StoreResult<u64> {
+        let rows = sqlx::query(
+            "SELECT id, content FROM tabs t
+             WHERE content LIKE '%data-tab-id%'
+               AND NOT EXISTS (SELECT 1 FROM tab_links l WHERE l.source_id = t.id)"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut tx = self.pool.begin().await?;
+        for row in &rows {
+            let content: Option<String> = row.get("content");
+            sync_links(&mut tx, row.get("id"), &content.unwrap_or_default()).await?;
+        }
+        tx.commit().await?;
+
+        Ok(rows.len() as u64)
+    }
+
+    async fn list_trash(&self) -> StoreResult<Vec<TrashEntry>> {
+        let rows = sqlx::query(
+            "SELECT t.id, t.title, t.parent_id, t.deleted_at,
+                    (SELECT COUNT(*) FROM tabs d WHERE d.deleted_root = t.id) AS tab_count
+             FROM tabs t
+             WHERE t.deleted_root = t.id
+             ORDER BY t.deleted_at DESC"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(|row| TrashEntry {
+            id: row.get("id"),
+            title: row.get("title"),
+            parent_id: row.get("parent_id"),
+            deleted_at: row.get("deleted_at"),
+            tab_count: row.get("tab_count"),
+        }).collect())
+    }
+
+    async fn restore_from_trash(&self, id: &str) -> StoreResult<u64> {
+        let mut tx = self.pool.begin().await?;
+
+        let row = sqlx::query(
+            "SELECT t.parent_id, p.deleted_at AS parent_deleted_at FROM tabs t
+             LEFT JOIN tabs p ON p.id = t.parent_id
+             WHERE t.id = $1 AND t.deleted_root = $1"
+        )
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| StoreError::NotFound(format!("No trash entry for tab {}", id)))?;
+
+        // Restoring under a trashed parent would leave the subtree unreachable.
+        if row.get::<Option<i64>, _>("parent_deleted_at").is_some() {
+            let parent_id: String = row.get("parent_id");
+            return Err(StoreError::Conflict(format!("Parent {} is in the trash; restore it first", parent_id)));
+        }
+
+        let res = sqlx::query("UPDATE tabs SET deleted_at = NULL, deleted_root = NULL WHERE deleted_root = $1")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(res.rows_affected())
+    }
+
+    async fn purge_trash(&self, cutoff: i64, roots: &[String]) -> StoreResult<u64> {
+        let res = sqlx::query("DELETE FROM tabs WHERE deleted_at IS NOT NULL AND deleted_at <= $1 AND deleted_root = ANY($2)")
+            .bind(cutoff)
+            .bind(roots)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(res.rows_affected())
+    }
+
+    async fn count_users(&self) -> StoreResult<i64> {
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM users")
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(count)
+    }
+
+    async fn create_user(&self, user: &User, password_hash: &str) -> StoreResult<()> {
+        let res = sqlx::query(
+            "INSERT INTO users (id, username, password_hash, created_at) VALUES ($1, $2, $3, $4)"
+        )
+        .bind(&user.id)
+        .bind(&user.username)
+        .bind(password_hash)
+        .bind(user.created_at)
+        .execute(&self.pool)
+        .await;
+
+        match res {
+            Ok(_) => Ok(()),
+            Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+                Err(StoreError::Conflict(format!("Username {} is taken", user.username)))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn create_first_user(&self, user: &User, password_hash: &str) -> StoreResult<bool> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("SELECT pg_advisory_xact_lock($1)")
+            .bind(SIGNUP_LOCK_KEY)
+            .execute(&mut *tx)
+            .await?;
+
+        let res = sqlx::query(
+            "INSERT INTO users (id, username, password_hash, created_at)
+             SELECT $1, $2, $3, $4 WHERE NOT EXISTS (SELECT 1 FROM users)"
+        )
+        .bind(&user.id)
+        .bind(&user.username)
+        .bind(password_hash)
+        .bind(user.created_at)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(res.rows_affected() == 1)
+    }
+
+    async fn find_login(&self, username: &str) -> StoreResult<Option<(User, String)>> {
+        let row = sqlx::query("SELECT id, username, created_at, password_hash FROM users WHERE username = $1")
+            .bind(username)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.map(|row| (user_from_row(&row), row.get("password_hash"))))
+    }
+
+    async fn create_token(&self, token: &NewToken) -> StoreResult<()> {
+        sqlx::query("DELETE FROM auth_tokens WHERE expires_at IS NOT NULL AND expires_at <= $1")
+            .bind(token.created_at)
+            .execute(&self.pool)
+            .await?;
+
+        sqlx::query(
+            "INSERT INTO auth_tokens (id, user_id, token_hash, kind, name, created_at, expires_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7)"
+        )
+        .bind(&token.id)
+        .bind(&token.user_id)
+        .bind(&token.token_hash)
+        .bind(token.kind.as_str())
+        .bind(&token.name)
+        .bind(token.created_at)
+        .bind(token.expires_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn token_user(&self, token_hash: &str, now: i64) -> StoreResult<Option<User>> {
+        let row = sqlx::query(
+            "UPDATE auth_tokens SET last_used_at = $2
+             WHERE token_hash = $1 AND (expires_at IS NULL OR expires_at > $2)
+             RETURNING user_id"
+        )
+        .bind(token_hash)
+        .bind(now)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        let Some(row) = row else { return Ok(None) };
+        let user_id: String = row.get("user_id");
+        let user = sqlx::query("SELECT id, username, created_at FROM users WHERE id = $1")
+            .bind(&user_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(user.as_ref().map(user_from_row))
+    }
+
+    async fn list_api_tokens(&self, user_id: &str) -> StoreResult<Vec<ApiToken>> {
+        let rows = sqlx::query(
+            "SELECT id, name, created_at, expires_at, last_used_at FROM auth_tokens
+             WHERE user_id = $1 AND kind = $2
+             ORDER BY created_at"
+        )
+        .bind(user_id)
+        .bind(TokenKind::Api.as_str())
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| ApiToken {
+                id: row.get("id"),
+                name: row.get("name"),
+                created_at: row.get("created_at"),
+                expires_at: row.get("expires_at"),
+                last_used_at: row.get("last_used_at"),
+            })
+            .collect())
+    }
+
+    async fn revoke_api_token(&self, user_id: &str, id: &str) -> StoreResult<bool> {
+        let res = sqlx::query("DELETE FROM auth_tokens WHERE id = $1 AND user_id = $2 AND kind = $3")
+            .bind(id)
+            .bind(user_id)
+            .bind(TokenKind::Api.as_str())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    async fn delete_session(&self, token_hash: &str) -> StoreResult<()> {
+        sqlx::query("DELETE FROM auth_tokens WHERE token_hash = $1 AND kind = $2")
+            .bind(token_hash)
+            .bind(TokenKind::Session.as_str())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn acl_index(&self) -> StoreResult<AclIndex> {
+        let nodes = sqlx::query("SELECT id, parent_id, deleted_at IS NULL AS live FROM tabs")
+            .fetch_all(&self.pool)
+            .await?;
+        let grants = sqlx::query(
+            "SELECT a.tab_id, a.user_id, u.username, a.role, a.granted_at
+             FROM tab_acl a JOIN users u ON u.id = a.user_id
+             ORDER BY a.granted_at"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut index = AclIndex::default();
+        for row in &nodes {
+            index.nodes.insert(row.get("id"), AclNode { parent_id: row.get("parent_id"), live: row.get("live") });
+        }
+        for row in &grants {
+            let Some(role) = Role::parse(row.get("role")) else { continue };
+            let grant = Grant {
+                tab_id: row.get("tab_id"),
+                user_id: row.get("user_id"),
+                username: row.get("username"),
+                role,
+                granted_at: row.get("granted_at"),
+            };
+            index.grants.entry(grant.tab_id.clone()).or_default().push(grant);
+        }
+        Ok(index)
+    }
+
+    async fn grant_access(&self, tab_id: &str, user_id: &str, role: Role, granted_at: i64) -> StoreResult<()> {
+        let res = sqlx::query(
+            "INSERT INTO tab_acl (tab_id, user_id, role, granted_at) VALUES ($1, $2, $3, $4)
+             ON CONFLICT (tab_id, user_id) DO UPDATE SET role = excluded.role, granted_at = excluded.granted_at"
+        )
+        .bind(tab_id)
+        .bind(user_id)
+        .bind(role.as_str())
+        .bind(granted_at)
+        .execute(&self.pool)
+        .await;
+
+        match res {
+            Ok(_) => Ok(()),
+            Err(sqlx::Error::Database(e)) if e.is_foreign_key_violation() => {
+                Err(StoreError::NotFound(format!("Tab {} not found", tab_id)))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn revoke_access(&self, tab_id: &str, user_id: &str) -> StoreResult<bool> {
+        let res = sqlx::query("DELETE FROM tab_acl WHERE tab_id = $1 AND user_id = $2")
+            .bind(tab_id)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    async fn create_share(&self, link: &ShareLink, token_hash: &str) -> StoreResult<()> {
+        sqlx::query(
+            "INSERT INTO share_links (id, tab_id, token_hash, created_by, created_at, expires_at)
+             VALUES ($1, $2, $3, $4, $5, $6)"
+        )
+        .bind(&link.id)
+        .bind(&link.tab_id)
+        .bind(token_hash)
+        .bind(&link.created_by)
+        .bind(link.created_at)
+        .bind(link.expires_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn find_share(&self, token_hash: &str, now: i64) -> StoreResult<Option<ShareLink>> {
+        let row = sqlx::query(
+            "SELECT id, tab_id, created_by, created_at, expires_at FROM share_links
+             WHERE token_hash = $1 AND (expires_at IS NULL OR expires_at > $2)"
+        )
+        .bind(token_hash)
+        .bind(now)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.as_ref().map(share_from_row))
+    }
+
+    async fn list_shares(&self, tab_id: &str) -> StoreResult<Vec<ShareLink>> {
+        let rows = sqlx::query(
+            "SELECT id, tab_id, created_by, created_at, expires_at FROM share_links
+             WHERE tab_id = $1 ORDER BY created_at"
+        )
+        .bind(tab_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(share_from_row).collect())
+    }
+
+    async fn revoke_share(&self, tab_id: &str, id: &str) -> StoreResult<bool> {
+        let res = sqlx::query("DELETE FROM share_links WHERE id = $1 AND tab_id = $2")
+            .bind(id)
+            .bind(tab_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+}
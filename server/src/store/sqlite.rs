@@ -0,0 +1,940 @@
+use async_trait::async_trait;
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteRow},
+    Pool, Row, Sqlite, Transaction,
+};
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::time::Duration;
+
+use super::{SaveOutcome, StoreError, StoreResult, TabChange, TabStore};
+use crate::acl::{AclIndex, AclNode, Grant, Role};
+use crate::auth::{ApiToken, NewToken, TokenKind, User};
+use crate::hierarchy::{check_order, cycle_error, splice};
+use crate::links::{extract_links, group_broken_links, BrokenLink, BrokenLinkReport, LinkEntry};
+use crate::revisions::{Revision, RevisionSummary};
+use crate::search::{build_path, collapse_whitespace, mark_hits, strip_html, SearchHit};
+use crate::share::ShareLink;
+use crate::trash::TrashEntry;
+use crate::{now_millis, Tab, TreeNode};
+
+const TAB_COLUMNS: &str = "id, title, content, child_window_id, parent_id, created_at, version";
+
+/// Single-file backend. Writers take the database lock up front
+/// (`BEGIN IMMEDIATE`), which stands in for the row locks and advisory lock
+/// the Postgres store relies on.
+pub struct SqliteStore {
+    pool: Pool<Sqlite>,
+}
+
+impl SqliteStore {
+    pub async fn connect(database_url: &str) -> Result<Self, sqlx::Error> {
+        let options = SqliteConnectOptions::from_str(database_url)?
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            .busy_timeout(Duration::from_secs(5))
+            .foreign_keys(true);
+
+        let pool = SqlitePoolOptions::new()
+            .max_connections(5)
+            .connect_with(options)
+            .await?;
+
+        sqlx::migrate!("./migrations/sqlite").run(&pool).await?;
+
+        Ok(SqliteStore { pool })
+    }
+
+    async fn begin_write(&self) -> Result<Transaction<'static, Sqlite>, sqlx::Error> {
+        self.pool.begin_with("BEGIN IMMEDIATE").await
+    }
+}
+
+fn tab_from_row(row: &SqliteRow) -> Tab {
+    Tab {
+        id: row.get("id"),
+        title: row.get("title"),
+        content: row.get::<Option<String>, _>("content").unwrap_or_default(),
+        child_window_id: row.get("child_window_id"),
+        parent_id: row.get("parent_id"),
+        created_at: row.get("created_at"),
+        version: Some(row.get("version")),
+    }
+}
+
+fn user_from_row(row: &SqliteRow) -> User {
+    User {
+        id: row.get("id"),
+        username: row.get("username"),
+        created_at: row.get("created_at"),
+    }
+}
+
+fn share_from_row(row: &SqliteRow) -> ShareLink {
+    ShareLink {
+        id: row.get("id"),
+        tab_id: row.get("tab_id"),
+        created_by: row.get("created_by"),
+        created_at: row.get("created_at"),
+        expires_at: row.get("expires_at"),
+    }
+}
+
+fn link_entry(row: &SqliteRow) -> LinkEntry {
+    LinkEntry {
+        id: row.get("id"),
+        title: row.get("title"),
+        text: row.get("link_text"),
+    }
+}
+
+/// Turns free text into an FTS5 query that ANDs every word. Each word is
+/// quoted so user input can't be read as FTS5 syntax.
+fn fts_query(query: &str) -> String {
+    query
+        .split_whitespace()
+        .map(|term| format!("\"{}\"", term.replace('"', "")))
+        .filter(|term| term != "\"\"")
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+async fn record_revision(
+    tx: &mut Transaction<'_, Sqlite>,
+    tab_id: &str,
+    title: &str,
+    content: &str,
+) -> Result<(), sqlx::Error> {
+    let latest = sqlx::query(
+        "SELECT rev, (title = ?2 AND content IS ?3) AS unchanged
+         FROM tab_revisions WHERE tab_id = ?1
+         ORDER BY rev DESC LIMIT 1"
+    )
+    .bind(tab_id)
+    .bind(title)
+    .bind(content)
+    .fetch_optional(&mut **tx)
+    .await?;
+
+    let next_rev = match latest {
+        Some(row) if row.get::<bool, _>("unchanged") => return Ok(()),
+        Some(row) => row.get::<i32, _>("rev") + 1,
+        None => 1,
+    };
+
+    sqlx::query(
+        "INSERT INTO tab_revisions (tab_id, rev, title, content, saved_at)
+         VALUES (?1, ?2, ?3, ?4, ?5)"
+    )
+    .bind(tab_id)
+    .bind(next_rev)
+    .bind(title)
+    .bind(content)
+    .bind(now_millis())
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+/// Rebuilds the derived rows for one tab: its outgoing links and its FTS entry.
+async fn sync_derived(
+    tx: &mut Transaction<'_, Sqlite>,
+    tab_id: &str,
+    title: &str,
+    content: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM tab_links WHERE source_id = ?1")
+        .bind(tab_id)
+        .execute(&mut **tx)
+        .await?;
+
+    for (position, link) in extract_links(content).iter().enumerate() {
+        sqlx::query(
+            "INSERT INTO tab_links (source_id, position, target_id, link_text) VALUES (?1, ?2, ?3, ?4)"
+        )
+        .bind(tab_id)
+        .bind(position as i64)
+        .bind(&link.target_id)
+        .bind(&link.text)
+        .execute(&mut **tx)
+        .await?;
+    }
+
+    sqlx::query("DELETE FROM tabs_fts WHERE id = ?1")
+        .bind(tab_id)
+        .execute(&mut **tx)
+        .await?;
+    sqlx::query("INSERT INTO tabs_fts (id, title, body) VALUES (?1, ?2, ?3)")
+        .bind(tab_id)
+        .bind(title)
+        .bind(strip_html(content))
+        .execute(&mut **tx)
+        .await?;
+
+    Ok(())
+}
+
+async fn would_create_cycle(
+    tx: &mut Transaction<'_, Sqlite>,
+    tab_id: &str,
+    parent_id: &str,
+) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query(
+        "WITH RECURSIVE ancestors(id, parent_id) AS (
+            SELECT id, parent_id FROM tabs WHERE id = ?2
+            UNION
+            SELECT t.id, t.parent_id FROM tabs t
+            INNER JOIN ancestors a ON t.id = a.parent_id
+        )
+        SELECT EXISTS (SELECT 1 FROM ancestors WHERE id = ?1) AS cycle"
+    )
+    .bind(tab_id)
+    .bind(parent_id)
+    .fetch_one(&mut **tx)
+    .await?;
+
+    Ok(row.get("cycle"))
+}
+
+async fn set_positions(tx: &mut Transaction<'_, Sqlite>, order: &[String]) -> Result<(), sqlx::Error> {
+    for (position, id) in order.iter().enumerate() {
+        sqlx::query("UPDATE tabs SET position = ?2 WHERE id = ?1")
+            .bind(id)
+            .bind(position as i64)
+            .execute(&mut **tx)
+            .await?;
+    }
+    Ok(())
+}
+
+/// The body of `save_tab`, inside a transaction the caller commits.
+async fn write_tab(
+    tx: &mut Transaction<'_, Sqlite>,
+    tab: &Tab,
+    expected_version: Option<i64>,
+) -> StoreResult<SaveOutcome> {
+    let previous = sqlx::query("SELECT version, parent_id FROM tabs WHERE id = ?1 AND deleted_at IS NULL")
+        .bind(&tab.id)
+        .fetch_optional(&mut **tx)
+        .await?
+        .map(|row| (row.get::<i64, _>("version"), row.get::<Option<String>, _>("parent_id")));
+
+    let saved = sqlx::query(
+        "INSERT INTO tabs (id, title, content, child_window_id, parent_id, created_at, version, position)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, 1, (
+            SELECT COALESCE(MAX(position) + 1, 0) FROM tabs
+            WHERE parent_id IS ?5 AND deleted_at IS NULL
+         ))
+         ON CONFLICT (id) DO UPDATE SET
+            title = ?2,
+            content = ?3,
+            child_window_id = ?4,
+            parent_id = ?5,
+            position = CASE WHEN tabs.parent_id IS NOT ?5 THEN excluded.position ELSE tabs.position END,
+            version = tabs.version + CASE
+                WHEN (tabs.title, tabs.content, tabs.child_window_id, tabs.parent_id)
+                    IS NOT (?2, ?3, ?4, ?5) THEN 1
+                ELSE 0
+            END
+         WHERE tabs.deleted_at IS NULL AND (?7 IS NULL OR tabs.version = ?7)
+         RETURNING version"
+    )
+    .bind(&tab.id)
+    .bind(&tab.title)
+    .bind(&tab.content)
+    .bind(&tab.child_window_id)
+    .bind(&tab.parent_id)
+    .bind(tab.created_at)
+    .bind(expected_version)
+    .fetch_optional(&mut **tx)
+    .await?;
+
+    if let Some(parent_id) = &tab.parent_id {
+        if would_create_cycle(tx, &tab.id, parent_id).await? {
+            return Err(cycle_error(&tab.id, parent_id));
+        }
+    }
+
+    let Some(row) = saved else {
+        let current = sqlx::query(&format!("SELECT {TAB_COLUMNS}, deleted_at FROM tabs WHERE id = ?1"))
+            .bind(&tab.id)
+            .fetch_one(&mut **tx)
+            .await?;
+        if current.get::<Option<i64>, _>("deleted_at").is_some() {
+            return Err(StoreError::Gone(format!("Tab {} is in the trash; restore it before saving", tab.id)));
+        }
+        return Ok(SaveOutcome::Conflict(tab_from_row(&current)));
+    };
+    let version: i64 = row.get("version");
+
+    record_revision(tx, &tab.id, &tab.title, &tab.content).await?;
+    sync_derived(tx, &tab.id, &tab.title, &tab.content).await?;
+
+    let change = TabChange::between(previous, version, &tab.parent_id);
+    Ok(SaveOutcome::Saved { version, change })
+}
+
+#[async_trait]
+impl TabStore for SqliteStore {
+    async fn list_tabs(&self) -> StoreResult<Vec<Tab>> {
+        let rows = sqlx::query(&format!(
+            "SELECT {TAB_COLUMNS} FROM tabs
+             WHERE deleted_at IS NULL
+             ORDER BY position, created_at, id"
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(tab_from_row).collect())
+    }
+
+    async fn list_tree(&self) -> StoreResult<Vec<TreeNode>> {
+        let rows = sqlx::query(
+            "SELECT id, title, parent_id, child_window_id, created_at FROM tabs
+             WHERE deleted_at IS NULL
+             ORDER BY position, created_at, id"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(|row| TreeNode {
+            id: row.get("id"),
+            title: row.get("title"),
+            parent_id: row.get("parent_id"),
+            child_window_id: row.get("child_window_id"),
+            created_at: row.get("created_at"),
+        }).collect())
+    }
+
+    async fn get_tab(&self, id: &str) -> StoreResult<Option<Tab>> {
+        let row = sqlx::query(&format!("SELECT {TAB_COLUMNS} FROM tabs WHERE id = ?1 AND deleted_at IS NULL"))
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.as_ref().map(tab_from_row))
+    }
+
+    async fn save_tab(&self, tab: &Tab, expected_version: Option<i64>) -> StoreResult<SaveOutcome> {
+        let mut tx = self.begin_write().await?;
+        let outcome = write_tab(&mut tx, tab, expected_version).await?;
+        if let SaveOutcome::Saved { .. } = outcome {
+            tx.commit().await?;
+        }
+        Ok(outcome)
+    }
+
+    async fn import_tabs(&self, tabs: &[Tab]) -> StoreResult<u64> {
+        let mut tx = self.begin_write().await?;
+        for tab in tabs {
+            if let SaveOutcome::Conflict(_) = write_tab(&mut tx, tab, None).await? {
+                return Err(StoreError::Conflict(format!("Tab {} changed during the import", tab.id)));
+            }
+        }
+        tx.commit().await?;
+        Ok(tabs.len() as u64)
+    }
+
+    async fn delete_tab(&self, id: &str) -> StoreResult<u64> {
+        let res = sqlx::query(
+            "WITH RECURSIVE tab_tree(id) AS (
+                SELECT id FROM tabs WHERE id = ?1 AND deleted_at IS NULL
+                UNION
+                SELECT t.id FROM tabs t
+                INNER JOIN tab_tree tt ON t.parent_id = tt.id
+                WHERE t.deleted_at IS NULL
+            )
+            UPDATE tabs SET deleted_at = ?2, deleted_root = ?1
+            WHERE id IN (SELECT id FROM tab_tree)"
+        )
+        .bind(id)
+        .bind(now_millis())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(res.rows_affected())
+    }
+
+    async fn move_tab(&self, id: &str, parent_id: Option<&str>, position: Option<usize>) -> StoreResult<Tab> {
+        let mut tx = self.begin_write().await?;
+
+        let exists = sqlx::query("SELECT 1 FROM tabs WHERE id = ?1 AND deleted_at IS NULL")
+            .bind(id)
+            .fetch_optional(&mut *tx)
+            .await?;
+        if exists.is_none() {
+            return Err(StoreError::NotFound(format!("Tab {} not found", id)));
+        }
+
+        if let Some(parent_id) = parent_id {
+            let parent = sqlx::query("SELECT 1 FROM tabs WHERE id = ?1 AND deleted_at IS NULL")
+                .bind(parent_id)
+                .fetch_optional(&mut *tx)
+                .await?;
+            if parent.is_none() {
+                return Err(StoreError::NotFound(format!("Target parent {} not found", parent_id)));
+            }
+            if would_create_cycle(&mut tx, id, parent_id).await? {
+                return Err(cycle_error(id, parent_id));
+            }
+        }
+
+        let siblings = sqlx::query(
+            "SELECT id FROM tabs
+             WHERE parent_id IS ?1 AND deleted_at IS NULL AND id <> ?2
+             ORDER BY position, created_at, id"
+        )
+        .bind(parent_id)
+        .bind(id)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let order = splice(siblings.iter().map(|row| row.get("id")).collect(), id, position);
+        set_positions(&mut tx, &order).await?;
+
+        let row = sqlx::query(&format!(
+            "UPDATE tabs SET parent_id = ?2,
+                version = version + CASE WHEN parent_id IS NOT ?2 THEN 1 ELSE 0 END
+             WHERE id = ?1
+             RETURNING {TAB_COLUMNS}"
+        ))
+        .bind(id)
+        .bind(parent_id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(tab_from_row(&row))
+    }
+
+    async fn reorder_window(&self, parent_id: Option<&str>, order: &[String]) -> StoreResult<()> {
+        let mut tx = self.begin_write().await?;
+
+        let rows = sqlx::query("SELECT id FROM tabs WHERE parent_id IS ?1 AND deleted_at IS NULL")
+            .bind(parent_id)
+            .fetch_all(&mut *tx)
+            .await?;
+
+        let current: HashSet<String> = rows.iter().map(|row| row.get("id")).collect();
+        check_order(&current, order)?;
+        set_positions(&mut tx, order).await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn list_revisions(&self, id: &str) -> StoreResult<Vec<RevisionSummary>> {
+        let rows = sqlx::query(
+            "SELECT rev, title, saved_at FROM tab_revisions WHERE tab_id = ?1 ORDER BY rev DESC"
+        )
+        .bind(id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(|row| RevisionSummary {
+            rev: row.get("rev"),
+            title: row.get("title"),
+            saved_at: row.get("saved_at"),
+        }).collect())
+    }
+
+    async fn get_revision(&self, id: &str, rev: i32) -> StoreResult<Option<Revision>> {
+        let row = sqlx::query(
+            "SELECT tab_id, rev, title, content, saved_at FROM tab_revisions WHERE tab_id = ?1 AND rev = ?2"
+        )
+        .bind(id)
+        .bind(rev)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|row| Revision {
+            tab_id: row.get("tab_id"),
+            rev: row.get("rev"),
+            title: row.get("title"),
+            content: row.get::<Option<String>, _>("content").unwrap_or_default(),
+            saved_at: row.get("saved_at"),
+        }))
+    }
+
+    async fn search(&self, query: &str, limit: i64) -> StoreResult<Vec<SearchHit>> {
+        let fts = fts_query(query);
+        if fts.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // bm25() is "lower is better"; weights favour the title like the
+        // Postgres index does. Column 0 is the unindexed id.
+        let rows = sqlx::query(
+            "SELECT t.id, t.title, t.parent_id,
+                    -bm25(tabs_fts, 0.0, 10.0, 1.0) AS rank,
+                    snippet(tabs_fts, 2, char(57344), char(57345), ' … ', 25) AS snippet
+             FROM tabs_fts
+             INNER JOIN tabs t ON t.id = tabs_fts.id
+             WHERE tabs_fts MATCH ?1 AND t.deleted_at IS NULL
+             ORDER BY rank DESC, t.title
+             LIMIT ?2"
+        )
+        .bind(&fts)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let parent_ids: Vec<String> = rows
+            .iter()
+            .filter_map(|row| row.get::<Option<String>, _>("parent_id"))
+            .collect();
+
+        let ancestor_rows = sqlx::query(
+            "WITH RECURSIVE ancestors(id, title, parent_id) AS (
+                SELECT id, title, parent_id FROM tabs WHERE id IN (SELECT value FROM json_each(?1))
+                UNION
+                SELECT t.id, t.title, t.parent_id FROM tabs t
+                INNER JOIN ancestors a ON t.id = a.parent_id
+            )
+            SELECT id, title, parent_id FROM ancestors"
+        )
+        .bind(serde_json::to_string(&parent_ids).unwrap_or_default())
+        .fetch_all(&self.pool)
+        .await?;
+        let ancestors: HashMap<String, (String, Option<String>)> = ancestor_rows
+            .iter()
+            .map(|row| (row.get("id"), (row.get("title"), row.get("parent_id"))))
+            .collect();
+
+        Ok(rows.iter().map(|row| SearchHit {
+            id: row.get("id"),
+            title: row.get("title"),
+            snippet: mark_hits(&collapse_whitespace(&row.get::<String, _>("snippet"))),
+            rank: row.get::<f64, _>("rank") as f32,
+            path: build_path(&ancestors, row.get("parent_id")),
+        }).collect())
+    }
+
+    async fn links(&self, id: &str) -> StoreResult<Vec<LinkEntry>> {
+        let rows = sqlx::query(
+            "SELECT l.target_id AS id, t.title, l.link_text FROM tab_links l
+             LEFT JOIN tabs t ON t.id = l.target_id AND t.deleted_at IS NULL
+             WHERE l.source_id = ?1
+             ORDER BY l.position"
+        )
+        .bind(id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(link_entry).collect())
+    }
+
+    async fn backlinks(&self, id: &str) -> StoreResult<Vec<LinkEntry>> {
+        let rows = sqlx::query(
+            "SELECT l.source_id AS id, t.title, l.link_text FROM tab_links l
+             INNER JOIN tabs t ON t.id = l.source_id AND t.deleted_at IS NULL
+             WHERE l.target_id = ?1
+             ORDER BY t.title, l.position"
+        )
+        .bind(id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(link_entry).collect())
+    }
+
+    async fn broken_links(&self) -> StoreResult<Vec<BrokenLinkReport>> {
+        let rows = sqlx::query(
+            "SELECT s.id, s.title, s.parent_id, l.target_id, l.link_text FROM tab_links l
+             INNER JOIN tabs s ON s.id = l.source_id AND s.deleted_at IS NULL
+             WHERE NOT EXISTS (SELECT 1 FROM tabs t WHERE t.id = l.target_id AND t.deleted_at IS NULL)
+             ORDER BY s.title, s.id, l.position"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(group_broken_links(rows.iter().map(|row| (
+            row.get("id"),
+            row.get("title"),
+            row.get("parent_id"),
+            BrokenLink { target_id: row.get("target_id"), text: row.get("link_text") },
+        ))))
+    }
+
+    async fn backfill_links(&self) -> StoreResult<u64> {
+        // The schema and the derived rows were born together here, so the only
+        // gap to fill is tabs inserted behind the application's back.
+        let rows = sqlx::query(
+            "SELECT id, title, content FROM tabs t
+             WHERE NOT EXISTS (SELECT 1 FROM tabs_fts f WHERE f.id = t.id)"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut tx = self.begin_write().await?;
+        for row in &rows {
+            let content: Option<String> = row.get("content");
+            sync_derived(&mut tx, row.get("id"), row.get("title"), &content.unwrap_or_default()).await?;
+        }
+        tx.commit().await?;
+
+        Ok(rows.len() as u64)
+    }
+
+    async fn list_trash(&self) -> StoreResult<Vec<TrashEntry>> {
+        let rows = sqlx::query(
+            "SELECT t.id, t.title, t.parent_id, t.deleted_at,
+                    (SELECT COUNT(*) FROM tabs d WHERE d.deleted_root = t.id) AS tab_count
+             FROM tabs t
+             WHERE t.deleted_root = t.id
+             ORDER BY t.deleted_at DESC"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(|row| TrashEntry {
+            id: row.get("id"),
+            title: row.get("title"),
+            parent_id: row.get("parent_id"),
+            deleted_at: row.get("deleted_at"),
+            tab_count: row.get("tab_count"),
+        }).collect())
+    }
+
+    async fn restore_from_trash(&self, id: &str) -> StoreResult<u64> {
+        let mut tx = self.begin_write().await?;
+
+        let row = sqlx::query(
+            "SELECT t.parent_id, p.deleted_at AS parent_deleted_at FROM tabs t
+             LEFT JOIN tabs p ON p.id = t.parent_id
+             WHERE t.id = ?1 AND t.deleted_root = ?1"
+        )
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| StoreError::NotFound(format!("No trash entry for tab {}", id)))?;
+
+        if row.get::<Option<i64>, _>("parent_deleted_at").is_some() {
+            let parent_id: String = row.get("parent_id");
+            return Err(StoreError::Conflict(format!("Parent {} is in the trash; restore it first", parent_id)));
+        }
+
+        let res = sqlx::query("UPDATE tabs SET deleted_at = NULL, deleted_root = NULL WHERE deleted_root = ?1")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(res.rows_affected())
+    }
+
+    async fn purge_trash(&self, cutoff: i64, roots: &[String]) -> StoreResult<u64> {
+        let mut tx = self.begin_write().await?;
+        let roots = serde_json::to_string(roots).unwrap_or_default();
+
+        // FTS5 tables can't take part in foreign keys, so clean them up by hand.
+        sqlx::query(
+            "DELETE FROM tabs_fts WHERE id IN (
+                SELECT id FROM tabs WHERE deleted_at IS NOT NULL AND deleted_at <= ?1
+                    AND deleted_root IN (SELECT value FROM json_each(?2))
+            )"
+        )
+        .bind(cutoff)
+        .bind(&roots)
+        .execute(&mut *tx)
+        .await?;
+
+        let res = sqlx::query(
+            "DELETE FROM tabs WHERE deleted_at IS NOT NULL AND deleted_at <= ?1
+                AND deleted_root IN (SELECT value FROM json_each(?2))"
+        )
+        .bind(cutoff)
+        .bind(&roots)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(res.rows_affected())
+    }
+
+    async fn count_users(&self) -> StoreResult<i64> {
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM users")
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(count)
+    }
+
+    async fn create_user(&self, user: &User, password_hash: &str) -> StoreResult<()> {
+        let res = sqlx::query(
+            "INSERT INTO users (id, username, password_hash, created_at) VALUES (?1, ?2, ?3, ?4)"
+        )
+        .bind(&user.id)
+        .bind(&user.username)
+        .bind(password_hash)
+        .bind(user.created_at)
+        .execute(&self.pool)
+        .await;
+
+        match res {
+            Ok(_) => Ok(()),
+            Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+                Err(StoreError::Conflict(format!("Username {} is taken", user.username)))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn create_first_user(&self, user: &User, password_hash: &str) -> StoreResult<bool> {
+        // One statement, and SQLite runs one writer at a time.
+        let res = sqlx::query(
+            "INSERT INTO users (id, username, password_hash, created_at)
+             SELECT ?1, ?2, ?3, ?4 WHERE NOT EXISTS (SELECT 1 FROM users)"
+        )
+        .bind(&user.id)
+        .bind(&user.username)
+        .bind(password_hash)
+        .bind(user.created_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(res.rows_affected() == 1)
+    }
+
+    async fn find_login(&self, username: &str) -> StoreResult<Option<(User, String)>> {
+        let row = sqlx::query("SELECT id, username, created_at, password_hash FROM users WHERE username = ?1")
+            .bind(username)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.map(|row| (user_from_row(&row), row.get("password_hash"))))
+    }
+
+    async fn create_token(&self, token: &NewToken) -> StoreResult<()> {
+        sqlx::query("DELETE FROM auth_tokens WHERE expires_at IS NOT NULL AND expires_at <= ?1")
+            .bind(token.created_at)
+            .execute(&self.pool)
+            .await?;
+
+        sqlx::query(
+            "INSERT INTO auth_tokens (id, user_id, token_hash, kind, name, created_at, expires_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)"
+        )
+        .bind(&token.id)
+        .bind(&token.user_id)
+        .bind(&token.token_hash)
+        .bind(token.kind.as_str())
+        .bind(&token.name)
+        .bind(token.created_at)
+        .bind(token.expires_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn token_user(&self, token_hash: &str, now: i64) -> StoreResult<Option<User>> {
+        let row = sqlx::query(
+            "UPDATE auth_tokens SET last_used_at = ?2
+             WHERE token_hash = ?1 AND (expires_at IS NULL OR expires_at > ?2)
+             RETURNING user_id"
+        )
+        .bind(token_hash)
+        .bind(now)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        let Some(row) = row else { return Ok(None) };
+        let user_id: String = row.get("user_id");
+        let user = sqlx::query("SELECT id, username, created_at FROM users WHERE id = ?1")
+            .bind(&user_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(user.as_ref().map(user_from_row))
+    }
+
+    async fn list_api_tokens(&self, user_id: &str) -> StoreResult<Vec<ApiToken>> {
+        let rows = sqlx::query(
+            "SELECT id, name, created_at, expires_at, last_used_at FROM auth_tokens
+             WHERE user_id = ?1 AND kind = ?2
+             ORDER BY created_at"
+        )
+        .bind(user_id)
+        .bind(TokenKind::Api.as_str())
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| ApiToken {
+                id: row.get("id"),
+                name: row.get("name"),
+                created_at: row.get("created_at"),
+                expires_at: row.get("expires_at"),
+                last_used_at: row.get("last_used_at"),
+            })
+            .collect())
+    }
+
+    async fn revoke_api_token(&self, user_id: &str, id: &str) -> StoreResult<bool> {
+        let res = sqlx::query("DELETE FROM auth_tokens WHERE id = ?1 AND user_id = ?2 AND kind = ?3")
+            .bind(id)
+            .bind(user_id)
+            .bind(TokenKind::Api.as_str())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    async fn delete_session(&self, token_hash: &str) -> StoreResult<()> {
+        sqlx::query("DELETE FROM auth_tokens WHERE token_hash = ?1 AND kind = ?2")
+            .bind(token_hash)
+            .bind(TokenKind::Session.as_str())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn acl_index(&self) -> StoreResult<AclIndex> {
+        let nodes = sqlx::query("SELECT id, parent_id, deleted_at IS NULL AS live FROM tabs")
+            .fetch_all(&self.pool)
+            .await?;
+        let grants = sqlx::query(
+            "SELECT a.tab_id, a.user_id, u.username, a.role, a.granted_at
+             FROM tab_acl a JOIN users u ON u.id = a.user_id
+             ORDER BY a.granted_at"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut index = AclIndex::default();
+        for row in &nodes {
+            index.nodes.insert(row.get("id"), AclNode { parent_id: row.get("parent_id"), live: row.get("live") });
+        }
+        for row in &grants {
+            let Some(role) = Role::parse(row.get("role")) else { continue };
+            let grant = Grant {
+                tab_id: row.get("tab_id"),
+                user_id: row.get("user_id"),
+                username: row.get("username"),
+                role,
+                granted_at: row.get("granted_at"),
+            };
+            index.grants.entry(grant.tab_id.clone()).or_default().push(grant);
+        }
+        Ok(index)
+    }
+
+    async fn grant_access(&self, tab_id: &str, user_id: &str, role: Role, granted_at: i64) -> StoreResult<()> {
+        let res = sqlx::query(
+            "INSERT INTO tab_acl (tab_id, user_id, role, granted_at) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (tab_id, user_id) DO UPDATE SET role = excluded.role, granted_at = excluded.granted_at"
+        )
+        .bind(tab_id)
+        .bind(user_id)
+        .bind(role.as_str())
+        .bind(granted_at)
+        .execute(&self.pool)
+        .await;
+
+        match res {
+            Ok(_) => Ok(()),
+            Err(sqlx::Error::Database(e)) if e.is_foreign_key_violation() => {
+                Err(StoreError::NotFound(format!("Tab {} not found", tab_id)))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn revoke_access(&self, tab_id: &str, user_id: &str) -> StoreResult<bool> {
+        let res = sqlx::query("DELETE FROM tab_acl WHERE tab_id = ?1 AND user_id = ?2")
+            .bind(tab_id)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    async fn create_share(&self, link: &ShareLink, token_hash: &str) -> StoreResult<()> {
+        sqlx::query(
+            "INSERT INTO share_links (id, tab_id, token_hash, created_by, created_at, expires_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)"
+        )
+        .bind(&link.id)
+        .bind(&link.tab_id)
+        .bind(token_hash)
+        .bind(&link.created_by)
+        .bind(link.created_at)
+        .bind(link.expires_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn find_share(&self, token_hash: &str, now: i64) -> StoreResult<Option<ShareLink>> {
+        let row = sqlx::query(
+            "SELECT id, tab_id, created_by, created_at, expires_at FROM share_links
+             WHERE token_hash = ?1 AND (expires_at IS NULL OR expires_at > ?2)"
+        )
+        .bind(token_hash)
+        .bind(now)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.as_ref().map(share_from_row))
+    }
+
+    async fn list_shares(&self, tab_id: &str) -> StoreResult<Vec<ShareLink>> {
+        let rows = sqlx::query(
+            "SELECT id, tab_id, created_by, created_at, expires_at FROM share_links
+             WHERE tab_id = ?1 ORDER BY created_at"
+        )
+        .bind(tab_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(share_from_row).collect())
+    }
+
+    async fn revoke_share(&self, tab_id: &str, id: &str) -> StoreResult<bool> {
+        let res = sqlx::query("DELETE FROM share_links WHERE id = ?1 AND tab_id = ?2")
+            .bind(id)
+            .bind(tab_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fts_queries_quote_every_term() {
+        assert_eq!(fts_query("black hole"), "\"black\" \"hole\"");
+        assert_eq!(fts_query("say \"NEAR\" OR \"\""), "\"say\" \"NEAR\" \"OR\"");
+        assert_eq!(fts_query("   "), "");
+    }
+
+    #[tokio::test]
+    async fn reopening_a_database_keeps_its_data_and_schema() {
+        let path = std::env::temp_dir().join(format!("miller-{}.db", crate::auth::random_hex(8)));
+        let url = format!("sqlite://{}", path.display());
+
+        let store = SqliteStore::connect(&url).await.unwrap();
+        store.save_tab(&crate::testing::tab("a", None, "<p>kept</p>"), None).await.unwrap();
+        store.pool.close().await;
+
+        // The migrations already ran, so the second open must skip them cleanly.
+        let store = SqliteStore::connect(&url).await.unwrap();
+        assert_eq!(store.get_tab("a").await.unwrap().unwrap().content, "<p>kept</p>");
+        assert_eq!(store.list_revisions("a").await.unwrap().len(), 1);
+        store.pool.close().await;
+
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+}
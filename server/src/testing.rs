@@ -0,0 +1,47 @@
+//! Fixtures for the unit tests: stores that need no server, and an app state
+//! around them for calling handlers directly.
+
+use std::sync::Arc;
+
+use crate::attachments::Attachments;
+use crate::auth::{random_hex, User};
+use crate::events::Events;
+use crate::store::{self, TabStore};
+use crate::{collab, AppState, Tab};
+
+/// Every backend that runs without a server, freshly migrated.
+pub async fn stores() -> Vec<(&'static str, Arc<dyn TabStore>)> {
+    vec![
+        ("memory", store::connect("memory://").await.unwrap()),
+        ("sqlite", store::connect("sqlite::memory:").await.unwrap()),
+    ]
+}
+
+pub fn state(store: Arc<dyn TabStore>) -> AppState {
+    AppState {
+        store,
+        events: Events::local(),
+        collab: collab::Rooms::default(),
+        attachments: Attachments::scratch(),
+    }
+}
+
+/// A tab titled after its id.
+pub fn tab(id: &str, parent_id: Option<&str>, content: &str) -> Tab {
+    Tab {
+        id: id.to_string(),
+        title: id.to_string(),
+        content: content.to_string(),
+        child_window_id: Some(id.to_string()),
+        parent_id: parent_id.map(str::to_string),
+        created_at: 1,
+        version: None,
+    }
+}
+
+/// A stored user, so grants to them can be made.
+pub async fn user(store: &Arc<dyn TabStore>, username: &str) -> User {
+    let user = User { id: random_hex(16), username: username.to_string(), created_at: 1 };
+    store.create_user(&user, "").await.unwrap();
+    user
+}
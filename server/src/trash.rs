@@ -0,0 +1,193 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+use crate::acl::{self, AclIndex, Role};
+use crate::auth::CurrentUser;
+use crate::events::ChangeEvent;
+use crate::{now_millis, store_error, AppState};
+
+const DEFAULT_RETENTION_DAYS: i64 = 30;
+const DAY_MILLIS: i64 = 24 * 60 * 60 * 1000;
+
+/// One delete operation: the tab the user deleted plus everything it took down.
+#[derive(Serialize)]
+pub struct TrashEntry {
+    pub id: String,
+    pub title: String,
+    pub parent_id: Option<String>,
+    pub deleted_at: i64,
+    /// Number of tabs removed by this deletion, the root included.
+    pub tab_count: i64,
+}
+
+#[derive(Deserialize)]
+pub struct PurgeParams {
+    older_than_days: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct TrashResult {
+    tab_count: u64,
+}
+
+/// Days a deleted subtree stays restorable, from `TRASH_RETENTION_DAYS`.
+fn retention_days() -> i64 {
+    std::env::var("TRASH_RETENTION_DAYS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_RETENTION_DAYS)
+}
+
+pub async fn list_trash(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser
+) -> Result<Json<Vec<TrashEntry>>, (StatusCode, String)> {
+    let index = acl::load(&state).await?;
+    let mut entries = state.store.list_trash().await.map_err(|e| store_error("List Trash", e))?;
+    entries.retain(|entry| index.can(&entry.id, &user.id, Role::Viewer));
+    Ok(Json(entries))
+}
+
+/// Brings back the subtree removed by deleting `id`. Only works on the tab the
+/// user actually deleted, not on one of its descendants.
+pub async fn restore_from_trash(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<String>
+) -> Result<Json<TrashResult>, (StatusCode, String)> {
+    acl::require(&acl::load(&state).await?, &id, &user, Role::Editor)?;
+    let tab_count = state.store.restore_from_trash(&id).await.map_err(|e| store_error("Restore Trash", e))?;
+
+    println!("♻️ Restored {} records from trash", tab_count);
+    state.events.publish(ChangeEvent::Restored { id, count: tab_count }).await;
+    Ok(Json(TrashResult { tab_count }))
+}
+
+/// Permanently removes trashed tabs older than the retention period
+/// (`?older_than_days=` overrides it). Revisions and links go with them.
+/// Only entries the caller owns are purged.
+pub async fn purge_trash(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Query(params): Query<PurgeParams>
+) -> Result<Json<TrashResult>, (StatusCode, String)> {
+    let days = params.older_than_days.unwrap_or_else(retention_days).max(0);
+    // A period longer than time itself just purges nothing.
+    let cutoff = now_millis().saturating_sub(days.saturating_mul(DAY_MILLIS));
+
+    let index = acl::load(&state).await?;
+    let entries = state.store.list_trash().await.map_err(|e| store_error("Purge Trash", e))?;
+    let roots = purgeable(&index, &entries, cutoff, &user.id);
+    let tab_count = if roots.is_empty() {
+        0
+    } else {
+        state.store.purge_trash(cutoff, &roots).await.map_err(|e| store_error("Purge Trash", e))?
+    };
+
+    println!("🔥 {} purged {} records from trash", user.username, tab_count);
+    if tab_count > 0 {
+        state.events.publish(ChangeEvent::Purged { count: tab_count }).await;
+    }
+    Ok(Json(TrashResult { tab_count }))
+}
+
+/// Trash entries old enough to go that `user_id` owns. An entry holding an
+/// older one that stays (deleted before its parent was) stays too, so nothing
+/// is left pointing at a purged parent.
+fn purgeable(index: &AclIndex, entries: &[TrashEntry], cutoff: i64, user_id: &str) -> Vec<String> {
+    let mut roots: HashSet<&str> = HashSet::new();
+    let mut kept: Vec<&str> = Vec::new();
+    for entry in entries {
+        if entry.deleted_at <= cutoff && index.can(&entry.id, user_id, Role::Owner) {
+            roots.insert(&entry.id);
+        } else {
+            kept.push(&entry.id);
+        }
+    }
+    for id in kept {
+        for ancestor in index.lineage(id) {
+            roots.remove(ancestor);
+        }
+    }
+    entries.iter().map(|entry| entry.id.clone()).filter(|id| roots.contains(id.as_str())).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::User;
+    use crate::testing::{state, stores, tab, user};
+
+    async fn purge(state: &AppState, user: &User, days: i64) -> u64 {
+        let params = PurgeParams { older_than_days: Some(days) };
+        let Json(result) = purge_trash(State(state.clone()), CurrentUser(user.clone()), Query(params)).await.unwrap();
+        result.tab_count
+    }
+
+    #[tokio::test]
+    async fn extreme_retention_periods_neither_overflow_nor_purge_early() {
+        for (name, store) in stores().await {
+            let ann = user(&store, "ann").await;
+            store.save_tab(&tab("a", None, ""), None).await.unwrap();
+            store.delete_tab("a").await.unwrap();
+
+            let state = state(store);
+            assert_eq!(purge(&state, &ann, i64::MAX).await, 0, "{}", name);
+            assert_eq!(purge(&state, &ann, i64::MIN).await, 1, "{}", name);
+        }
+    }
+
+    fn entry(id: &str, deleted_at: i64) -> TrashEntry {
+        TrashEntry { id: id.to_string(), title: id.to_string(), parent_id: None, deleted_at, tab_count: 1 }
+    }
+
+    #[test]
+    fn an_entry_still_holding_a_younger_one_is_not_purgeable() {
+        // `child` went to the trash before `root` did; `late` is too young and
+        // `theirs` is bob's.
+        let mut index = AclIndex::default();
+        for (id, parent_id) in [("root", None), ("child", Some("root")), ("late", None), ("theirs", None)] {
+            index.nodes.insert(id.to_string(), acl::AclNode { parent_id: parent_id.map(str::to_string), live: false });
+        }
+        let grant = acl::Grant { tab_id: "theirs".to_string(), user_id: "bob".to_string(), username: "bob".to_string(), role: Role::Owner, granted_at: 1 };
+        index.grants.insert("theirs".to_string(), vec![grant]);
+
+        let entries = [entry("late", 50), entry("root", 20), entry("child", 10), entry("theirs", 10)];
+        assert_eq!(purgeable(&index, &entries, 30, "ann"), ["root", "child"]);
+        assert_eq!(purgeable(&index, &entries, 30, "bob"), ["root", "child", "theirs"]);
+
+        // Once `late` sits under `root`, `root` has to wait for it.
+        index.nodes.insert("late".to_string(), acl::AclNode { parent_id: Some("root".to_string()), live: false });
+        assert_eq!(purgeable(&index, &entries, 30, "ann"), ["child"]);
+    }
+
+    #[tokio::test]
+    async fn only_owners_purge_and_only_they_see_restricted_trash() {
+        for (name, store) in stores().await {
+            let ann = user(&store, "ann").await;
+            let bob = user(&store, "bob").await;
+            store.save_tab(&tab("a", None, ""), None).await.unwrap();
+            store.grant_access("a", &ann.id, Role::Owner, 1).await.unwrap();
+            store.grant_access("a", &bob.id, Role::Editor, 1).await.unwrap();
+            store.delete_tab("a").await.unwrap();
+
+            let state = state(store);
+            let Json(listed) = list_trash(State(state.clone()), CurrentUser(bob.clone())).await.unwrap();
+            assert_eq!(listed.len(), 1, "{}", name);
+            assert_eq!(purge(&state, &bob, 0).await, 0, "{}", name);
+
+            state.store.revoke_access("a", &bob.id).await.unwrap();
+            let Json(listed) = list_trash(State(state.clone()), CurrentUser(bob.clone())).await.unwrap();
+            assert!(listed.is_empty(), "{}", name);
+            let restored = restore_from_trash(State(state.clone()), CurrentUser(bob), Path("a".to_string())).await;
+            assert_eq!(restored.err().map(|(status, _)| status), Some(StatusCode::NOT_FOUND), "{}", name);
+
+            assert_eq!(purge(&state, &ann, 0).await, 1, "{}", name);
+        }
+    }
+}
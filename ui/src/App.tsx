@@ -17,11 +17,18 @@ import { Link } from '@tiptap/extension-link';
 import type { Tab, WindowData, SortMode, SaveStatus } from './types';
 import { WikiLink } from './extensions/WikiLink';
 import ExportModal from './components/ExportModal';
+import LoginModal from './components/LoginModal';
 import EditorToolbar from './components/EditorToolbar';
 import './App.css';
 
 const API_URL = "http://localhost:8080";
 
+// First attachment image in a tab, shown as a small preview in its column.
+const thumbnailFor = (content: string): string | null => {
+  const match = content.match(/\/attachments\/([0-9a-f]{64})/);
+  return match ? `${API_URL}/attachments/${match[1]}/thumbnail` : null;
+};
+
 export default function App() {
   // --- CORE STATE ---
   const [windows, setWindows] = useState<Record<string, WindowData>>({ 'root': { id: 'root', tabs: [] } });
@@ -34,10 +41,18 @@ export default function App() {
   const [globalSortMode, setGlobalSortMode] = useState<SortMode>('oldest');
   const [isDarkMode, setIsDarkMode] = useState<boolean>(() => localStorage.getItem('theme') === 'dark');
   const [isExportModalOpen, setIsExportModalOpen] = useState(false);
+  const [needsLogin, setNeedsLogin] = useState(false);
+  const [sessionKey, setSessionKey] = useState(0);
   
   // --- SYNC STATE ---
   const [saveStatus, setSaveStatus] = useState<SaveStatus>('saved');
   const [lastSaved, setLastSaved] = useState<string | null>(null);
+  // Server version of each tab as last loaded or saved, sent back with every
+  // save so a stale copy can't overwrite a newer one. Kept out of `windows`
+  // so recording a version doesn't trigger another autosave.
+  const versions = useRef<Record<string, number>>({});
+  // Tabs whose save was refused as stale, with the version the server has.
+  const [conflicts, setConflicts] = useState<Record<string, number>>({});
   
   const isInitialMount = useRef(true);
   const fileInputRef = useRef<HTMLInputElement>(null);
@@ -52,9 +67,12 @@ export default function App() {
   useEffect(() => {
     const loadFromDb = async () => {
       try {
-        const res = await fetch(`${API_URL}/tabs`);
+        const res = await fetch(`${API_URL}/tabs`, { credentials: 'include' });
+        if (res.status === 401) { setNeedsLogin(true); return; }
         const dbTabs: any[] = await res.json();
         if (!dbTabs || dbTabs.length === 0) return;
+        versions.current = Object.fromEntries(dbTabs.map(t => [t.id, Number(t.version)]));
+        setConflicts({});
 
         const newWindows: Record<string, WindowData> = { 'root': { id: 'root', tabs: [] } };
         dbTabs.forEach(t => { if (t.id) newWindows[t.id] = { id: t.id, tabs: [], collapsed: false }; });
@@ -64,14 +82,15 @@ export default function App() {
           if (!newWindows[targetWinId]) newWindows[targetWinId] = { id: targetWinId, tabs: [], collapsed: false };
           newWindows[targetWinId].tabs.push({
             id: t.id, title: t.title, content: t.content, 
-            createdAt: Number(t.created_at), parentId: t.parent_id
+            createdAt: Number(t.created_at), parentId: t.parent_id,
+            readOnly: t.role === 'viewer'
           });
         });
         setWindows(newWindows);
       } catch (e) { console.error("❌ DB Load failed", e); }
     };
     loadFromDb();
-  }, []);
+  }, [sessionKey]);
 
   // --- 3. AUTO-SAVE EFFECT ---
   useEffect(() => {
@@ -79,20 +98,29 @@ export default function App() {
     setSaveStatus('saving');
     const timer = setTimeout(async () => {
       try {
+        const stale: Record<string, number> = {};
         const promises = Object.entries(windows).flatMap(([winId, win]) => 
-          win.tabs.map(tab => 
-            fetch(`${API_URL}/tabs`, {
+          win.tabs.filter(tab => !tab.readOnly).map(async tab => {
+            const res = await fetch(`${API_URL}/tabs`, {
               method: 'POST',
+              credentials: 'include',
               headers: { 'Content-Type': 'application/json' },
               body: JSON.stringify({ 
                 id: tab.id, title: tab.title, content: tab.content, 
                 parent_id: winId === 'root' ? null : winId, 
-                child_window_id: tab.id, created_at: tab.createdAt 
+                child_window_id: tab.id, created_at: tab.createdAt,
+                version: versions.current[tab.id]
               }),
-            })
-          )
+            });
+            // A 409 carries the copy the server has instead.
+            if (res.ok) versions.current[tab.id] = (await res.json()).version;
+            else if (res.status === 409) stale[tab.id] = Number((await res.json()).version);
+            return res;
+          })
         );
-        await Promise.all(promises);
+        const responses = await Promise.all(promises);
+        if (responses.some(r => r.status === 401)) { setNeedsLogin(true); setSaveStatus('error'); return; }
+        if (Object.keys(stale).length > 0) { setConflicts(stale); setSaveStatus('conflict'); return; }
         setSaveStatus('saved');
         setLastSaved(new Date().toLocaleTimeString([], { hour: '2-digit', minute: '2-digit', second: '2-digit' }));
       } catch (e) { setSaveStatus('error'); }
@@ -100,6 +128,15 @@ export default function App() {
     return () => clearTimeout(timer);
   }, [windows]);
 
+  // Someone else saved these tabs since they were loaded. Either take their
+  // copy (dropping the edits made here) or save over it on purpose.
+  const reloadConflicts = () => setSessionKey(k => k + 1);
+  const overwriteConflicts = () => {
+    versions.current = { ...versions.current, ...conflicts };
+    setConflicts({});
+    setWindows(p => ({ ...p }));
+  };
+
   const collapseAllWindows = () => {
     setWindows(prev => {
       const next = { ...prev };
@@ -203,11 +240,13 @@ export default function App() {
   useEffect(() => {
     if (editor && activeTabId) {
       let content = "";
+      let readOnly = false;
       for (const winId in windows) {
         const tab = windows[winId].tabs.find(t => t.id === activeTabId);
-        if (tab) { content = tab.content; break; }
+        if (tab) { content = tab.content; readOnly = !!tab.readOnly; break; }
       }
       if (content !== editor.getHTML()) editor.commands.setContent(content);
+      if (editor.isEditable === readOnly) editor.setEditable(!readOnly, false);
     }
   }, [activeTabId, editor, windows]);
 
@@ -274,7 +313,7 @@ export default function App() {
     setWindows(next);
     setActivePath(prev => prev.filter(id => id === 'root' || next[id]));
     if (activeTabId && idsToRemove.has(activeTabId)) setActiveTabId(null);
-    try { await fetch(`${API_URL}/tabs/${tabId}`, { method: 'DELETE' }); } catch (e) { console.error(e); }
+    try { await fetch(`${API_URL}/tabs/${tabId}`, { method: 'DELETE', credentials: 'include' }); } catch (e) { console.error(e); }
   };
 
   const handleTabClick = (windowId: string, tab: Tab, index: number) => {
@@ -291,25 +330,25 @@ export default function App() {
   const handleImport = (e: React.ChangeEvent<HTMLInputElement>) => {
     const file = e.target.files?.[0];
     if (!file) return;
-    const reader = new FileReader();
-    reader.onload = (event) => {
+    // The server stores the whole file in one go (a JSON export as a copy
+    // with fresh ids, a zip as a Markdown vault), then everything is reloaded.
+    const isVault = file.name.toLowerCase().endsWith('.zip');
+    const url = isVault ? `${API_URL}/import/vault` : `${API_URL}/import/json?regenerate_ids=true`;
+    const read: Promise<BodyInit> = isVault ? file.arrayBuffer() : file.text();
+    read.then(async body => {
       try {
-        const importedData: any[] = JSON.parse(event.target?.result as string);
-        const newWindows: Record<string, WindowData> = { 'root': { id: 'root', tabs: [] } };
-        const idMap: Record<string, string> = {}; 
-        importedData.forEach(item => { idMap[item.title] = `tab-${Math.random().toString(36).substring(2, 11)}`; });
-
-        [...importedData].sort((a, b) => (a.depth || 0) - (b.depth || 0)).forEach(item => {
-          const newId = idMap[item.title];
-          const targetWinId = (item.fromParent !== "Root" && idMap[item.fromParent]) ? idMap[item.fromParent] : 'root';
-          if (!newWindows[targetWinId]) newWindows[targetWinId] = { id: targetWinId, tabs: [], collapsed: false };
-          newWindows[targetWinId].tabs.push({ id: newId, title: item.title, content: item.content, createdAt: item.createdAt || Date.now() });
-          if (!newWindows[newId]) newWindows[newId] = { id: newId, tabs: [], collapsed: false };
+        const res = await fetch(url, {
+          method: 'POST',
+          credentials: 'include',
+          headers: { 'Content-Type': isVault ? 'application/zip' : 'application/json' },
+          body,
         });
-        setWindows(newWindows);
-      } catch (err) { alert("Import failed: Ensure you are using a valid JSON export file."); }
-    };
-    reader.readAsText(file);
+        if (res.status === 401) { setNeedsLogin(true); return; }
+        if (!res.ok) { alert(`Import failed: ${await res.text()}`); return; }
+        setSessionKey(k => k + 1);
+      } catch (err) { alert("Import failed: Ensure you are using a JSON export or a zip of Markdown notes."); }
+    });
+    e.target.value = '';
   };
 
   const getEditorStats = () => {
@@ -413,7 +452,7 @@ export default function App() {
                             {Object.values(windows).some(w => w.id !== 'root' && !w.collapsed) ? 'COLLAPSE ALL' : 'EXPAND ALL'}
                           </button>
                           <button className="theme-toggle-btn" onClick={() => setIsDarkMode(!isDarkMode)}>{isDarkMode ? '🌙 DARK' : '☀️ LIGHT'}</button>
-                          <input type="file" ref={fileInputRef} style={{ display: 'none' }} accept=".json" onChange={handleImport} />
+                          <input type="file" ref={fileInputRef} style={{ display: 'none' }} accept=".json,.zip" onChange={handleImport} />
                         </div>
                       </div>
                     </div>
@@ -452,6 +491,7 @@ export default function App() {
                               }} 
                             />
                           ) : ( <span className="tab-title">{tab.title}</span> )}
+                          {thumbnailFor(tab.content) && <img className="tab-thumb" src={thumbnailFor(tab.content)!} alt="" loading="lazy" />}
                           <div className="tab-actions">
                             <button className="edit-btn" onClick={(e) => { e.stopPropagation(); setEditingTabId(tab.id); }}>✎</button>
                             <button className="del-btn" onClick={(e) => { e.stopPropagation(); deleteTab(winId, tab.id); }}>✕</button>
@@ -470,7 +510,7 @@ export default function App() {
         <div className="writing-space">
           {activeTabId && editor ? (
             <div className="editor-wrapper">
-              <EditorToolbar editor={editor} windows={windows} saveStatus={saveStatus} lastSaved={lastSaved} handleManualRetry={() => setWindows(p => ({...p}))} />
+              <EditorToolbar editor={editor} apiUrl={API_URL} windows={windows} saveStatus={saveStatus} lastSaved={lastSaved} handleManualRetry={() => setWindows(p => ({...p}))} conflictTitles={Object.values(windows).flatMap(w => w.tabs).filter(t => t.id in conflicts).map(t => t.title)} handleReloadConflicts={reloadConflicts} handleOverwriteConflicts={overwriteConflicts} />
               <EditorContent editor={editor} className="rich-editor" />
               <div className="editor-footer">
                 <div className="stat">Length: <span>{getEditorStats().chars}</span></div>
@@ -483,6 +523,7 @@ export default function App() {
       </div>
 
       {isExportModalOpen && <ExportModal windows={windows} onClose={() => setIsExportModalOpen(false)} />}
+      {needsLogin && <LoginModal apiUrl={API_URL} onLoggedIn={() => { setNeedsLogin(false); setSessionKey(k => k + 1); }} />}
     </div>
   );
 }
\ No newline at end of file
@@ -10,24 +10,35 @@ import type { WindowData, SaveStatus } from '../types';
 
 interface EditorToolbarProps {
   editor: Editor;
+  apiUrl: string;
   windows: Record<string, WindowData>;
   saveStatus: SaveStatus;
   lastSaved: string | null;
   handleManualRetry: () => void;
+  // Titles of tabs someone else changed since they were loaded here.
+  conflictTitles: string[];
+  handleReloadConflicts: () => void;
+  handleOverwriteConflicts: () => void;
 }
 
-export default function EditorToolbar({ editor, windows, saveStatus, lastSaved, handleManualRetry }: EditorToolbarProps) {
+export default function EditorToolbar({ editor, apiUrl, windows, saveStatus, lastSaved, handleManualRetry, conflictTitles, handleReloadConflicts, handleOverwriteConflicts }: EditorToolbarProps) {
   const [linkSearch, setLinkSearch] = useState({ active: false, query: '' });
 
-  const handleImageUpload = (e: React.ChangeEvent<HTMLInputElement>) => {
+  // Images go to the attachment store; only their URL lands in the content.
+  const handleImageUpload = async (e: React.ChangeEvent<HTMLInputElement>) => {
     const file = e.target.files?.[0];
-    if (file) {
-      const reader = new FileReader();
-      reader.onload = (event) => {
-        const base64 = event.target?.result as string;
-        editor.chain().focus().setImage({ src: base64 }).run();
-      };
-      reader.readAsDataURL(file);
+    if (!file) return;
+    const form = new FormData();
+    form.append('file', file);
+    try {
+      const res = await fetch(`${apiUrl}/attachments`, { method: 'POST', credentials: 'include', body: form });
+      if (!res.ok) throw new Error(await res.text());
+      const { url } = await res.json();
+      // The server scales on request; no need to ship full-size photos into the editor.
+      editor.chain().focus().setImage({ src: `${url}?w=1024` }).run();
+    } catch (err) {
+      console.error("❌ Image upload failed", err);
+      alert("Image upload failed");
     }
   };
 
@@ -109,6 +120,13 @@ export default function EditorToolbar({ editor, windows, saveStatus, lastSaved,
           {saveStatus === 'saving' && "● Syncing..."}
           {saveStatus === 'saved' && <div className="saved-group"><span>✓ Saved</span>{lastSaved && <span className="save-time">at {lastSaved}</span>}</div>}
           {saveStatus === 'error' && <div className="error-group"><span>⚠ Sync Error</span><button className="retry-sync-btn" onClick={handleManualRetry}>Retry</button></div>}
+          {saveStatus === 'conflict' && (
+            <div className="error-group" title={conflictTitles.join(', ')}>
+              <span>⚠ Changed elsewhere: {conflictTitles.join(', ')}</span>
+              <button className="retry-sync-btn" onClick={handleReloadConflicts}>Reload</button>
+              <button className="retry-sync-btn" onClick={handleOverwriteConflicts}>Keep mine</button>
+            </div>
+          )}
         </div>
       </div>
     </div>
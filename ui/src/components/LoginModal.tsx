@@ -0,0 +1,62 @@
+// src/components/LoginModal.tsx
+import { useState } from 'react';
+
+interface LoginModalProps {
+  apiUrl: string;
+  onLoggedIn: () => void;
+}
+
+export default function LoginModal({ apiUrl, onLoggedIn }: LoginModalProps) {
+  const [username, setUsername] = useState('');
+  const [password, setPassword] = useState('');
+  const [error, setError] = useState<string | null>(null);
+  const [busy, setBusy] = useState(false);
+
+  const post = (path: string) => fetch(`${apiUrl}${path}`, {
+    method: 'POST',
+    credentials: 'include',
+    headers: { 'Content-Type': 'application/json' },
+    body: JSON.stringify({ username, password }),
+  });
+
+  // The server only accepts anonymous sign-up for the very first account
+  // (unless ALLOW_SIGNUP is set), so "Create Account" is mostly for setup.
+  const submit = async (register: boolean) => {
+    setBusy(true);
+    setError(null);
+    try {
+      if (register) {
+        const res = await post('/auth/register');
+        if (!res.ok) { setError(await res.text()); return; }
+      }
+      const res = await post('/auth/login');
+      if (!res.ok) { setError(await res.text()); return; }
+      onLoggedIn();
+    } catch (e) {
+      setError('Could not reach the server');
+    } finally {
+      setBusy(false);
+    }
+  };
+
+  return (
+    <div className="modal-overlay">
+      <form className="export-modal" onSubmit={e => { e.preventDefault(); submit(false); }}>
+        <h3 style={{color: '#007acc', margin: '0 0 15px 0'}}>Log In</h3>
+        <div className="modal-field">
+          <label>Username</label>
+          <input autoFocus value={username} onChange={e => setUsername(e.target.value)} />
+        </div>
+        <div className="modal-field">
+          <label>Password</label>
+          <input type="password" value={password} onChange={e => setPassword(e.target.value)} />
+        </div>
+        {error && <div className="login-error">{error}</div>}
+        <div className="modal-actions">
+          <button type="button" className="cancel-btn" disabled={busy} onClick={() => submit(true)}>Create Account</button>
+          <button type="submit" className="confirm-btn" disabled={busy}>Log In</button>
+        </div>
+      </form>
+    </div>
+  );
+}
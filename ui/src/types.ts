@@ -5,6 +5,8 @@ export interface Tab {
   content: string;
   createdAt: number;
   parentId?: string;
+  // Set when the server only grants view access to this branch.
+  readOnly?: boolean;
 }
 
 export interface WindowData {
@@ -16,4 +18,4 @@ export interface WindowData {
 
 export type SortMode = 'oldest' | 'alpha' | 'alpha-desc' | 'newest';
 
-export type SaveStatus = 'saved' | 'saving' | 'error';
\ No newline at end of file
+export type SaveStatus = 'saved' | 'saving' | 'error' | 'conflict';
\ No newline at end of file